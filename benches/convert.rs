@@ -0,0 +1,76 @@
+//! Benchmarks of the conversion of a large generated map
+//!
+//! Run with `cargo bench`, the map is a row of rooms where every room has
+//! different heights so each portal gets upper and lower walls
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use wad_reader::convert::{self, GltfOptions};
+use wad_reader::gen;
+use wad_reader::texture::{self, TextureLoader};
+use wad_reader::wad::{self, Wad};
+
+#[path = "../src/testing.rs"]
+#[allow(dead_code)]
+mod testing;
+
+/// The number of rooms, the vertices are 16 bit so the row of rooms can't
+/// be wider than 32767 units
+const ROOMS: usize = 250;
+
+fn large_wad() -> Vec<u8> {
+    let heights = (0..ROOMS)
+        .map(|i| ((i % 4) as i16 * 8, 128 + (i % 3) as i16 * 16))
+        .collect::<Vec<_>>();
+    let map = testing::MapBuilder::rooms("MAP01", &heights);
+
+    testing::WadBuilder::new()
+        .lumps(testing::game_lumps())
+        .lumps(map.lumps())
+        .build(b"IWAD")
+}
+
+fn context(wad: &Wad) -> gen::Context {
+    let palettes = texture::read_all_palettes(wad).unwrap();
+    let color_maps = texture::read_all_color_maps(wad).unwrap();
+    let texture_loader =
+        TextureLoader::new(wad, color_maps[0].clone(), palettes[0].clone())
+            .unwrap();
+
+    gen::Context::new(texture_loader, gen::GenOptions::default())
+}
+
+fn convert_large_map(c: &mut Criterion) {
+    let data = large_wad();
+    let wad = Wad::parse(&data).unwrap();
+    let wad_map = wad::Map::parse_from_wad(&wad, "MAP01").unwrap();
+
+    c.bench_function("gen_map", |b| {
+        b.iter_batched(
+            || context(&wad),
+            |mut context| convert::Map::gen_map(&mut context, &wad_map, None),
+            BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("gen_map_and_build_gltf", |b| {
+        b.iter_batched(
+            || context(&wad),
+            |mut context| {
+                let map = convert::Map::gen_map(&mut context, &wad_map, None);
+                convert::build_map_gltf(
+                    &context,
+                    &wad_map,
+                    map,
+                    None,
+                    &GltfOptions::default(),
+                )
+                .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, convert_large_map);
+criterion_main!(benches);
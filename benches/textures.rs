@@ -0,0 +1,131 @@
+//! Benchmarks of the texture loading with a WAD that has as many textures
+//! as an IWAD
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use wad_reader::texture::{self, TextureLoader};
+use wad_reader::wad::Wad;
+
+#[path = "../src/testing.rs"]
+#[allow(dead_code)]
+mod testing;
+
+/// The number of patches, textures and flats, DOOM2.WAD has around 400
+/// textures and 150 flats
+const COUNT: usize = 1000;
+
+fn large_wad() -> Vec<u8> {
+    let patch_names =
+        (0..COUNT).map(|i| format!("P{:05}", i)).collect::<Vec<_>>();
+    let texture_names =
+        (0..COUNT).map(|i| format!("T{:05}", i)).collect::<Vec<_>>();
+    let patches = (0..COUNT as i16)
+        .map(|i| [(0, 0, i), (32, 0, (i + 1) % COUNT as i16)])
+        .collect::<Vec<_>>();
+
+    let defs = (0..COUNT)
+        .map(|i| testing::TextureDef {
+            name: &texture_names[i],
+            masked: false,
+            width: 64,
+            height: 64,
+            patches: &patches[i],
+        })
+        .collect::<Vec<_>>();
+
+    let mut lumps = vec![
+        ("PLAYPAL".to_string(), testing::playpal(1)),
+        ("COLORMAP".to_string(), testing::colormap(1)),
+        (
+            "PNAMES".to_string(),
+            testing::pnames(
+                &patch_names.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            ),
+        ),
+        ("TEXTURE1".to_string(), testing::texture_lump(&defs)),
+        ("P_START".to_string(), Vec::new()),
+    ];
+    for (i, name) in patch_names.iter().enumerate() {
+        let patch = testing::solid_patch(64, 64, |x, y| (x + y + i) as u8);
+        lumps.push((name.clone(), patch));
+    }
+    lumps.push(("P_END".to_string(), Vec::new()));
+
+    lumps.push(("F_START".to_string(), Vec::new()));
+    for i in 0..COUNT {
+        let flat = testing::flat(|x, y| (x * y + i) as u8);
+        lumps.push((format!("F{:05}", i), flat));
+    }
+    lumps.push(("F_END".to_string(), Vec::new()));
+
+    testing::WadBuilder::new().lumps(lumps).build(b"IWAD")
+}
+
+fn texture_loader(wad: &Wad) -> TextureLoader {
+    let palettes = texture::read_all_palettes(wad).unwrap();
+    let color_maps = texture::read_all_color_maps(wad).unwrap();
+    TextureLoader::new(wad, color_maps[0].clone(), palettes[0].clone())
+        .unwrap()
+}
+
+fn lookup_by_name(c: &mut Criterion) {
+    let data = large_wad();
+    let wad = Wad::parse(&data).unwrap();
+    let texture_loader = texture_loader(&wad);
+
+    let names = (0..COUNT)
+        .flat_map(|i| [format!("T{:05}", i), format!("f{:05}", i)])
+        .collect::<Vec<_>>();
+
+    c.bench_function("load_from_name", |b| {
+        b.iter(|| {
+            for name in &names {
+                criterion::black_box(texture_loader.load_from_name(name));
+            }
+        })
+    });
+}
+
+/// Loads all the textures with one thread and with one thread for each
+/// core, needs the parallel feature (cargo bench --features parallel)
+#[cfg(feature = "parallel")]
+fn load_threads(c: &mut Criterion) {
+    let data = large_wad();
+    let wad = Wad::parse(&data).unwrap();
+
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut group = c.benchmark_group("texture_loader");
+    group.sample_size(10);
+
+    let mut thread_counts = vec![1];
+    if cores > 1 {
+        thread_counts.push(cores);
+    }
+
+    for threads in thread_counts {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+
+        group.bench_function(format!("{}_threads", threads), |b| {
+            b.iter(|| pool.install(|| texture_loader(&wad)))
+        });
+    }
+
+    group.finish();
+}
+
+#[cfg(not(feature = "parallel"))]
+fn load_threads(c: &mut Criterion) {
+    let data = large_wad();
+    let wad = Wad::parse(&data).unwrap();
+
+    let mut group = c.benchmark_group("texture_loader");
+    group.sample_size(10);
+    group.bench_function("1_threads", |b| b.iter(|| texture_loader(&wad)));
+    group.finish();
+}
+
+criterion_group!(benches, lookup_by_name, load_threads);
+criterion_main!(benches);
@@ -0,0 +1,428 @@
+//! Module for the animated flats and textures
+//!
+//! An animation is all the flats (or textures) between the first and the
+//! last frame in the order they are defined in the WAD, this is the same
+//! way DOOM finds the frames so PWADs can add frames in the middle
+//!
+//! Boom WADs can replace the hardcoded animations with an ANIMATED lump and
+//! list the switch textures in a SWITCHES lump
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use crate::texture::{TextureLoader, TextureTyp};
+use crate::util;
+use crate::wad::{self, Wad};
+
+/// DOOM runs the game logic at 35 tics per second
+pub const TICS_PER_SECOND: u32 = 35;
+
+/// The size of a record in the ANIMATED lump
+const ANIMATED_RECORD_SIZE: usize = 23;
+/// The size of a record in the SWITCHES lump
+const SWITCHES_RECORD_SIZE: usize = 20;
+
+#[derive(Clone, Debug)]
+pub struct AnimationDef {
+    typ: TextureTyp,
+    first: Cow<'static, str>,
+    last: Cow<'static, str>,
+    tics: u32,
+}
+
+impl AnimationDef {
+    const fn flat(first: &'static str, last: &'static str) -> Self {
+        Self {
+            typ: TextureTyp::Flat,
+            first: Cow::Borrowed(first),
+            last: Cow::Borrowed(last),
+            tics: 8,
+        }
+    }
+
+    const fn texture(first: &'static str, last: &'static str) -> Self {
+        Self {
+            typ: TextureTyp::Texture,
+            first: Cow::Borrowed(first),
+            last: Cow::Borrowed(last),
+            tics: 8,
+        }
+    }
+}
+
+/// A switch texture and the texture it changes to when it's used
+#[derive(Clone, Debug)]
+pub struct Switch {
+    pub off: String,
+    pub on: String,
+    /// 1 for the shareware, 2 for the registered and 3 for the commercial
+    /// (DOOM II) version of the game
+    pub episode: u16,
+}
+
+/// The animations hardcoded in DOOM and DOOM II (animdefs in p_spec.c)
+const VANILLA_ANIMATIONS: &[AnimationDef] = &[
+    AnimationDef::flat("NUKAGE1", "NUKAGE3"),
+    AnimationDef::flat("FWATER1", "FWATER4"),
+    AnimationDef::flat("SWATER1", "SWATER4"),
+    AnimationDef::flat("LAVA1", "LAVA4"),
+    AnimationDef::flat("BLOOD1", "BLOOD3"),
+    AnimationDef::flat("RROCK05", "RROCK08"),
+    AnimationDef::flat("SLIME01", "SLIME04"),
+    AnimationDef::flat("SLIME05", "SLIME08"),
+    AnimationDef::flat("SLIME09", "SLIME12"),
+    AnimationDef::texture("BLODGR1", "BLODGR4"),
+    AnimationDef::texture("SLADRIP1", "SLADRIP3"),
+    AnimationDef::texture("BLODRIP1", "BLODRIP4"),
+    AnimationDef::texture("FIREWALA", "FIREWALL"),
+    AnimationDef::texture("GSTFONT1", "GSTFONT3"),
+    AnimationDef::texture("FIRELAV3", "FIRELAVA"),
+    AnimationDef::texture("FIREMAG1", "FIREMAG3"),
+    AnimationDef::texture("FIREBLU1", "FIREBLU2"),
+    AnimationDef::texture("ROCKRED1", "ROCKRED3"),
+    AnimationDef::texture("BFALL1", "BFALL4"),
+    AnimationDef::texture("SFALL1", "SFALL4"),
+    AnimationDef::texture("WFALL1", "WFALL4"),
+    AnimationDef::texture("DBRAIN1", "DBRAIN4"),
+];
+
+#[derive(Clone, Debug)]
+pub struct Animation {
+    /// The texture ids of the frames, the first frame is the one used by
+    /// the geometry
+    pub frames: Vec<usize>,
+    /// How many tics each frame is shown
+    pub tics: u32,
+}
+
+/// Parses the ANIMATED lump, the records are read until the record with
+/// the type 0xff
+///
+/// Record layout: type (u8, 0 = flat, 1 = texture), last frame (9 bytes),
+/// first frame (9 bytes), tics (u32)
+pub fn read_animated(wad: &Wad) -> wad::Result<Vec<AnimationDef>> {
+    let data = wad.read_dir_by_name("ANIMATED")?;
+
+    let mut result = Vec::new();
+    for record in data.chunks_exact(ANIMATED_RECORD_SIZE) {
+        let typ = match record[0] {
+            0xff => break,
+            // NOTE: Bit 1 is used by some ports for the decals on textures
+            t if t & 1 == 0 => TextureTyp::Flat,
+            _ => TextureTyp::Texture,
+        };
+
+        let last = util::array_to_string(&record[1..10]);
+        let first = util::array_to_string(&record[10..19]);
+        let tics = u32::from_le_bytes(
+            record[19..23]
+                .try_into()
+                .map_err(|_| wad::Error::ArrayConvertionFailed)?,
+        );
+
+        result.push(AnimationDef {
+            typ,
+            first: Cow::Owned(first),
+            last: Cow::Owned(last),
+            tics,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Parses the SWITCHES lump, the records are read until the record with
+/// the episode 0
+///
+/// Record layout: off texture (9 bytes), on texture (9 bytes), episode
+/// (u16)
+pub fn read_switches(wad: &Wad) -> wad::Result<Vec<Switch>> {
+    let data = wad.read_dir_by_name("SWITCHES")?;
+
+    let mut result = Vec::new();
+    for record in data.chunks_exact(SWITCHES_RECORD_SIZE) {
+        let episode = u16::from_le_bytes(
+            record[18..20]
+                .try_into()
+                .map_err(|_| wad::Error::ArrayConvertionFailed)?,
+        );
+        if episode == 0 {
+            break;
+        }
+
+        result.push(Switch {
+            off: util::array_to_string(&record[0..9]),
+            on: util::array_to_string(&record[9..18]),
+            episode,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Returns the animations of the WAD, the ANIMATED lump replaces the
+/// hardcoded animations when the WAD has one
+pub fn animation_defs(wad: &Wad) -> wad::Result<Vec<AnimationDef>> {
+    match read_animated(wad) {
+        Ok(defs) => Ok(defs),
+        Err(wad::Error::NoDirFound) => Ok(VANILLA_ANIMATIONS.to_vec()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the animation the texture is a frame of, None if the texture
+/// isn't animated
+pub fn find_animation(
+    texture_loader: &TextureLoader,
+    defs: &[AnimationDef],
+    texture_id: usize,
+) -> Option<Animation> {
+    let texture = texture_loader.load_from_id(texture_id)?;
+
+    for def in defs {
+        if def.typ != texture.typ() {
+            continue;
+        }
+
+        let first = texture_loader.find_id_of_type(&def.first, def.typ);
+        let last = texture_loader.find_id_of_type(&def.last, def.typ);
+
+        // NOTE: The animation is only used if both the first and the last
+        // frame exists (the shareware WAD is missing some of them)
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) if first < last => (first, last),
+            _ => continue,
+        };
+
+        if (first..=last).contains(&texture_id) {
+            return Some(Animation {
+                frames: (first..=last).collect(),
+                tics: def.tics,
+            });
+        }
+    }
+
+    None
+}
+
+/// Writes all the frames of the animations as images and a json file
+/// describing the animations, the switches (if any) are written the same
+/// way to a json file of their own
+pub fn dump<P>(
+    texture_loader: &TextureLoader,
+    animations: &[Animation],
+    switches: &[Switch],
+    output_dir: P,
+) -> std::io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    // NOTE: A missing output directory is reported by the first write
+    let output_dir = PathBuf::from(output_dir.as_ref());
+
+    use serde_json::json;
+
+    let unknown = |id: usize| {
+        std::io::Error::other(format!(
+            "there is no texture with the id {}",
+            id
+        ))
+    };
+    let name = |id: usize| {
+        texture_loader
+            .get_name_from_id(id)
+            .ok_or_else(|| unknown(id))
+    };
+
+    let mut result = Vec::new();
+    for animation in animations {
+        for &frame in &animation.frames {
+            let texture = texture_loader
+                .load_from_id(frame)
+                .ok_or_else(|| unknown(frame))?;
+
+            let mut path = output_dir.clone();
+            path.push(name(frame)?);
+            path.set_extension("png");
+
+            let data = util::write_texture_to_png(texture);
+            util::write_binary_file(path, &data)?;
+        }
+
+        let frames = animation
+            .frames
+            .iter()
+            .map(|&id| name(id))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        result.push(json!({
+            "name": frames[0],
+            "frames": frames,
+            "tics": animation.tics,
+            "frame_duration":
+                animation.tics as f64 / TICS_PER_SECOND as f64,
+        }));
+    }
+
+    let text = serde_json::to_string_pretty(&result).unwrap();
+    let mut path = output_dir.clone();
+    path.push("animations");
+    path.set_extension("json");
+    util::write_binary_file(path, text.as_bytes())?;
+
+    if switches.is_empty() {
+        return Ok(());
+    }
+
+    let mut result = Vec::new();
+    for switch in switches {
+        for name in [&switch.off, &switch.on] {
+            if let Some((_, texture)) = texture_loader.load_from_name(name) {
+                let mut path = output_dir.clone();
+                path.push(name);
+                path.set_extension("png");
+
+                let data = util::write_texture_to_png(texture);
+                util::write_binary_file(path, &data)?;
+            }
+        }
+
+        result.push(json!({
+            "off": switch.off,
+            "on": switch.on,
+            "episode": switch.episode,
+        }));
+    }
+
+    let text = serde_json::to_string_pretty(&result).unwrap();
+    let mut path = output_dir;
+    path.push("switches");
+    path.set_extension("json");
+    util::write_binary_file(path, text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, WadBuilder};
+    use crate::texture;
+
+    /// A 9 byte name, the names of ANIMATED and SWITCHES ends with a NUL
+    fn name9(name: &str) -> Vec<u8> {
+        let mut result = testing::name8(name).to_vec();
+        result.push(0);
+        result
+    }
+
+    fn animated_record(
+        typ: u8,
+        first: &str,
+        last: &str,
+        tics: u32,
+    ) -> Vec<u8> {
+        let mut record = vec![typ];
+        record.extend_from_slice(&name9(last));
+        record.extend_from_slice(&name9(first));
+        record.extend_from_slice(&tics.to_le_bytes());
+        record
+    }
+
+    fn switches_record(off: &str, on: &str, episode: u16) -> Vec<u8> {
+        let mut record = name9(off);
+        record.extend_from_slice(&name9(on));
+        record.extend_from_slice(&episode.to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn read_crafted_animated_and_switches() {
+        // NOTE: The records after the sentinels are never read
+        let animated = [
+            animated_record(0, "SLIME1", "SLIME4", 8),
+            animated_record(1, "BLINK1", "BLINK2", 16),
+            animated_record(0xff, "", "", 0),
+            animated_record(1, "UNUSED1", "UNUSED2", 8),
+        ]
+        .concat();
+        let switches = [
+            switches_record("SW1RED", "SW2RED", 1),
+            switches_record("SW1BIG", "SW2BIG", 3),
+            switches_record("", "", 0),
+            switches_record("SW1NOT", "SW2NOT", 2),
+        ]
+        .concat();
+        assert_eq!(animated.len(), 4 * ANIMATED_RECORD_SIZE);
+        assert_eq!(switches.len(), 4 * SWITCHES_RECORD_SIZE);
+
+        let data = WadBuilder::new()
+            .lumps(vec![
+                ("ANIMATED".to_string(), animated),
+                ("SWITCHES".to_string(), switches),
+            ])
+            .build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        let defs = read_animated(&wad).unwrap();
+        let defs = defs
+            .iter()
+            .map(|d| (d.typ, &d.first[..], &d.last[..], d.tics))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            defs,
+            [
+                (TextureTyp::Flat, "SLIME1", "SLIME4", 8),
+                (TextureTyp::Texture, "BLINK1", "BLINK2", 16),
+            ]
+        );
+
+        let switches = read_switches(&wad).unwrap();
+        let switches = switches
+            .iter()
+            .map(|s| (&s.off[..], &s.on[..], s.episode))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            switches,
+            [("SW1RED", "SW2RED", 1), ("SW1BIG", "SW2BIG", 3)]
+        );
+
+        assert_eq!(animation_defs(&wad).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn vanilla_animations_without_animated() {
+        let wad_data = testing::two_rooms_wad();
+        let wad = Wad::parse(&wad_data).unwrap();
+        let defs = animation_defs(&wad).unwrap();
+        assert_eq!(defs.len(), VANILLA_ANIMATIONS.len());
+    }
+
+    #[test]
+    fn dump_errors_are_returned() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let palettes = texture::read_all_palettes(&wad).unwrap();
+        let color_maps = texture::read_all_color_maps(&wad).unwrap();
+        let loader = TextureLoader::new(
+            &wad,
+            color_maps[0].clone(),
+            palettes[0].clone(),
+        )
+        .unwrap();
+
+        let (wall, _) = loader.load_from_name(testing::WALL).unwrap();
+        let animation = |frames| Animation { frames, tics: 8 };
+
+        let dir = testing::temp_dir("animation_dump");
+        dump(&loader, &[animation(vec![wall])], &[], &dir).unwrap();
+        assert!(dir.join("WALL.png").exists());
+        assert!(dir.join("animations.json").exists());
+
+        let missing = dir.join("missing");
+        let result = dump(&loader, &[animation(vec![wall])], &[], missing);
+        assert!(result.is_err());
+
+        // NOTE: The id is past the end of the textures
+        let unknown = animation(vec![loader.len()]);
+        assert!(dump(&loader, &[unknown], &[], &dir).is_err());
+    }
+}
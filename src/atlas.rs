@@ -0,0 +1,480 @@
+//! Module to pack textures into a single texture atlas
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::math::{Vec2, Vec4};
+use crate::polygon::{Mesh, Vertex};
+use crate::texture::{Texture, TextureLoader, TextureTyp};
+use crate::util;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    MissingTexture(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MissingTexture(id) => {
+                write!(f, "the texture #{} doesn't exist", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Linear interpolation between two vertices, t = 0 is a and t = 1 is b
+fn lerp_vertex(a: &Vertex, b: &Vertex, t: f32) -> Vertex {
+    let color = Vec4::new(
+        a.color.x + (b.color.x - a.color.x) * t,
+        a.color.y + (b.color.y - a.color.y) * t,
+        a.color.z + (b.color.z - a.color.z) * t,
+        a.color.w + (b.color.w - a.color.w) * t,
+    );
+
+    Vertex::new(
+        a.pos + (b.pos - a.pos) * t,
+        a.normal + (b.normal - a.normal) * t,
+        a.uv + (b.uv - a.uv) * t,
+        color,
+    )
+}
+
+/// Splits the convex polygon at the line where the axis of the vertices
+/// is equal to line, returns the part below and the part above the line
+fn split_polygon(
+    polygon: &[Vertex],
+    axis: fn(&Vertex) -> f32,
+    line: f32,
+) -> (Vec<Vertex>, Vec<Vertex>) {
+    let mut below = Vec::new();
+    let mut above = Vec::new();
+
+    for (index, a) in polygon.iter().enumerate() {
+        let b = &polygon[(index + 1) % polygon.len()];
+        let da = axis(a) - line;
+        let db = axis(b) - line;
+
+        if da <= 0.0 {
+            below.push(*a);
+        }
+        if da >= 0.0 {
+            above.push(*a);
+        }
+
+        if (da < 0.0 && db > 0.0) || (da > 0.0 && db < 0.0) {
+            let vertex = lerp_vertex(a, b, da / (da - db));
+            below.push(vertex);
+            above.push(vertex);
+        }
+    }
+
+    (below, above)
+}
+
+/// Splits the triangle where the uv crosses a whole number, every part
+/// covers at most one copy of the texture
+fn split_at_texture_edges(triangle: [Vertex; 3]) -> Vec<Vec<Vertex>> {
+    let axes: [fn(&Vertex) -> f32; 2] = [|v| v.uv.x, |v| v.uv.y];
+
+    let mut parts = vec![triangle.to_vec()];
+    for axis in axes {
+        let mut result = Vec::new();
+        for part in parts {
+            let min = part.iter().map(axis).fold(f32::INFINITY, f32::min);
+            let max = part.iter().map(axis).fold(f32::NEG_INFINITY, f32::max);
+
+            let mut rest = part;
+            for line in (min.floor() as i64 + 1)..(max.ceil() as i64) {
+                let (below, above) = split_polygon(&rest, axis, line as f32);
+                if below.len() >= 3 {
+                    result.push(below);
+                }
+                rest = above;
+            }
+
+            if rest.len() >= 3 {
+                result.push(rest);
+            }
+        }
+
+        parts = result;
+    }
+
+    parts
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct AtlasRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+pub struct Atlas {
+    texture: Texture,
+    rects: HashMap<usize, AtlasRect>,
+}
+
+impl Atlas {
+    /// Packs the textures into rows (shelves) sorted by height, the width of
+    /// the atlas is picked from the total area of all the textures
+    ///
+    /// Each texture gets a gutter of pad pixels that repeats the edge of
+    /// the texture so linear filtering doesn't sample the neighbours, the
+    /// rects (and the uvs) only covers the texture without the gutter
+    pub fn build(
+        texture_loader: &TextureLoader,
+        texture_ids: &HashSet<usize>,
+        pad: usize,
+    ) -> Result<Self> {
+        // NOTE: Sort the ids so the packing is the same between runs
+        let mut texture_ids = texture_ids.iter().copied().collect::<Vec<_>>();
+        texture_ids.sort();
+
+        let mut textures = texture_ids
+            .iter()
+            .map(|&id| {
+                let texture = texture_loader
+                    .load_from_id(id)
+                    .ok_or(Error::MissingTexture(id))?;
+                Ok((id, texture.extend_edges(pad)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        textures.sort_by_key(|(_, t)| std::cmp::Reverse(t.height()));
+
+        let total_area = textures
+            .iter()
+            .map(|(_, t)| t.width() * t.height())
+            .sum::<usize>();
+        let max_width =
+            textures.iter().map(|(_, t)| t.width()).max().unwrap_or(1);
+
+        let side = (total_area as f32).sqrt().ceil() as usize;
+        let width = side.max(max_width).next_power_of_two();
+
+        let mut rects = HashMap::new();
+
+        let mut x = 0;
+        let mut y = 0;
+        let mut shelf_height = 0;
+        for (id, texture) in &textures {
+            if x + texture.width() > width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+
+            rects.insert(
+                *id,
+                AtlasRect {
+                    x: x + pad,
+                    y: y + pad,
+                    width: texture.width() - pad * 2,
+                    height: texture.height() - pad * 2,
+                },
+            );
+
+            x += texture.width();
+            shelf_height = shelf_height.max(texture.height());
+        }
+
+        let height = (y + shelf_height).max(1).next_power_of_two();
+
+        let mut pixels = vec![0u8; width * height * 4];
+        for (id, texture) in &textures {
+            let rect = &rects[id];
+            let (x, y) = (rect.x - pad, rect.y - pad);
+            let row_size = texture.width() * 4;
+
+            for row in 0..texture.height() {
+                let src = row * row_size;
+                let dst = (x + (y + row) * width) * 4;
+                pixels[dst..dst + row_size]
+                    .copy_from_slice(&texture.pixels()[src..src + row_size]);
+            }
+        }
+
+        let texture = Texture::new(TextureTyp::Texture, width, height, pixels);
+
+        Ok(Self { texture, rects })
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Converts an uv inside the texture to an uv inside the atlas
+    ///
+    /// NOTE: Only uvs inside the range 0..1 maps to the right texture, use
+    /// remap_mesh for surfaces that tiles the texture
+    pub fn map_uv(&self, texture_id: usize, uv: Vec2) -> Vec2 {
+        let rect = self.rects[&texture_id];
+
+        let offset = Vec2::new(rect.x as f32, rect.y as f32);
+        let size = Vec2::new(rect.width as f32, rect.height as f32);
+        let atlas_size = Vec2::new(
+            self.texture.width() as f32,
+            self.texture.height() as f32,
+        );
+
+        (offset + uv * size) / atlas_size
+    }
+
+    /// Returns the mesh with the uvs inside the atlas, the triangles are
+    /// split at the edges of the texture so the surfaces that tiles the
+    /// texture repeats it instead of sampling the neighbours in the atlas
+    pub fn remap_mesh(&self, mesh: &Mesh) -> Mesh {
+        let mut result = Mesh::with_capacity(
+            mesh.vertex_buffer.len(),
+            mesh.index_buffer.len(),
+        );
+
+        let texture_id = match mesh.texture_id {
+            Some(texture_id) => texture_id,
+            None => {
+                result.merge(mesh);
+                return result;
+            }
+        };
+        result.texture_id = mesh.texture_id;
+
+        for triangle in mesh.index_buffer.chunks_exact(3) {
+            let triangle =
+                [0, 1, 2].map(|i| mesh.vertex_buffer[triangle[i] as usize]);
+
+            for mut part in split_at_texture_edges(triangle) {
+                // NOTE: Every part is inside one copy of the texture so the
+                // copy can be moved to 0..1, the clamp removes the rounding
+                // errors from the split
+                let count = part.len() as f32;
+                let center = part
+                    .iter()
+                    .fold(Vec2::new(0.0, 0.0), |a, v| a + v.uv / count);
+                let copy = Vec2::new(center.x.floor(), center.y.floor());
+
+                for vertex in &mut part {
+                    let uv = vertex.uv - copy;
+                    let uv =
+                        Vec2::new(uv.x.clamp(0.0, 1.0), uv.y.clamp(0.0, 1.0));
+                    vertex.uv = self.map_uv(texture_id, uv);
+                }
+
+                // NOTE: The parts are convex so a fan keeps the winding of
+                // the triangle
+                let indices = (1..part.len() as u32 - 1)
+                    .flat_map(|i| [0, i, i + 1])
+                    .collect::<Vec<_>>();
+                result.add_triangles(&part, &indices);
+            }
+        }
+
+        result
+    }
+
+    pub fn dump<P>(
+        &self,
+        texture_loader: &TextureLoader,
+        output_dir: P,
+    ) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        // NOTE: A missing output directory is reported by the first write
+        let output_dir = PathBuf::from(output_dir.as_ref());
+
+        use serde_json::json;
+
+        let mut ids = self.rects.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+
+        let rects = ids
+            .iter()
+            .map(|id| {
+                let rect = &self.rects[id];
+                let name =
+                    texture_loader.get_name_from_id(*id).ok_or_else(|| {
+                        std::io::Error::other(format!(
+                            "there is no texture with the id {}",
+                            id
+                        ))
+                    })?;
+
+                Ok(json!({
+                    "name": name,
+                    "x": rect.x,
+                    "y": rect.y,
+                    "width": rect.width,
+                    "height": rect.height,
+                }))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let result = json!({
+            "width": self.texture.width(),
+            "height": self.texture.height(),
+            "textures": rects,
+        });
+
+        let text = serde_json::to_string_pretty(&result).unwrap();
+        let mut path = output_dir.clone();
+        path.push("atlas");
+        path.set_extension("json");
+        util::write_binary_file(path, text.as_bytes())?;
+
+        let mut path = output_dir;
+        path.push("atlas");
+        path.set_extension("png");
+        let data = util::write_texture_to_png(&self.texture);
+        util::write_binary_file(path, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3;
+    use crate::testing;
+    use crate::texture;
+    use crate::wad::Wad;
+
+    fn texture_loader(wad: &Wad) -> TextureLoader {
+        let palettes = texture::read_all_palettes(wad).unwrap();
+        let color_maps = texture::read_all_color_maps(wad).unwrap();
+        TextureLoader::new(wad, color_maps[0].clone(), palettes[0].clone())
+            .unwrap()
+    }
+
+    #[test]
+    fn tiled_uvs_stay_inside_the_texture() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let texture_loader = texture_loader(&wad);
+
+        let wall = texture_loader.load_from_name(testing::WALL).unwrap().0;
+        let floor = texture_loader.load_from_name(testing::FLOOR).unwrap().0;
+        let atlas =
+            Atlas::build(&texture_loader, &HashSet::from([wall, floor]), 2)
+                .unwrap();
+
+        // NOTE: A square that repeats the texture 2 times in both directions
+        let vertex = |x: f32, y: f32| {
+            Vertex::new(
+                Vec3::new(x, y, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec2::new(x, y),
+                Vec4::new(1.0, 1.0, 1.0, 1.0),
+            )
+        };
+        let mut mesh = Mesh::new();
+        mesh.texture_id = Some(wall);
+        mesh.add_triangles(
+            &[
+                vertex(0.0, 0.0),
+                vertex(2.0, 0.0),
+                vertex(2.0, 2.0),
+                vertex(0.0, 2.0),
+            ],
+            &[0, 1, 2, 0, 2, 3],
+        );
+
+        let result = atlas.remap_mesh(&mesh);
+        assert!(result.index_buffer.len() / 3 >= 8);
+
+        let rect = atlas.rects[&wall];
+        let size = Vec2::new(
+            atlas.texture().width() as f32,
+            atlas.texture().height() as f32,
+        );
+        for vertex in &result.vertex_buffer {
+            let pixel = vertex.uv * size;
+            assert!(pixel.x >= rect.x as f32 - 0.001);
+            assert!(pixel.x <= (rect.x + rect.width) as f32 + 0.001);
+            assert!(pixel.y >= rect.y as f32 - 0.001);
+            assert!(pixel.y <= (rect.y + rect.height) as f32 + 0.001);
+        }
+
+        // NOTE: The split doesn't change the surface, the area is the same
+        let area = |mesh: &Mesh| {
+            mesh.index_buffer
+                .chunks_exact(3)
+                .map(|t| {
+                    let a = mesh.vertex_buffer[t[0] as usize].pos;
+                    let b = mesh.vertex_buffer[t[1] as usize].pos;
+                    let c = mesh.vertex_buffer[t[2] as usize].pos;
+                    (b - a).cross(c - a).length() / 2.0
+                })
+                .sum::<f32>()
+        };
+        assert!((area(&result) - area(&mesh)).abs() < 0.001);
+    }
+
+    #[test]
+    fn missing_texture_is_an_error() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let texture_loader = texture_loader(&wad);
+
+        assert!(matches!(
+            Atlas::build(&texture_loader, &HashSet::from([9999]), 0),
+            Err(Error::MissingTexture(9999))
+        ));
+    }
+
+    #[test]
+    fn gutter_repeats_the_nearest_pixel() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let texture_loader = texture_loader(&wad);
+
+        let pad = 3;
+        let (wall, texture) =
+            texture_loader.load_from_name(testing::WALL).unwrap();
+        let floor = texture_loader.load_from_name(testing::FLOOR).unwrap().0;
+        let atlas =
+            Atlas::build(&texture_loader, &HashSet::from([wall, floor]), pad)
+                .unwrap();
+
+        let rect = atlas.rects[&wall];
+        assert_eq!((rect.width, rect.height), (64, 64));
+        assert!(rect.x >= pad && rect.y >= pad);
+
+        for y in rect.y - pad..rect.y + rect.height + pad {
+            for x in rect.x - pad..rect.x + rect.width + pad {
+                // NOTE: Inside the texture this is the pixel itself
+                let nearest_x = x.clamp(rect.x, rect.x + rect.width - 1);
+                let nearest_y = y.clamp(rect.y, rect.y + rect.height - 1);
+                assert_eq!(
+                    atlas.texture().get_pixel(x, y),
+                    texture.get_pixel(nearest_x - rect.x, nearest_y - rect.y),
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dump_to_a_missing_directory_is_an_error() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let texture_loader = texture_loader(&wad);
+
+        let wall = texture_loader.load_from_name(testing::WALL).unwrap().0;
+        let atlas =
+            Atlas::build(&texture_loader, &HashSet::from([wall]), 2).unwrap();
+
+        let dir = testing::temp_dir("atlas_dump");
+        atlas.dump(&texture_loader, &dir).unwrap();
+        assert!(dir.join("atlas.json").exists());
+        assert!(dir.join("atlas.png").exists());
+
+        assert!(atlas.dump(&texture_loader, dir.join("missing")).is_err());
+    }
+}
@@ -0,0 +1,1497 @@
+//! Module to convert the generated geometry of a map to the output formats
+//!
+//! [`Map::gen_map`] generates the meshes of the sectors and the write
+//! functions writes them as glTF, OBJ or MIME
+
+use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::atlas::Atlas;
+use crate::gen;
+use crate::gltf::{
+    AlphaMode, Gltf, GltfTextureInfo, SamplerConfig, SamplerFilter,
+    SamplerWrap, UpAxis,
+};
+use crate::math::{Vec3, Vec4};
+use crate::mime;
+use crate::obj;
+use crate::polygon::{Mesh, Quad};
+use crate::texture;
+use crate::wad;
+
+/// How the glTF nodes are named
+///
+/// The suffixes are import hints for Godot, "-col" imports the mesh together
+/// with a static collision shape and "-colonly" only creates the collision
+/// shape (used for the invisible slope meshes)
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum NodeNaming {
+    /// Plain names without any suffixes
+    Plain,
+    /// Names with the "-col" and "-colonly" suffixes
+    Collision,
+}
+
+impl NodeNaming {
+    pub fn name(&self, name: String, suffix: &str) -> String {
+        match self {
+            NodeNaming::Plain => name,
+            NodeNaming::Collision => format!("{}{}", name, suffix),
+        }
+    }
+}
+
+/// How the glTF output is split into more then one file
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum SplitBy {
+    /// One file for each sector
+    Sector,
+    /// One file for each texture, the slope meshes are written to a file of
+    /// their own
+    Texture,
+}
+
+/// How the normals of the meshes are written
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum Normals {
+    /// Every face keeps its own normal (hard edges)
+    Flat,
+    /// The normals of the faces sharing a vertex are averaged and the
+    /// vertices are welded (soft edges)
+    Smooth,
+}
+
+/// What is done to the meshes before they are written
+#[derive(Copy, Clone, Debug)]
+pub struct MeshOptions {
+    pub weld: bool,
+    pub normals: Normals,
+}
+
+/// The options of the glTF output, the defaults are the same as the
+/// defaults of the command line
+pub struct GltfOptions {
+    pub node_naming: NodeNaming,
+    pub merge_sectors: bool,
+    pub filter: SamplerFilter,
+    pub mesh: MeshOptions,
+    pub max_texture_size: Option<usize>,
+    pub up_axis: UpAxis,
+    /// Tag the primitives with the surface, sector and texture
+    pub primitive_extras: bool,
+    /// Only write untextured collision meshes
+    pub collision_only: bool,
+    /// Replaces the generator of the glTF asset
+    pub generator: Option<String>,
+    pub copyright: Option<String>,
+    /// The file names of the WAD and the IWAD, written to the extras of the
+    /// glTF asset together with the map name
+    pub wad_file: String,
+    pub iwad_file: Option<String>,
+    /// Check the glTF for broken references before writing it
+    pub validate: bool,
+}
+
+impl Default for GltfOptions {
+    fn default() -> Self {
+        Self {
+            node_naming: NodeNaming::Collision,
+            merge_sectors: false,
+            filter: SamplerFilter::Nearest,
+            mesh: MeshOptions {
+                weld: false,
+                normals: Normals::Flat,
+            },
+            max_texture_size: None,
+            up_axis: UpAxis::Y,
+            primitive_extras: false,
+            collision_only: false,
+            generator: None,
+            copyright: None,
+            wad_file: String::new(),
+            iwad_file: None,
+            validate: false,
+        }
+    }
+}
+
+/// The number of vertices written before and after welding
+#[derive(Copy, Clone, Default, Debug)]
+pub struct VertexStats {
+    pub before_weld: usize,
+    pub after_weld: usize,
+}
+
+impl VertexStats {
+    fn prepare_mesh(&mut self, mesh: &mut Mesh, options: MeshOptions) {
+        self.before_weld += mesh.vertex_buffer.len();
+        if options.normals == Normals::Smooth {
+            mesh.smooth_normals();
+        }
+
+        // NOTE: The smooth normals needs the shared vertices to be welded
+        // or the faces still have their own vertices
+        if options.weld || options.normals == Normals::Smooth {
+            mesh.weld();
+        }
+        self.after_weld += mesh.vertex_buffer.len();
+    }
+}
+
+/// The generated geometry of a sector, the walls are kept as quads until
+/// they are written
+pub struct Sector {
+    /// Index of the sector inside the sectors of the WAD map
+    pub index: usize,
+    pub floor_mesh: Mesh,
+    pub ceiling_mesh: Mesh,
+    pub wall_quads: Vec<Quad>,
+    pub slope_quads: Vec<Quad>,
+}
+
+/// Merges the mesh into the mesh with the same texture, a new mesh is
+/// added for textures that doesn't have one yet
+fn merge_by_texture(meshes: &mut Vec<Mesh>, mesh: &Mesh) {
+    match meshes.iter_mut().find(|m| m.texture_id == mesh.texture_id) {
+        Some(existing) => existing.merge(mesh),
+        None => {
+            let mut new_mesh = Mesh::new();
+            new_mesh.merge(mesh);
+            meshes.push(new_mesh);
+        }
+    }
+}
+
+impl Sector {
+    /// The walls of the sector with one mesh for each texture, sorted by
+    /// the texture id so the output is the same between runs
+    pub fn wall_meshes(&self) -> Vec<Mesh> {
+        let mut meshes = Vec::new();
+        for quad in &self.wall_quads {
+            let mut mesh = Mesh::new();
+            mesh.texture_id = Some(quad.texture_id);
+            mesh.add_vertices(&quad.points, false);
+
+            merge_by_texture(&mut meshes, &mesh);
+        }
+
+        meshes.sort_by_key(|m| m.texture_id);
+        meshes
+    }
+
+    /// All the surfaces of the sector as one mesh without a texture
+    pub fn collision_mesh(&self) -> Mesh {
+        let mut result = Mesh::new();
+        for mut mesh in self.meshes_by_texture() {
+            mesh.texture_id = None;
+            result.merge(&mesh);
+        }
+
+        result
+    }
+
+    /// All the surfaces of the sector with one mesh for each texture, the
+    /// floor and the ceiling becomes one mesh when they use the same flat
+    pub fn meshes_by_texture(&self) -> Vec<Mesh> {
+        let mut meshes = Vec::new();
+        for mesh in [&self.floor_mesh, &self.ceiling_mesh] {
+            if mesh.texture_id.is_some() {
+                merge_by_texture(&mut meshes, mesh);
+            }
+        }
+
+        for mesh in self.wall_meshes() {
+            merge_by_texture(&mut meshes, &mesh);
+        }
+
+        meshes
+    }
+
+    fn new(
+        index: usize,
+        floor_mesh: Mesh,
+        ceiling_mesh: Mesh,
+        wall_quads: Vec<Quad>,
+        slope_quads: Vec<Quad>,
+    ) -> Self {
+        Self {
+            index,
+            floor_mesh,
+            ceiling_mesh,
+            wall_quads,
+            slope_quads,
+        }
+    }
+
+    pub fn gen_sector(
+        context: &mut gen::Context,
+        wad_map: &wad::Map,
+        index: usize,
+    ) -> Self {
+        let wad_sector = &wad_map.sectors[index];
+
+        let floor_mesh = gen::gen_floor(context, wad_map, wad_sector);
+
+        let ceiling_mesh = gen::gen_ceiling(context, wad_map, wad_sector);
+
+        let (wall_quads, slope_quads) =
+            gen::gen_walls(context, wad_map, wad_sector);
+
+        Sector::new(index, floor_mesh, ceiling_mesh, wall_quads, slope_quads)
+    }
+}
+
+/// The generated geometry of a map
+pub struct Map {
+    pub sectors: Vec<Sector>,
+}
+
+impl Map {
+    fn new(sectors: Vec<Sector>) -> Self {
+        Self { sectors }
+    }
+
+    /// Returns the ids of the textures used by the meshes of the map
+    pub fn texture_ids(&self) -> HashSet<usize> {
+        let mut result = HashSet::new();
+        for sector in &self.sectors {
+            result.extend(sector.floor_mesh.texture_id);
+            result.extend(sector.ceiling_mesh.texture_id);
+            result.extend(sector.wall_quads.iter().map(|q| q.texture_id));
+        }
+
+        result
+    }
+
+    /// Splits the map into smaller maps, the name of each part is used for
+    /// the file name
+    pub fn split(
+        self,
+        context: &gen::Context,
+        split_by: SplitBy,
+    ) -> std::io::Result<Vec<(String, Map)>> {
+        match split_by {
+            SplitBy::Sector => Ok(self
+                .sectors
+                .into_iter()
+                .map(|sector| {
+                    (
+                        format!("sector_{}", sector.index),
+                        Map::new(vec![sector]),
+                    )
+                })
+                .collect()),
+
+            SplitBy::Texture => {
+                let mut groups: BTreeMap<usize, Vec<Sector>> = BTreeMap::new();
+                let mut slopes = Vec::new();
+
+                // NOTE: The sectors are visited in order so the last sector
+                // of a group is the current sector if it already has been
+                // added to the group
+                fn part(
+                    groups: &mut BTreeMap<usize, Vec<Sector>>,
+                    texture_id: usize,
+                    index: usize,
+                ) -> &mut Sector {
+                    let sectors = groups.entry(texture_id).or_default();
+                    if sectors.last().is_none_or(|s| s.index != index) {
+                        sectors.push(Sector::new(
+                            index,
+                            Mesh::new(),
+                            Mesh::new(),
+                            Vec::new(),
+                            Vec::new(),
+                        ));
+                    }
+
+                    sectors.last_mut().unwrap()
+                }
+
+                for sector in self.sectors {
+                    let index = sector.index;
+
+                    if let Some(texture_id) = sector.floor_mesh.texture_id {
+                        part(&mut groups, texture_id, index).floor_mesh =
+                            sector.floor_mesh;
+                    }
+
+                    if let Some(texture_id) = sector.ceiling_mesh.texture_id {
+                        part(&mut groups, texture_id, index).ceiling_mesh =
+                            sector.ceiling_mesh;
+                    }
+
+                    for quad in sector.wall_quads {
+                        part(&mut groups, quad.texture_id, index)
+                            .wall_quads
+                            .push(quad);
+                    }
+
+                    if !sector.slope_quads.is_empty() {
+                        slopes.push(Sector::new(
+                            index,
+                            Mesh::new(),
+                            Mesh::new(),
+                            Vec::new(),
+                            sector.slope_quads,
+                        ));
+                    }
+                }
+
+                let mut result = groups
+                    .into_iter()
+                    .map(|(texture_id, sectors)| {
+                        let name = texture_name(context, texture_id)?;
+
+                        // NOTE: The id keeps the file names unique, a flat
+                        // and a texture can have the same name
+                        Ok((
+                            format!("texture_{}_{}", name, texture_id),
+                            Map::new(sectors),
+                        ))
+                    })
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                if !slopes.is_empty() {
+                    result.push(("slopes".to_string(), Map::new(slopes)));
+                }
+
+                Ok(result)
+            }
+        }
+    }
+
+    /// Generates the geometry of all the sectors or only the sector
+    /// only_sector (used to debug the geometry of a single sector)
+    pub fn gen_map(
+        context: &mut gen::Context,
+        wad_map: &wad::Map,
+        only_sector: Option<usize>,
+    ) -> Self {
+        let mut sectors = Vec::new();
+
+        for index in 0..wad_map.sectors.len() {
+            if only_sector.is_some_and(|only| only != index) {
+                continue;
+            }
+
+            let map_sector = Sector::gen_sector(context, wad_map, index);
+
+            sectors.push(map_sector);
+        }
+
+        Map::new(sectors)
+    }
+
+    pub fn translate(&mut self, offset: Vec3) {
+        for sector in &mut self.sectors {
+            for mesh in [&mut sector.floor_mesh, &mut sector.ceiling_mesh] {
+                for vertex in &mut mesh.vertex_buffer {
+                    vertex.pos += offset;
+                }
+            }
+
+            let quads = sector
+                .wall_quads
+                .iter_mut()
+                .chain(sector.slope_quads.iter_mut());
+            for quad in quads {
+                for point in &mut quad.points {
+                    point.pos += offset;
+                }
+            }
+        }
+    }
+}
+
+/// The gameplay data of the sector that is stored in the extras of the
+/// sector nodes
+fn sector_extras(wad_sector: &wad::Sector) -> serde_json::Value {
+    let line_specials = wad_sector
+        .lines
+        .iter()
+        .filter(|l| l.special != 0)
+        .map(|l| serde_json::json!({ "special": l.special, "tag": l.tag }))
+        .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "sector_special": wad_sector.special,
+        "tag": wad_sector.tag,
+        "light_level": wad_sector.light_level,
+        "floor_height": wad_sector.floor_height,
+        "ceiling_height": wad_sector.ceiling_height,
+        "line_specials": line_specials,
+    })
+}
+
+/// Encodes the texture as a PNG, textures larger then max_size are
+/// downscaled first (the uvs are normalized so they stay the same)
+fn texture_to_png(
+    texture: &texture::Texture,
+    max_size: Option<usize>,
+) -> Vec<u8> {
+    match max_size.and_then(|size| texture.fit_within(size)) {
+        Some(texture) => texture.to_png(),
+        None => texture.to_png(),
+    }
+}
+
+/// The name of the texture, the ids comes from the texture loader of the
+/// context so an unknown id is an error instead of a panic
+fn texture_name(
+    context: &gen::Context,
+    texture_id: usize,
+) -> std::io::Result<&str> {
+    context
+        .texture_loader
+        .get_name_from_id(texture_id)
+        .map(|name| name.as_str())
+        .ok_or_else(|| unknown_texture(texture_id))
+}
+
+fn load_texture(
+    context: &gen::Context,
+    texture_id: usize,
+) -> std::io::Result<&texture::Texture> {
+    context
+        .texture_loader
+        .load_from_id(texture_id)
+        .ok_or_else(|| unknown_texture(texture_id))
+}
+
+fn unknown_texture(texture_id: usize) -> std::io::Error {
+    std::io::Error::other(format!(
+        "there is no texture with the id {}",
+        texture_id
+    ))
+}
+
+pub fn write_map_gltf<P>(
+    context: &gen::Context,
+    wad_map: &wad::Map,
+    map: Map,
+    atlas: Option<&Atlas>,
+    options: &GltfOptions,
+    output_file: P,
+) -> std::io::Result<VertexStats>
+where
+    P: AsRef<Path>,
+{
+    let (gltf, stats) = build_map_gltf(context, wad_map, map, atlas, options)?;
+
+    let file = std::fs::File::create(output_file)?;
+    gltf.write_to(std::io::BufWriter::new(file))?;
+
+    Ok(stats)
+}
+
+/// Builds the glTF of the map in memory, the error is the problems found
+/// when options.validate is set
+pub fn build_map_gltf(
+    context: &gen::Context,
+    wad_map: &wad::Map,
+    mut map: Map,
+    atlas: Option<&Atlas>,
+    options: &GltfOptions,
+) -> std::io::Result<(Gltf, VertexStats)> {
+    let mut gltf = Gltf::new();
+    gltf.set_up_axis(options.up_axis);
+    let mut stats = VertexStats::default();
+
+    let map_name = wad_map.name.as_str();
+
+    if let Some(generator) = &options.generator {
+        gltf.set_generator(generator.clone());
+    }
+    if let Some(copyright) = &options.copyright {
+        gltf.set_copyright(copyright.clone());
+    }
+    gltf.set_asset_extras(serde_json::json!({
+        "wad": options.wad_file,
+        "iwad": options.iwad_file,
+        "map": map_name,
+    }));
+
+    let scene_id = gltf.create_scene(map_name.to_string());
+    // NOTE: Repeating the atlas would bleed the textures at the edges of
+    // the atlas into each other
+    let wrap = if atlas.is_some() {
+        SamplerWrap::ClampToEdge
+    } else {
+        SamplerWrap::Repeat
+    };
+
+    let sampler_config = SamplerConfig {
+        filter: options.filter,
+        wrap_s: wrap,
+        wrap_t: wrap,
+    };
+    let texture_sampler =
+        gltf.create_sampler("Default Sampler".to_string(), sampler_config);
+
+    // NOTE: DOOM textures are either fully transparent or fully opaque so
+    // masking is enough and avoids the sorting problems of blending
+    let alpha_mode = |has_alpha: bool| {
+        if has_alpha {
+            AlphaMode::Mask(0.5)
+        } else {
+            AlphaMode::Opaque
+        }
+    };
+
+    let mut textures = HashMap::new();
+    let mut texture_alpha = HashMap::new();
+    let mut shared_material = None;
+    if options.collision_only {
+        let material_id = gltf.create_material(
+            "Collision".to_string(),
+            Vec4::new(1.0, 1.0, 1.0, 1.0),
+            None,
+        );
+        shared_material = Some(material_id);
+    } else if context.options.debug_mode.is_some() {
+        let material_id = gltf.create_unlit_material(
+            "Debug".to_string(),
+            Vec4::new(1.0, 1.0, 1.0, 1.0),
+        );
+        shared_material = Some(material_id);
+    } else if let Some(atlas) = atlas {
+        let name = "Atlas".to_string();
+        let png = texture_to_png(atlas.texture(), options.max_texture_size);
+        let image_id = gltf.create_image(name.clone(), &png);
+        let gltf_texture_id =
+            gltf.create_texture(name.clone(), texture_sampler, image_id);
+
+        let material_id = gltf.create_material(
+            name,
+            Vec4::new(1.0, 1.0, 1.0, 1.0),
+            Some(GltfTextureInfo::new(gltf_texture_id)),
+        );
+
+        // NOTE: The atlas itself has empty space between the textures so
+        // check the textures packed into it instead
+        let has_alpha = context.texture_queue.iter().any(|&id| {
+            context
+                .texture_loader
+                .load_from_id(id)
+                .map(|t| t.is_transparent())
+                .unwrap_or(false)
+        });
+        gltf.set_material_alpha_mode(material_id, alpha_mode(has_alpha));
+
+        shared_material = Some(material_id);
+    } else {
+        // NOTE: Only the textures used by the map, a part of a split map
+        // doesn't use all the textures
+        let used_textures = map.texture_ids();
+        for &texture_id in context
+            .texture_queue
+            .iter()
+            .filter(|id| used_textures.contains(id))
+        {
+            let texture = load_texture(context, texture_id)?;
+            let name = texture_name(context, texture_id)?.to_string();
+            let png = texture_to_png(texture, options.max_texture_size);
+            let image_id = gltf.create_image(name.clone(), &png);
+            let gltf_texture_id =
+                gltf.create_texture(name, texture_sampler, image_id);
+
+            textures.insert(texture_id, gltf_texture_id);
+            texture_alpha.insert(texture_id, texture.is_transparent());
+        }
+    }
+
+    let material = |gltf: &mut Gltf, name: String, texture_id: usize| {
+        if let Some(material_id) = shared_material {
+            return material_id;
+        }
+
+        let material_id = gltf.create_material(
+            name,
+            Vec4::new(1.0, 1.0, 1.0, 1.0),
+            Some(GltfTextureInfo::new(textures[&texture_id])),
+        );
+        gltf.set_material_alpha_mode(
+            material_id,
+            alpha_mode(texture_alpha[&texture_id]),
+        );
+
+        // NOTE: Fullbright surfaces emit their own texture so they look the
+        // same no matter how the scene is lit
+        if context.is_fullbright(texture_id) {
+            gltf.set_material_emissive(
+                material_id,
+                Vec3::new(1.0, 1.0, 1.0),
+                Some(GltfTextureInfo::new(textures[&texture_id])),
+            );
+        }
+
+        material_id
+    };
+
+    let primitive_extras =
+        |surface: &str,
+         sector_index: Option<usize>,
+         texture_id: Option<usize>| {
+            if !options.primitive_extras {
+                return None;
+            }
+
+            let texture = texture_id
+                .and_then(|id| context.texture_loader.get_name_from_id(id));
+            Some(serde_json::json!({
+                "surface": surface,
+                "sector": sector_index,
+                "texture": texture,
+            }))
+        };
+
+    // NOTE: The collision only meshes isn't drawn so they only create the
+    // collision shape
+    let sector_suffix = if options.collision_only {
+        "-colonly"
+    } else {
+        "-col"
+    };
+
+    // NOTE: When merging the sectors all the primitives goes into one mesh
+    // and all the slopes into another one
+    let slopes_only =
+        context.options.debug_mode == Some(gen::DebugMode::SlopesOnly);
+    let merged_mesh_id = if options.merge_sectors && !slopes_only {
+        Some(gltf.create_mesh(map_name.to_string()))
+    } else {
+        None
+    };
+    let mut merged_slope_mesh = Mesh::new();
+
+    // NOTE: The uvs are moved inside the atlas when the surfaces are added,
+    // the walls are quads until then
+    let remap = |mesh: Mesh| match atlas {
+        Some(atlas) => atlas.remap_mesh(&mesh),
+        None => mesh,
+    };
+
+    for sector in &mut map.sectors {
+        let sector_index = sector.index;
+        sector.floor_mesh = remap(std::mem::take(&mut sector.floor_mesh));
+        sector.ceiling_mesh = remap(std::mem::take(&mut sector.ceiling_mesh));
+
+        // NOTE: Sectors where all the textures are missing doesn't have any
+        // primitives and a glTF mesh needs at least one
+        let has_primitives = sector.floor_mesh.texture_id.is_some()
+            || sector.ceiling_mesh.texture_id.is_some()
+            || !sector.wall_quads.is_empty();
+
+        // NOTE: Only the slopes are written in the SlopesOnly debug mode
+        if !slopes_only && (has_primitives || options.merge_sectors) {
+            let mesh_id = if let Some(mesh_id) = merged_mesh_id {
+                mesh_id
+            } else {
+                gltf.create_mesh(format!("Sector #{}", sector_index))
+            };
+
+            // NOTE: The collision only output has one untextured primitive
+            // for each sector
+            if options.collision_only {
+                let mut mesh = sector.collision_mesh();
+                stats.prepare_mesh(&mut mesh, options.mesh);
+
+                gltf.add_mesh_primitive(
+                    mesh_id,
+                    &mesh,
+                    shared_material.unwrap(),
+                    primitive_extras("collision", Some(sector_index), None),
+                );
+            } else {
+                if let Some(texture_id) = sector.floor_mesh.texture_id {
+                    stats.prepare_mesh(&mut sector.floor_mesh, options.mesh);
+
+                    let material_id = material(
+                        &mut gltf,
+                        format!("Sector #{} Floor", sector_index),
+                        texture_id,
+                    );
+
+                    gltf.add_mesh_primitive(
+                        mesh_id,
+                        &sector.floor_mesh,
+                        material_id,
+                        primitive_extras(
+                            "floor",
+                            Some(sector_index),
+                            Some(texture_id),
+                        ),
+                    );
+                }
+
+                if let Some(texture_id) = sector.ceiling_mesh.texture_id {
+                    stats.prepare_mesh(&mut sector.ceiling_mesh, options.mesh);
+
+                    let material_id = material(
+                        &mut gltf,
+                        format!("Sector #{} Ceiling", sector_index),
+                        texture_id,
+                    );
+
+                    gltf.add_mesh_primitive(
+                        mesh_id,
+                        &sector.ceiling_mesh,
+                        material_id,
+                        primitive_extras(
+                            "ceiling",
+                            Some(sector_index),
+                            Some(texture_id),
+                        ),
+                    );
+                }
+
+                for mesh in sector.wall_meshes() {
+                    let mut mesh = remap(mesh);
+                    let texture_id = mesh.texture_id.unwrap();
+                    stats.prepare_mesh(&mut mesh, options.mesh);
+
+                    let material_id = material(
+                        &mut gltf,
+                        format!(
+                            "Sector #{} Walls Tex #{}",
+                            sector_index, texture_id
+                        ),
+                        texture_id,
+                    );
+
+                    gltf.add_mesh_primitive(
+                        mesh_id,
+                        &mesh,
+                        material_id,
+                        primitive_extras(
+                            "wall",
+                            Some(sector_index),
+                            Some(texture_id),
+                        ),
+                    );
+                }
+            }
+
+            if !options.merge_sectors {
+                let name = options
+                    .node_naming
+                    .name(format!("Sector #{}", sector_index), sector_suffix);
+                let extras = sector_extras(&wad_map.sectors[sector_index]);
+                let node_id =
+                    gltf.create_node(name, mesh_id, None, Some(extras));
+
+                gltf.add_node_to_scene(scene_id, node_id);
+            }
+        }
+
+        if options.merge_sectors {
+            for quad in &sector.slope_quads {
+                merged_slope_mesh.add_vertices(&quad.points, false);
+            }
+
+            continue;
+        }
+
+        // NOTE: A glTF accessor can't be empty
+        if sector.slope_quads.is_empty() {
+            continue;
+        }
+
+        let slope_mesh_id =
+            gltf.create_mesh(format!("Sector #{}: Slope Mesh", sector_index));
+
+        let mut slope_mesh = Mesh::new();
+        for quad in &sector.slope_quads {
+            slope_mesh.add_vertices(&quad.points, false);
+        }
+        stats.prepare_mesh(&mut slope_mesh, options.mesh);
+
+        let material_id = if let Some(material_id) = shared_material {
+            material_id
+        } else {
+            gltf.create_material(
+                format!("Sector #{}: Slope Mesh", sector_index),
+                Vec4::new(1.0, 1.0, 1.0, 1.0),
+                None,
+            )
+        };
+
+        gltf.add_mesh_primitive(
+            slope_mesh_id,
+            &slope_mesh,
+            material_id,
+            primitive_extras("slope", Some(sector_index), None),
+        );
+
+        let name = options
+            .node_naming
+            .name(format!("Sector #{}: Slope Mesh", sector_index), "-colonly");
+        let extra_node_id = gltf.create_node(name, slope_mesh_id, None, None);
+        gltf.add_node_to_scene(scene_id, extra_node_id);
+    }
+
+    if let Some(mesh_id) = merged_mesh_id {
+        let name = options
+            .node_naming
+            .name(map_name.to_string(), sector_suffix);
+        let node_id = gltf.create_node(name, mesh_id, None, None);
+        gltf.add_node_to_scene(scene_id, node_id);
+    }
+
+    // NOTE: A glTF accessor can't be empty and most maps has no slopes
+    if options.merge_sectors && !merged_slope_mesh.vertex_buffer.is_empty() {
+        stats.prepare_mesh(&mut merged_slope_mesh, options.mesh);
+
+        let slope_mesh_id =
+            gltf.create_mesh(format!("{}: Slope Mesh", map_name));
+
+        let material_id = if let Some(material_id) = shared_material {
+            material_id
+        } else {
+            gltf.create_material(
+                format!("{}: Slope Mesh", map_name),
+                Vec4::new(1.0, 1.0, 1.0, 1.0),
+                None,
+            )
+        };
+
+        gltf.add_mesh_primitive(
+            slope_mesh_id,
+            &merged_slope_mesh,
+            material_id,
+            primitive_extras("slope", None, None),
+        );
+
+        let name = options
+            .node_naming
+            .name(format!("{}: Slope Mesh", map_name), "-colonly");
+        let node_id = gltf.create_node(name, slope_mesh_id, None, None);
+        gltf.add_node_to_scene(scene_id, node_id);
+    }
+
+    if options.validate {
+        if let Err(problems) = gltf.validate() {
+            for problem in &problems {
+                eprintln!("  {}", problem);
+            }
+
+            return Err(std::io::Error::other(format!(
+                "the glTF has {} problem(s)",
+                problems.len()
+            )));
+        }
+    }
+
+    Ok((gltf, stats))
+}
+
+/// Writes one glTF file for each part of the split map and a json file
+/// listing the files, every file is a complete glTF with the textures it
+/// uses
+pub fn write_map_gltf_split<P>(
+    context: &gen::Context,
+    wad_map: &wad::Map,
+    map: Map,
+    atlas: Option<&Atlas>,
+    options: &GltfOptions,
+    split_by: SplitBy,
+    output_file: P,
+) -> std::io::Result<VertexStats>
+where
+    P: AsRef<Path>,
+{
+    let output_file = output_file.as_ref();
+    let stem = output_file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("map");
+
+    let mut stats = VertexStats::default();
+    let mut files = Vec::new();
+    for (name, part) in map.split(context, split_by)? {
+        let sectors = part.sectors.iter().map(|s| s.index).collect::<Vec<_>>();
+
+        let file_name = format!("{}_{}.glb", stem, name);
+        let path = output_file.with_file_name(&file_name);
+        let part_stats =
+            write_map_gltf(context, wad_map, part, atlas, options, &path)?;
+        stats.before_weld += part_stats.before_weld;
+        stats.after_weld += part_stats.after_weld;
+
+        files.push(serde_json::json!({
+            "name": name,
+            "file": file_name,
+            "sectors": sectors,
+        }));
+    }
+
+    let split_by = match split_by {
+        SplitBy::Sector => "sector",
+        SplitBy::Texture => "texture",
+    };
+    let result = serde_json::json!({
+        "split_by": split_by,
+        "files": files,
+    });
+
+    let text = serde_json::to_string_pretty(&result).unwrap();
+    std::fs::write(output_file.with_extension("json"), text)?;
+
+    Ok(stats)
+}
+
+pub fn write_map_mime<P>(
+    context: &gen::Context,
+    map: Map,
+    mesh_options: MeshOptions,
+    output_file: P,
+) -> std::io::Result<VertexStats>
+where
+    P: AsRef<Path>,
+{
+    let (mime_map, stats) = build_map_mime(context, map, mesh_options)?;
+    mime_map.save_to_file(output_file)?;
+
+    Ok(stats)
+}
+
+/// Builds the MIME map in memory
+pub fn build_map_mime(
+    context: &gen::Context,
+    map: Map,
+    mesh_options: MeshOptions,
+) -> std::io::Result<(mime::Map, VertexStats)> {
+    let mut mime_map = mime::Map::new();
+    let mut stats = VertexStats::default();
+
+    // NOTE: Each mesh becomes a primitive so the surfaces with the same
+    // texture are merged into one mesh
+    for sector in &map.sectors {
+        for mut mesh in sector.meshes_by_texture() {
+            let texture_id = mesh.texture_id.unwrap();
+            stats.prepare_mesh(&mut mesh, mesh_options);
+            mime_map.add_mesh(&mesh, texture_name(context, texture_id)?);
+        }
+    }
+
+    Ok((mime_map, stats))
+}
+
+/// The OBJ of a map in memory, the textures are the PNGs that goes in the
+/// textures directory next to the OBJ file
+pub struct ObjModel {
+    pub obj: obj::Obj,
+    pub materials: Vec<obj::Material>,
+    pub textures: Vec<(String, Vec<u8>)>,
+}
+
+/// Writes the map as an OBJ file, the textures are written as PNGs to the
+/// textures directory next to the OBJ file
+pub fn write_map_obj<P>(
+    context: &gen::Context,
+    map: Map,
+    atlas: Option<&Atlas>,
+    mesh_options: MeshOptions,
+    max_texture_size: Option<usize>,
+    output_file: P,
+) -> std::io::Result<VertexStats>
+where
+    P: AsRef<Path>,
+{
+    let (model, stats) =
+        build_map_obj(context, map, atlas, mesh_options, max_texture_size)?;
+
+    let output_file = output_file.as_ref();
+    let mut texture_dir = output_file.to_path_buf();
+    texture_dir.set_file_name("textures");
+
+    if !model.textures.is_empty() {
+        std::fs::create_dir_all(&texture_dir)?;
+    }
+
+    for (name, data) in &model.textures {
+        let mut path = texture_dir.clone();
+        path.push(name);
+        path.set_extension("png");
+        std::fs::write(path, data)?;
+    }
+
+    model.obj.save_to_file(output_file, &model.materials)?;
+
+    Ok(stats)
+}
+
+/// Builds the OBJ of the map in memory
+pub fn build_map_obj(
+    context: &gen::Context,
+    map: Map,
+    atlas: Option<&Atlas>,
+    mesh_options: MeshOptions,
+    max_texture_size: Option<usize>,
+) -> std::io::Result<(ObjModel, VertexStats)> {
+    let mut obj = obj::Obj::new();
+    let mut stats = VertexStats::default();
+    let mut textures = Vec::new();
+
+    let mut add_png = |name: &str, texture: &texture::Texture| {
+        textures.push((
+            name.to_string(),
+            texture_to_png(texture, max_texture_size),
+        ));
+
+        obj::Material {
+            name: name.to_string(),
+            texture_path: Some(format!("textures/{}.png", name)),
+        }
+    };
+
+    // NOTE: A flat and a texture can have the same name so the texture id is
+    // added to the material and PNG names
+    let material_name = |texture_id: usize| {
+        let name = texture_name(context, texture_id)?;
+        Ok::<_, std::io::Error>(format!("{}_{}", name, texture_id))
+    };
+
+    // NOTE: Same as the glTF output, the debug modes and the atlas uses one
+    // material for everything
+    let mut materials = Vec::new();
+    let shared_material = if context.options.debug_mode.is_some() {
+        materials.push(obj::Material {
+            name: "Debug".to_string(),
+            texture_path: None,
+        });
+        Some("Debug")
+    } else if let Some(atlas) = atlas {
+        materials.push(add_png("Atlas", atlas.texture()));
+        Some("Atlas")
+    } else {
+        let mut texture_ids =
+            context.texture_queue.iter().copied().collect::<Vec<_>>();
+        texture_ids.sort();
+
+        for texture_id in texture_ids {
+            let texture = load_texture(context, texture_id)?;
+            materials.push(add_png(&material_name(texture_id)?, texture));
+        }
+
+        None
+    };
+
+    let material = |texture_id: usize| match shared_material {
+        Some(name) => Ok(name.to_string()),
+        None => material_name(texture_id),
+    };
+
+    for sector in &map.sectors {
+        for mesh in sector.meshes_by_texture() {
+            let mut mesh = match atlas {
+                Some(atlas) => atlas.remap_mesh(&mesh),
+                None => mesh,
+            };
+            let texture_id = mesh.texture_id.unwrap();
+            stats.prepare_mesh(&mut mesh, mesh_options);
+            obj.add_mesh(&mesh, &material(texture_id)?);
+        }
+    }
+
+    let model = ObjModel {
+        obj,
+        materials,
+        textures,
+    };
+
+    Ok((model, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MapBuilder, WadBuilder};
+    use crate::texture::{self, TextureLoader};
+    use crate::wad::Wad;
+
+    fn context(wad: &Wad) -> gen::Context {
+        let palettes = texture::read_all_palettes(wad).unwrap();
+        let color_maps = texture::read_all_color_maps(wad).unwrap();
+        let texture_loader = TextureLoader::new(
+            wad,
+            color_maps[0].clone(),
+            palettes[0].clone(),
+        )
+        .unwrap();
+
+        gen::Context::new(texture_loader, gen::GenOptions::default())
+    }
+
+    #[test]
+    fn fixture_matches_builder() {
+        let wad = testing::two_rooms_wad();
+        if std::env::var_os("WAD_READER_BLESS").is_some() {
+            std::fs::write(testing::TWO_ROOMS_WAD, &wad).unwrap();
+        }
+
+        let fixture = std::fs::read(testing::TWO_ROOMS_WAD).unwrap();
+        assert!(
+            fixture == wad,
+            "{} is out of date, run the tests with WAD_READER_BLESS=1",
+            testing::TWO_ROOMS_WAD
+        );
+    }
+
+    #[test]
+    fn convert_two_rooms_to_gltf() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        let map = Map::gen_map(&mut context, &wad_map, None);
+        assert_eq!(map.sectors.len(), 2);
+
+        let options = GltfOptions {
+            validate: true,
+            ..Default::default()
+        };
+        let path = testing::temp_dir("convert_two_rooms").join("E1M1.glb");
+        write_map_gltf(&context, &wad_map, map, None, &options, &path)
+            .unwrap();
+
+        let json = testing::glb_json(&std::fs::read(&path).unwrap());
+        let names = json["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| node["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "Sector #0-col",
+                "Sector #0: Slope Mesh-colonly",
+                "Sector #1-col",
+                "Sector #1: Slope Mesh-colonly",
+            ]
+        );
+
+        // NOTE: WALL, FLOOR and CEIL
+        assert_eq!(json["images"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn convert_two_rooms_to_mime() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        let map = Map::gen_map(&mut context, &wad_map, None);
+        let path = testing::temp_dir("convert_mime").join("E1M1.mime");
+        let mesh_options = MeshOptions {
+            weld: false,
+            normals: Normals::Flat,
+        };
+        write_map_mime(&context, map, mesh_options, &path).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[0..4], b"MIME");
+        assert_eq!(
+            u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            mime::MIME_VERSION
+        );
+
+        let mime_map = mime::Map::parse(&data).unwrap();
+        let mut names = mime_map.texture_names.clone();
+        names.sort();
+        assert_eq!(names, [testing::CEIL, testing::FLOOR, testing::WALL]);
+        assert!(!mime_map.vertices.is_empty());
+        assert_eq!(mime_map.indices.len() % 3, 0);
+    }
+
+    #[test]
+    fn convert_two_rooms_to_obj() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+        let mesh_options = MeshOptions {
+            weld: false,
+            normals: Normals::Flat,
+        };
+
+        let map = Map::gen_map(&mut context, &wad_map, None);
+        let mut vertices = 0;
+        let mut faces = 0;
+        for sector in &map.sectors {
+            for mesh in sector.meshes_by_texture() {
+                vertices += mesh.vertex_buffer.len();
+                faces += mesh.index_buffer.len() / 3;
+            }
+        }
+
+        let dir = testing::temp_dir("convert_obj");
+        let path = dir.join("E1M1.obj");
+        write_map_obj(&context, map, None, mesh_options, None, &path).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let count = |prefix: &str| {
+            text.lines().filter(|l| l.starts_with(prefix)).count()
+        };
+        assert_eq!(count("v "), vertices);
+        assert_eq!(count("vt "), vertices);
+        assert_eq!(count("vn "), vertices);
+        assert_eq!(count("f "), faces);
+
+        for name in [testing::CEIL, testing::FLOOR, testing::WALL] {
+            let (id, _) = context.texture_loader.load_from_name(name).unwrap();
+            let material = format!("{}_{}", name, id);
+            assert!(text.contains(&format!("usemtl {}\n", material)));
+            assert!(dir.join("textures").join(material + ".png").exists());
+        }
+    }
+
+    #[test]
+    fn split_files_has_the_same_primitives() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let options = GltfOptions::default();
+
+        let primitives = |json: &serde_json::Value| {
+            json["meshes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|mesh| mesh["primitives"].as_array().unwrap().len())
+                .sum::<usize>()
+        };
+
+        let mut unsplit = context(&wad);
+        let map = Map::gen_map(&mut unsplit, &wad_map, None);
+        let (gltf, _) =
+            build_map_gltf(&unsplit, &wad_map, map, None, &options).unwrap();
+        let expected = primitives(&testing::glb_json(&gltf.write_model()));
+
+        for (split_by, count) in [(SplitBy::Sector, 2), (SplitBy::Texture, 4)]
+        {
+            let dir = testing::temp_dir(&format!("split_{:?}", split_by));
+            let mut context = context(&wad);
+            let map = Map::gen_map(&mut context, &wad_map, None);
+            write_map_gltf_split(
+                &context,
+                &wad_map,
+                map,
+                None,
+                &options,
+                split_by,
+                dir.join("E1M1.glb"),
+            )
+            .unwrap();
+
+            let index =
+                std::fs::read_to_string(dir.join("E1M1.json")).unwrap();
+            let index: serde_json::Value =
+                serde_json::from_str(&index).unwrap();
+            let files = index["files"].as_array().unwrap();
+            assert_eq!(files.len(), count);
+
+            let mut total = 0;
+            for file in files {
+                let name = file["file"].as_str().unwrap();
+                let glb = std::fs::read(dir.join(name)).unwrap();
+                total += primitives(&testing::glb_json(&glb));
+            }
+            assert_eq!(total, expected, "{:?}", split_by);
+        }
+    }
+
+    #[test]
+    fn udmf_map_has_geometry() {
+        let map = testing::MapBuilder::rooms("MAP01", &[(0, 128), (16, 112)]);
+        let data = testing::WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .lumps(map.udmf_lumps("zdoom", b"ZGLN"))
+            .build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "MAP01").unwrap();
+        let mut context = context(&wad);
+
+        let map = Map::gen_map(&mut context, &wad_map, None);
+        assert_eq!(map.sectors.len(), 2);
+        for sector in &map.sectors {
+            assert!(!sector.collision_mesh().vertex_buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn flat_and_smooth_normals() {
+        let map = testing::MapBuilder::rooms("E1M1", &[(0, 128)]);
+        let data = testing::WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .lumps(map.lumps())
+            .build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+        let map = Map::gen_map(&mut context, &wad_map, None);
+
+        let wall_id = context.texture_loader.load_from_name(testing::WALL);
+        let wall_id = wall_id.unwrap().0;
+        let walls = || {
+            map.sectors[0]
+                .meshes_by_texture()
+                .into_iter()
+                .find(|mesh| mesh.texture_id == Some(wall_id))
+                .unwrap()
+        };
+
+        // NOTE: The room is a box, the 4 walls are quads with 4 vertices
+        // each
+        let mut flat = walls();
+        let mut stats = VertexStats::default();
+        let options = MeshOptions {
+            weld: false,
+            normals: Normals::Flat,
+        };
+        stats.prepare_mesh(&mut flat, options);
+        assert_eq!(flat.vertex_buffer.len(), 16);
+        for vertex in &flat.vertex_buffer {
+            let n = vertex.normal;
+            assert_eq!(n.x.abs() + n.y.abs() + n.z.abs(), 1.0);
+        }
+
+        // NOTE: Smooth averages the normals at the 8 corners so they points
+        // out of the corners, the uv seams at the corners keeps the
+        // vertices of the walls apart
+        let mut smooth = walls();
+        let options = MeshOptions {
+            weld: false,
+            normals: Normals::Smooth,
+        };
+        stats.prepare_mesh(&mut smooth, options);
+        assert_eq!(smooth.vertex_buffer.len(), 16);
+        assert_eq!(smooth.index_buffer.len(), flat.index_buffer.len());
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        for vertex in &smooth.vertex_buffer {
+            let n = vertex.normal;
+            assert!((n.x.abs() - diagonal).abs() < 1e-6);
+            assert_eq!(n.y, 0.0);
+            assert!((n.z.abs() - diagonal).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn collision_only_has_no_images_or_textures() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        let map = Map::gen_map(&mut context, &wad_map, None);
+        let options = GltfOptions {
+            collision_only: true,
+            validate: true,
+            ..Default::default()
+        };
+        let (gltf, _) =
+            build_map_gltf(&context, &wad_map, map, None, &options).unwrap();
+
+        let json = testing::glb_json(&gltf.write_model());
+        let count = |key: &str| {
+            json.get(key).map_or(0, |v| v.as_array().unwrap().len())
+        };
+        assert_eq!(count("images"), 0);
+        assert_eq!(count("textures"), 0);
+        for material in json["materials"].as_array().unwrap() {
+            let pbr = &material["pbrMetallicRoughness"];
+            assert!(pbr["baseColorTexture"].is_null());
+        }
+
+        let nodes = json["nodes"].as_array().unwrap();
+        assert!(!nodes.is_empty());
+        for node in nodes {
+            let name = node["name"].as_str().unwrap();
+            assert!(name.ends_with("-colonly"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn merged_sectors_have_no_empty_accessors() {
+        // NOTE: The rooms of two_rooms.wad has a step low enough for a
+        // slope, the single room has no slopes at all
+        let two_rooms = std::fs::read(testing::TWO_ROOMS_WAD).unwrap();
+        let one_room = WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .lumps(MapBuilder::rooms("E1M1", &[(0, 128)]).lumps())
+            .build(b"IWAD");
+
+        for (data, has_slopes) in [(two_rooms, true), (one_room, false)] {
+            let wad = Wad::parse(&data).unwrap();
+            let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+            let mut context = context(&wad);
+
+            let map = Map::gen_map(&mut context, &wad_map, None);
+            let options = GltfOptions {
+                merge_sectors: true,
+                validate: true,
+                ..Default::default()
+            };
+            let (gltf, _) =
+                build_map_gltf(&context, &wad_map, map, None, &options)
+                    .unwrap();
+
+            let json = testing::glb_json(&gltf.write_model());
+            for accessor in json["accessors"].as_array().unwrap() {
+                assert!(accessor["count"].as_u64().unwrap() > 0);
+            }
+
+            let names = json["nodes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|node| node["name"].as_str().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(names.len(), if has_slopes { 2 } else { 1 });
+            assert_eq!(names[0], "E1M1-col");
+        }
+    }
+
+    #[test]
+    fn unknown_texture_id_is_an_error() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        // NOTE: The id is past the end of the textures of the loader
+        let unknown = context.texture_loader.len();
+        let mut maps = (0..4)
+            .map(|_| {
+                let mut map = Map::gen_map(&mut context, &wad_map, None);
+                map.sectors[0].floor_mesh.texture_id = Some(unknown);
+                map
+            })
+            .collect::<Vec<_>>();
+        context.texture_queue.insert(unknown);
+
+        let dir = testing::temp_dir("unknown_texture");
+        let options = GltfOptions::default();
+        let mesh_options = MeshOptions {
+            weld: false,
+            normals: Normals::Flat,
+        };
+        let map = maps.pop().unwrap();
+        assert!(
+            build_map_gltf(&context, &wad_map, map, None, &options).is_err()
+        );
+        let map = maps.pop().unwrap();
+        assert!(map.split(&context, SplitBy::Texture).is_err());
+        let map = maps.pop().unwrap();
+        let path = dir.join("E1M1.mime");
+        assert!(write_map_mime(&context, map, mesh_options, &path).is_err());
+        let map = maps.pop().unwrap();
+        let path = dir.join("E1M1.obj");
+        let result =
+            write_map_obj(&context, map, None, mesh_options, None, &path);
+        assert!(result.is_err());
+    }
+}
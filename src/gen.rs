@@ -1,41 +1,107 @@
 use std::collections::HashSet;
 use crate::wad;
 use crate::util;
-use crate::texture::{Texture, TextureLoader};
+use crate::texture::{self, Texture, TextureLoader};
 use crate::polygon::{Mesh, Quad, Vertex};
 use crate::math::{Vec2, Vec3, Vec4};
 
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum DebugMode {
+    /// Color the vertices with the normal
+    Normals,
+    /// Color the vertices with the uv (r = u, g = v)
+    Uvs,
+    /// Color the slopes red and the rest of the geometry gray
+    Slopes,
+    /// Same as slopes but only the slopes are written
+    SlopesOnly,
+}
+
+/// Options that changes how the geometry is generated, the defaults
+/// generates the map as it looks in the game
+#[derive(Clone, Default, Debug)]
+pub struct GenOptions {
+    pub debug_mode: Option<DebugMode>,
+    /// Use the missing texture placeholder for surfaces without a texture
+    /// instead of skipping them
+    pub show_missing: bool,
+    /// Names of the textures that should be drawn at full brightness
+    pub fullbright_textures: Vec<String>,
+    /// Map the flats of the floors and ceilings like wall textures, the uvs
+    /// starts at 0 in the top left corner of the sector instead of at the
+    /// origin of the map
+    pub flat_to_texture: bool,
+}
+
 pub struct Context {
     pub texture_loader: TextureLoader,
     pub texture_queue: HashSet<usize>,
+    pub options: GenOptions,
+    /// All the problems found while generating the geometry
+    pub warnings: Vec<String>,
 }
 
 impl Context {
-    pub fn new(texture_loader: TextureLoader) -> Self {
+    pub fn new(texture_loader: TextureLoader, options: GenOptions) -> Self {
         Self {
             texture_loader,
             texture_queue: HashSet::new(),
+            options,
+            warnings: Vec::new(),
         }
     }
 
+    pub fn is_fullbright(&self, texture_id: usize) -> bool {
+        if let Some(name) = self.texture_loader.get_name_from_id(texture_id) {
+            self.options
+                .fullbright_textures
+                .iter()
+                .any(|n| n.eq_ignore_ascii_case(name))
+        } else {
+            false
+        }
+    }
+
+    /// Returns None when the texture is missing and the surface should be
+    /// skipped
     fn texture(
         &mut self,
         texture_name: &str,
         queue_texture: bool,
-    ) -> (usize, &Texture) {
-        if let Some(texture) = self.texture_loader.load_from_name(texture_name)
-        {
-            if queue_texture {
-                self.texture_queue.insert(texture.0);
-            }
-            return texture;
+    ) -> Option<(usize, &Texture)> {
+        // NOTE: The debug modes doesn't use any textures
+        let queue_texture = queue_texture && self.options.debug_mode.is_none();
+
+        let missing_name = texture::is_missing_name(texture_name)
+            || self.texture_loader.is_placeholder(texture_name);
+        let texture = if missing_name {
+            None
         } else {
-            let texture = self.texture_loader.missing_texture();
-            if queue_texture {
-                self.texture_queue.insert(texture.0);
+            self.texture_loader.load_from_name(texture_name)
+        };
+
+        // NOTE: "-" (and the placeholder texture) means that there is no
+        // texture, only warn about names that doesn't exist
+        if texture.is_none() && !missing_name {
+            let warning = format!("Missing texture '{}'", texture_name);
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+        }
+
+        let texture = match texture {
+            Some(texture) => texture,
+            None if self.options.show_missing => {
+                self.texture_loader.missing_texture()
             }
-            return texture;
+            None => return None,
+        };
+
+        if queue_texture {
+            self.texture_queue.insert(texture.0);
         }
+
+        Some(texture)
     }
 
     // fn queue_texture(&mut self, texture_name: &str) -> Option<usize> {
@@ -50,38 +116,160 @@ impl Context {
     // }
 }
 
+/// Calculates the normal of each vertex by averaging the normals of the
+/// triangles the vertex is part of. Uses the fallback normal for degenerate
+/// polygons
+fn calculate_normals(verts: &mut [Vertex], triangles: &[u32], fallback: Vec3) {
+    let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); verts.len()];
+
+    for triangle in triangles.chunks(3) {
+        let a = triangle[0] as usize;
+        let b = triangle[1] as usize;
+        let c = triangle[2] as usize;
+
+        let pa = verts[a].pos;
+        let pb = verts[b].pos;
+        let pc = verts[c].pos;
+
+        // NOTE: Not normalized so bigger triangles have more weight
+        let normal = (pb - pa).cross(pc - pa);
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
+    }
+
+    for (vert, normal) in verts.iter_mut().zip(normals) {
+        vert.normal = if normal.length() > f32::EPSILON {
+            normal.normalize()
+        } else {
+            fallback
+        };
+    }
+}
+
+fn apply_debug_color(debug_mode: Option<DebugMode>, verts: &mut [Vertex]) {
+    if let Some(debug_mode) = debug_mode {
+        for vert in verts {
+            vert.color = match debug_mode {
+                DebugMode::Normals => {
+                    let x = (vert.normal.x * 0.5) + 0.5;
+                    let y = (vert.normal.y * 0.5) + 0.5;
+                    let z = (vert.normal.z * 0.5) + 0.5;
+                    Vec4::new(x, y, z, 1.0)
+                }
+
+                DebugMode::Uvs => {
+                    // NOTE: rem_euclid so negative uvs also ends up in 0..1
+                    let u = vert.uv.x.rem_euclid(1.0);
+                    let v = vert.uv.y.rem_euclid(1.0);
+                    Vec4::new(u, v, 0.0, 1.0)
+                }
+
+                DebugMode::Slopes | DebugMode::SlopesOnly => {
+                    Vec4::new(0.5, 0.5, 0.5, 1.0)
+                }
+            };
+        }
+    }
+}
+
+/// The number of vertices and indices needed for the floor or the ceiling of
+/// a sector, each sub sector is a triangle fan
+fn polygon_buffer_sizes(wad_sector: &wad::Sector) -> (usize, usize) {
+    let vertex_count = wad_sector.sub_sectors.iter().map(|s| s.count).sum();
+    let index_count = wad_sector
+        .sub_sectors
+        .iter()
+        .map(|s| s.count.saturating_sub(2) * 3)
+        .sum();
+
+    (vertex_count, index_count)
+}
+
+/// The uv offset of the flats of the sector, with flat_to_texture the
+/// offset moves the top left flat of the sector to (0, 0)
+///
+/// NOTE: The offset is whole flats so the flats still lines up with the
+/// flats of the sectors next to it
+fn flat_uv_offset(
+    context: &Context,
+    wad_map: &wad::Map,
+    wad_sector: &wad::Sector,
+    dim: Vec2,
+) -> Vec2 {
+    if !context.options.flat_to_texture {
+        return Vec2::new(0.0, 0.0);
+    }
+
+    let mut min_u = f32::INFINITY;
+    let mut min_v = f32::INFINITY;
+    for sub_sector in &wad_sector.sub_sectors {
+        for segment in 0..sub_sector.count {
+            let segment = wad_map.segments[sub_sector.start + segment];
+            let start = wad_map.vertex(segment.start_vertex);
+            let uv = Vec2::new(start.x, start.y) * dim;
+            min_u = min_u.min(uv.x);
+            min_v = min_v.min(uv.y);
+        }
+    }
+
+    if min_u.is_finite() && min_v.is_finite() {
+        Vec2::new(-min_u.floor(), -min_v.floor())
+    } else {
+        Vec2::new(0.0, 0.0)
+    }
+}
+
 pub fn gen_floor(
     context: &mut Context,
     wad_map: &wad::Map,
     wad_sector: &wad::Sector,
 ) -> Mesh {
-    let mut mesh = Mesh::new();
+    let (vertex_count, index_count) = polygon_buffer_sizes(wad_sector);
+    let mut mesh = Mesh::with_capacity(vertex_count, index_count);
 
     let (texture_id, texture) =
-        context.texture(&wad_sector.floor_texture, true);
+        match context.texture(&wad_sector.floor_texture, true) {
+            Some(texture) => texture,
+            None => return mesh,
+        };
     mesh.texture_id = Some(texture_id);
 
     let w = 1.0 / texture.width() as f32;
     let h = 1.0 / texture.height() as f32;
 
     let dim = Vec2::new(w, -h);
+    let offset = flat_uv_offset(context, wad_map, wad_sector, dim);
 
     for sub_sector in &wad_sector.sub_sectors {
-        let mut verts = Vec::new();
+        let mut verts = Vec::with_capacity(sub_sector.count);
 
         for segment in 0..sub_sector.count {
             let segment = wad_map.segments[sub_sector.start + segment];
             let start = wad_map.vertex(segment.start_vertex);
 
-            let pos = Vec3::new(start.x, wad_sector.floor_height, start.y);
-            let uv = Vec2::new(start.x, start.y) * dim;
+            let height = wad_sector.floor_height_at(start.x, start.y);
+            let pos = Vec3::new(start.x, height, start.y);
+            let uv = Vec2::new(start.x, start.y) * dim + offset;
             let color = Vec4::new(1.0, 1.0, 1.0, 1.0);
             let normal = Vec3::new(0.0, 1.0, 0.0);
             verts.push(Vertex::new(pos, normal, uv, color));
         }
 
         util::cleanup_lines(&mut verts);
-        mesh.add_vertices(&verts, true);
+
+        // NOTE: Sub sectors can collapse to a line or a point after the
+        // cleanup and then there is nothing to triangulate
+        if verts.len() < 3 {
+            continue;
+        }
+
+        // NOTE: Triangulate once and use the same triangles for the
+        // normals and the mesh
+        let triangles = util::triangulate(&verts, true);
+        calculate_normals(&mut verts, &triangles, Vec3::new(0.0, 1.0, 0.0));
+        apply_debug_color(context.options.debug_mode, &mut verts);
+        mesh.add_triangles(&verts, &triangles);
     }
 
     mesh
@@ -92,56 +280,98 @@ pub fn gen_ceiling(
     wad_map: &wad::Map,
     wad_sector: &wad::Sector,
 ) -> Mesh {
-    let mut mesh = Mesh::new();
+    let (vertex_count, index_count) = polygon_buffer_sizes(wad_sector);
+    let mut mesh = Mesh::with_capacity(vertex_count, index_count);
 
     let (texture_id, texture) =
-        context.texture(&wad_sector.ceiling_texture, true);
+        match context.texture(&wad_sector.ceiling_texture, true) {
+            Some(texture) => texture,
+            None => return mesh,
+        };
     mesh.texture_id = Some(texture_id);
 
     let w = 1.0 / texture.width() as f32;
     let h = 1.0 / texture.height() as f32;
 
     let dim = Vec2::new(w, -h);
+    let offset = flat_uv_offset(context, wad_map, wad_sector, dim);
 
     for sub_sector in &wad_sector.sub_sectors {
-        let mut verts = Vec::new();
+        let mut verts = Vec::with_capacity(sub_sector.count);
 
         for segment in 0..sub_sector.count {
             let segment = wad_map.segments[sub_sector.start + segment];
             let start = wad_map.vertex(segment.start_vertex);
 
-            let pos = Vec3::new(start.x, wad_sector.ceiling_height, start.y);
-            let uv = Vec2::new(start.x, start.y) * dim;
+            let height = wad_sector.ceiling_height_at(start.x, start.y);
+            let pos = Vec3::new(start.x, height, start.y);
+            let uv = Vec2::new(start.x, start.y) * dim + offset;
             let color = Vec4::new(1.0, 1.0, 1.0, 1.0);
             let normal = Vec3::new(0.0, -1.0, 0.0);
             verts.push(Vertex::new(pos, normal, uv, color));
         }
 
         util::cleanup_lines(&mut verts);
-        mesh.add_vertices(&verts, false);
+
+        // NOTE: Sub sectors can collapse to a line or a point after the
+        // cleanup and then there is nothing to triangulate
+        if verts.len() < 3 {
+            continue;
+        }
+
+        // NOTE: Triangulate once and use the same triangles for the
+        // normals and the mesh
+        let triangles = util::triangulate(&verts, false);
+        calculate_normals(&mut verts, &triangles, Vec3::new(0.0, -1.0, 0.0));
+        apply_debug_color(context.options.debug_mode, &mut verts);
+        mesh.add_triangles(&verts, &triangles);
     }
 
     mesh
 }
 
-fn create_quad(p1: Vec2, p2: Vec2, bottom: f32, top: f32) -> Quad {
-    let pos0 = Vec3::new(p1.x, top, p1.y);
-    let pos1 = Vec3::new(p1.x, bottom, p1.y);
-    let pos2 = Vec3::new(p2.x, bottom, p2.y);
-    let pos3 = Vec3::new(p2.x, top, p2.y);
+/// The floor heights of the sector at the start and the end of a line
+fn floor_heights(
+    sector: &wad::Sector,
+    start: wad::Vertex,
+    end: wad::Vertex,
+) -> [f32; 2] {
+    [
+        sector.floor_height_at(start.x, start.y),
+        sector.floor_height_at(end.x, end.y),
+    ]
+}
+
+/// The ceiling heights of the sector at the start and the end of a line
+fn ceiling_heights(
+    sector: &wad::Sector,
+    start: wad::Vertex,
+    end: wad::Vertex,
+) -> [f32; 2] {
+    [
+        sector.ceiling_height_at(start.x, start.y),
+        sector.ceiling_height_at(end.x, end.y),
+    ]
+}
 
-    let a = pos1;
-    let b = pos3;
-    let c = pos2;
+/// Creates the quad from p1 to p2, the bottom and top are the heights at
+/// p1 and p2 so the quad can follow sloped floors and ceilings
+fn create_quad(p1: Vec2, p2: Vec2, bottom: [f32; 2], top: [f32; 2]) -> Quad {
+    let pos0 = Vec3::new(p1.x, top[0], p1.y);
+    let pos1 = Vec3::new(p1.x, bottom[0], p1.y);
+    let pos2 = Vec3::new(p2.x, bottom[1], p2.y);
+    let pos3 = Vec3::new(p2.x, top[1], p2.y);
+
+    // NOTE: The normal is calculated from the average height of the quad,
+    // one end of a sloped quad can have no height at all
+    let height = ((top[0] - bottom[0]) + (top[1] - bottom[1])) / 2.0;
+    let a = Vec3::new(p1.x, 0.0, p1.y);
+    let b = Vec3::new(p2.x, height, p2.y);
+    let c = Vec3::new(p2.x, 0.0, p2.y);
 
     // TODO(patrik): Check the normal
     let normal = ((b - a).cross(c - a)).normalize();
 
-    // let x = (normal.x * 0.5) + 0.5;
-    // let y = (normal.y * 0.5) + 0.5;
-    // let z = (normal.z * 0.5) + 0.5;
-    // let color = Vec4::new(x, y, z, 1.0);
-
     let color = Vec4::new(1.0, 1.0, 1.0, 1.0);
     let uv = Vec2::new(0.0, 0.0);
 
@@ -171,8 +401,18 @@ fn update_quad_uvs(
     let texture_size =
         Vec2::new(texture.width() as f32, texture.height() as f32);
 
+    // NOTE: The texture repeats horizontally so the offset can be wrapped
+    // inside the texture, large offsets would otherwise lose precision on
+    // long walls. rem_euclid keeps negative offsets wrapping the same
+    // direction as DOOM (-1 is the last column of the texture)
+    let offset = if texture_size.x > 0.0 {
+        Vec2::new(offset.x.rem_euclid(texture_size.x), offset.y)
+    } else {
+        offset
+    };
+
     if lower_peg {
-        y2 = offset.y + texture_size.y as f32;
+        y2 = offset.y + texture_size.y;
         y1 = y2 - height;
     }
 
@@ -196,13 +436,16 @@ fn create_normal_wall_quad(
     sidedef: &wad::Sidedef,
     start: wad::Vertex,
     end: wad::Vertex,
-) -> Quad {
-    let (texture_id, texture) = context.texture(&sidedef.middle_texture, true);
+) -> Option<Quad> {
+    let (texture_id, texture) =
+        context.texture(&sidedef.middle_texture, true)?;
+
+    let floor = floor_heights(sector, start, end);
+    let ceiling = ceiling_heights(sector, start, end);
 
     let start = Vec2::new(start.x, start.y);
     let end = Vec2::new(end.x, end.y);
-    let mut quad =
-        create_quad(start, end, sector.floor_height, sector.ceiling_height);
+    let mut quad = create_quad(start, end, floor, ceiling);
     quad.texture_id = texture_id;
 
     let length = (end - start).length();
@@ -213,82 +456,102 @@ fn create_normal_wall_quad(
         .contains(wad::LinedefFlags::LOWER_TEXTURE_UNPEGGED);
     update_quad_uvs(
         &mut quad,
-        &texture,
+        texture,
         length,
         offset,
-        sector.floor_height,
-        sector.ceiling_height,
+        floor[0].min(floor[1]),
+        ceiling[0].max(ceiling[1]),
         lower_peg,
     );
 
-    quad
+    Some(quad)
 }
 
+/// Generates the wall between two floors (lower_quad) or two ceilings
+///
+/// The texture is placed like DOOM does it:
+/// - Lower: top of the texture at the top of the wall
+/// - Lower unpegged: top of the texture at the ceiling of the sector the
+///   wall is seen from
+/// - Upper: bottom of the texture at the bottom of the wall
+/// - Upper unpegged: top of the texture at the top of the wall
+///
+/// The sidedef and viewer_sector is the side the wall is visible from, that
+/// is the side with the lower floor or the higher ceiling
+///
+/// The front and back are the heights at the start and the end of the line
+#[allow(clippy::too_many_arguments)]
 fn gen_diff_wall(
     texture: &Texture,
     linedef: &wad::Linedef,
     sidedef: &wad::Sidedef,
-    front_sector: &wad::Sector,
-    back_sector: &wad::Sector,
+    viewer_sector: &wad::Sector,
     start: wad::Vertex,
     end: wad::Vertex,
-    front: f32,
-    back: f32,
+    front: [f32; 2],
+    back: [f32; 2],
     lower_quad: bool,
 ) -> Quad {
+    let viewer_ceiling = ceiling_heights(viewer_sector, start, end);
+
     let start = Vec2::new(start.x, start.y);
     let end = Vec2::new(end.x, end.y);
     let mut quad = create_quad(start, end, front, back);
 
     let length = (end - start).length();
 
+    // NOTE: The quad can be upside down depending on which side is higher,
+    // update_quad_uvs handles that as long as bottom is below top
+    let bottom = front[0].min(front[1]).min(back[0]).min(back[1]);
+    let top = front[0].max(front[1]).max(back[0]).max(back[1]);
+
+    let mut offset =
+        Vec2::new(sidedef.x_offset as f32, sidedef.y_offset as f32);
+
     if lower_quad {
-        let mut offset =
-            Vec2::new(sidedef.x_offset as f32, sidedef.y_offset as f32);
         if linedef
             .flags
             .contains(wad::LinedefFlags::LOWER_TEXTURE_UNPEGGED)
         {
-            offset.y += front_sector.ceiling_height - back_sector.floor_height;
+            offset.y += viewer_ceiling[0].max(viewer_ceiling[1]) - top;
         }
 
         update_quad_uvs(
-            &mut quad, &texture, length, offset, front, back, false,
+            &mut quad, texture, length, offset, bottom, top, false,
         );
     } else {
-        let offset =
-            Vec2::new(sidedef.x_offset as f32, sidedef.y_offset as f32);
-
         let upper_peg = linedef
             .flags
             .contains(wad::LinedefFlags::UPPER_TEXTURE_UNPEGGED);
         update_quad_uvs(
-            &mut quad, &texture, length, offset, front, back, !upper_peg,
+            &mut quad, texture, length, offset, bottom, top, !upper_peg,
         );
     }
 
     quad
 }
 
+/// Generates the collision ramp of a step, the lower edge is pushed out
+/// from the step by the height of the step at both ends of the line
 fn gen_slope(
     start: wad::Vertex,
     end: wad::Vertex,
-    front: f32,
-    back: f32,
-    height: f32,
+    front: [f32; 2],
+    back: [f32; 2],
 ) -> Quad {
     let start = Vec2::new(start.x, start.y);
     let end = Vec2::new(end.x, end.y);
     let mut quad = create_quad(start, end, front, back);
 
     let normal = quad.points[0].normal;
+    let height = [(front[0] - back[0]).abs(), (front[1] - back[1]).abs()];
 
-    if front < back {
-        quad.points[1].pos += normal * height;
-        quad.points[2].pos += normal * height;
+    if front[0] + front[1] < back[0] + back[1] {
+        quad.points[1].pos += normal * height[0];
+        quad.points[2].pos += normal * height[1];
     } else {
-        quad.points[0].pos += normal * height;
-        quad.points[3].pos += normal * height;
+        quad.points[0].pos += normal * height[0];
+        quad.points[3].pos += normal * height[1];
     }
 
     quad
@@ -305,7 +568,7 @@ pub fn gen_walls(
     for sub_sector in &wad_sector.sub_sectors {
         for segment in 0..sub_sector.count {
             let segment = wad_map.segments[sub_sector.start + segment];
-            if segment.linedef == 0xffff {
+            if segment.linedef == wad::MINISEG {
                 continue;
             }
 
@@ -319,91 +582,461 @@ pub fn gen_walls(
                     let sidedef = &wad_map.sidedefs[sidedef];
 
                     let quad = create_normal_wall_quad(
-                        context, wad_sector, &linedef, &sidedef, start, end,
+                        context, wad_sector, &linedef, sidedef, start, end,
                     );
 
-                    quads.push(quad);
+                    if let Some(quad) = quad {
+                        quads.push(quad);
+                    }
                 }
             }
 
-            if linedef.front_sidedef.is_some()
-                && linedef.back_sidedef.is_some()
+            if let (Some(front_sidedef), Some(back_sidedef)) =
+                (linedef.front_sidedef, linedef.back_sidedef)
             {
-                let front_sidedef = linedef.front_sidedef.unwrap();
                 let front_sidedef = &wad_map.sidedefs[front_sidedef];
-
-                let back_sidedef = linedef.back_sidedef.unwrap();
                 let back_sidedef = &wad_map.sidedefs[back_sidedef];
 
+                // NOTE: Self-referencing sectors (the same sector on both
+                // sides, used for deep water and invisible walls) have no
+                // height differences so the line is skipped, the floor and
+                // ceiling of the sector are still generated from the sub
+                // sectors like any other sector
+                if front_sidedef.sector == back_sidedef.sector {
+                    continue;
+                }
+
                 let front_sector = &wad_map.sectors[front_sidedef.sector];
                 let back_sector = &wad_map.sectors[back_sidedef.sector];
 
                 // Generate the floor difference
-                if front_sector.floor_height != back_sector.floor_height {
-                    let front = front_sector.floor_height;
-                    let back = back_sector.floor_height;
-                    let height = (front - back).abs();
+                let front = floor_heights(front_sector, start, end);
+                let back = floor_heights(back_sector, start, end);
+                if front != back {
+                    let height = (front[0] - back[0])
+                        .abs()
+                        .max((front[1] - back[1]).abs());
 
                     if height <= 24.0 {
-                        let quad = gen_slope(start, end, front, back, height);
+                        let quad = gen_slope(start, end, front, back);
                         slope_quads.push(quad);
                     }
 
-                    let (texture_id, texture) =
-                        if front_sidedef.lower_texture == "-" {
-                            context.texture(&back_sidedef.lower_texture, true)
+                    let (viewer_sidedef, viewer_sector, other_sidedef) =
+                        if front[0] + front[1] < back[0] + back[1] {
+                            (front_sidedef, front_sector, back_sidedef)
                         } else {
-                            context.texture(&front_sidedef.lower_texture, true)
+                            (back_sidedef, back_sector, front_sidedef)
                         };
 
-                    let mut quad = gen_diff_wall(
-                        texture,
-                        &linedef,
-                        &front_sidedef,
-                        front_sector,
-                        back_sector,
-                        start,
-                        end,
-                        front,
-                        back,
-                        true,
-                    );
-                    quad.texture_id = texture_id;
-
-                    quads.push(quad);
+                    // NOTE: The wall is seen from the viewer side so the
+                    // texture and the offsets comes from the same sidedef,
+                    // the other side is only used when the texture is
+                    // missing
+                    let texture = if texture::is_missing_name(
+                        &viewer_sidedef.lower_texture,
+                    ) {
+                        context.texture(&other_sidedef.lower_texture, true)
+                    } else {
+                        context.texture(&viewer_sidedef.lower_texture, true)
+                    };
+
+                    if let Some((texture_id, texture)) = texture {
+                        let mut quad = gen_diff_wall(
+                            texture,
+                            &linedef,
+                            viewer_sidedef,
+                            viewer_sector,
+                            start,
+                            end,
+                            front,
+                            back,
+                            true,
+                        );
+                        quad.texture_id = texture_id;
+
+                        quads.push(quad);
+                    }
                 }
 
                 // Generate the height difference
-                if front_sector.ceiling_height != back_sector.ceiling_height {
-                    let front = front_sector.ceiling_height;
-                    let back = back_sector.ceiling_height;
-
-                    let (texture_id, texture) =
-                        if front_sidedef.upper_texture == "-" {
-                            context.texture(&back_sidedef.upper_texture, true)
+                let front = ceiling_heights(front_sector, start, end);
+                let back = ceiling_heights(back_sector, start, end);
+                if front != back {
+                    let (viewer_sidedef, viewer_sector, other_sidedef) =
+                        if front[0] + front[1] > back[0] + back[1] {
+                            (front_sidedef, front_sector, back_sidedef)
                         } else {
-                            context.texture(&front_sidedef.upper_texture, true)
+                            (back_sidedef, back_sector, front_sidedef)
                         };
 
-                    let mut quad = gen_diff_wall(
-                        texture,
-                        &linedef,
-                        &front_sidedef,
-                        front_sector,
-                        back_sector,
-                        start,
-                        end,
-                        back,
-                        front,
-                        false,
-                    );
-                    quad.texture_id = texture_id;
-
-                    quads.push(quad);
+                    let texture = if texture::is_missing_name(
+                        &viewer_sidedef.upper_texture,
+                    ) {
+                        context.texture(&other_sidedef.upper_texture, true)
+                    } else {
+                        context.texture(&viewer_sidedef.upper_texture, true)
+                    };
+
+                    if let Some((texture_id, texture)) = texture {
+                        let mut quad = gen_diff_wall(
+                            texture,
+                            &linedef,
+                            viewer_sidedef,
+                            viewer_sector,
+                            start,
+                            end,
+                            back,
+                            front,
+                            false,
+                        );
+                        quad.texture_id = texture_id;
+
+                        quads.push(quad);
+                    }
                 }
             }
         }
     }
 
+    for quad in &mut quads {
+        apply_debug_color(context.options.debug_mode, &mut quad.points);
+    }
+
+    if matches!(
+        context.options.debug_mode,
+        Some(DebugMode::Slopes | DebugMode::SlopesOnly)
+    ) {
+        for quad in &mut slope_quads {
+            for point in &mut quad.points {
+                point.color = Vec4::new(1.0, 0.0, 0.0, 1.0);
+            }
+        }
+    }
+
     (quads, slope_quads)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::wad::Wad;
+
+    fn context(wad: &Wad) -> Context {
+        let palettes = texture::read_all_palettes(wad).unwrap();
+        let color_maps = texture::read_all_color_maps(wad).unwrap();
+        let texture_loader = TextureLoader::new(
+            wad,
+            color_maps[0].clone(),
+            palettes[0].clone(),
+        )
+        .unwrap();
+
+        Context::new(texture_loader, GenOptions::default())
+    }
+
+    fn vertex(x: f32, z: f32) -> Vertex {
+        let pos = Vec3::new(x, 0.0, z);
+        let color = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        Vertex::new(pos, Vec3::default(), Vec2::default(), color)
+    }
+
+    #[test]
+    fn ccw_floor_has_an_upward_normal() {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let down = Vec3::new(0.0, -1.0, 0.0);
+
+        // NOTE: Counter clockwise when looking down on the floor, the
+        // fallback points down so it can't be the reason the test passes
+        let mut verts =
+            [vertex(0.0, 0.0), vertex(0.0, 64.0), vertex(64.0, 64.0)];
+        calculate_normals(&mut verts, &[0, 1, 2], down);
+        for vert in &verts {
+            assert!(vert.normal.approx_eq(up, 1e-6));
+        }
+
+        calculate_normals(&mut verts, &[0, 2, 1], up);
+        for vert in &verts {
+            assert!(vert.normal.approx_eq(down, 1e-6));
+        }
+
+        // NOTE: All the points on a line
+        let mut verts =
+            [vertex(0.0, 0.0), vertex(0.0, 32.0), vertex(0.0, 64.0)];
+        calculate_normals(&mut verts, &[0, 1, 2], up);
+        for vert in &verts {
+            assert!(vert.normal.approx_eq(up, 1e-6));
+        }
+    }
+
+    #[test]
+    fn floor_and_ceiling_normals() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        let floor = gen_floor(&mut context, &wad_map, &wad_map.sectors[0]);
+        assert!(!floor.vertex_buffer.is_empty());
+        for vertex in &floor.vertex_buffer {
+            assert!(vertex.normal.approx_eq(Vec3::new(0.0, 1.0, 0.0), 1e-6));
+        }
+
+        let ceiling = gen_ceiling(&mut context, &wad_map, &wad_map.sectors[0]);
+        assert!(!ceiling.vertex_buffer.is_empty());
+        for vertex in &ceiling.vertex_buffer {
+            assert!(vertex.normal.approx_eq(Vec3::new(0.0, -1.0, 0.0), 1e-6));
+        }
+    }
+
+    /// The top and bottom V of the upper wall between the two rooms
+    fn upper_wall_v(context: &mut Context, wad_map: &wad::Map) -> (f32, f32) {
+        let (quads, _) = gen_walls(context, wad_map, &wad_map.sectors[0]);
+        let upper = quads
+            .iter()
+            .find(|quad| {
+                quad.points
+                    .iter()
+                    .all(|p| p.pos.x == 128.0 && p.pos.y >= 112.0)
+            })
+            .unwrap();
+
+        let v_at = |height: f32| {
+            let point =
+                upper.points.iter().find(|p| p.pos.y == height).unwrap();
+            point.uv.y
+        };
+        (v_at(128.0), v_at(112.0))
+    }
+
+    #[test]
+    fn upper_texture_pegging() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let mut wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        // NOTE: The upper wall is 16 units high (112 to 128) and the
+        // texture is 64 units high. Pegged the bottom of the texture is at
+        // the bottom of the wall
+        assert_eq!(upper_wall_v(&mut context, &wad_map), (0.75, 1.0));
+
+        // NOTE: Unpegged the top of the texture is at the top of the wall
+        let portal = wad_map
+            .linedefs
+            .iter_mut()
+            .find(|l| l.flags.contains(wad::LinedefFlags::TWO_SIDED))
+            .unwrap();
+        portal
+            .flags
+            .insert(wad::LinedefFlags::UPPER_TEXTURE_UNPEGGED);
+        assert_eq!(upper_wall_v(&mut context, &wad_map), (0.0, 0.25));
+    }
+
+    #[test]
+    fn walls_follow_a_tilted_floor() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let mut wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        // NOTE: The floor of the second room goes from 16 at y = 0 to 24 at
+        // y = 128
+        let floor_at = |y: f32| 16.0 + y / 16.0;
+        wad_map.sectors[1].floor_plane = Some(wad::Plane {
+            a: 0.0,
+            b: -1.0 / 16.0,
+            c: 1.0,
+            d: -16.0,
+        });
+
+        let floor = gen_floor(&mut context, &wad_map, &wad_map.sectors[1]);
+        let heights = floor
+            .vertex_buffer
+            .iter()
+            .map(|v| v.pos.y)
+            .collect::<Vec<_>>();
+        assert!(heights.contains(&16.0));
+        assert!(heights.contains(&24.0));
+        for vertex in &floor.vertex_buffer {
+            assert_eq!(vertex.pos.y, floor_at(vertex.pos.z));
+        }
+
+        // NOTE: The lower wall of the portal between the rooms goes from
+        // the flat floor of the first room up to the tilted floor
+        let (quads, _) =
+            gen_walls(&mut context, &wad_map, &wad_map.sectors[0]);
+        let portal = quads
+            .iter()
+            .find(|quad| quad.points.iter().all(|p| p.pos.x == 128.0))
+            .unwrap();
+        let mut tops = portal
+            .points
+            .iter()
+            .filter(|p| p.pos.y != 0.0)
+            .map(|p| (p.pos.z, p.pos.y))
+            .collect::<Vec<_>>();
+        tops.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(tops, [(0.0, 16.0), (128.0, 24.0)]);
+
+        // NOTE: The right wall of the second room starts at the tilted
+        // floor
+        let (quads, _) =
+            gen_walls(&mut context, &wad_map, &wad_map.sectors[1]);
+        let wall = quads
+            .iter()
+            .find(|quad| quad.points.iter().all(|p| p.pos.x == 256.0))
+            .unwrap();
+        assert_ne!(wall.points[1].pos.y, wall.points[2].pos.y);
+        for point in [wall.points[1], wall.points[2]] {
+            assert_eq!(point.pos.y, floor_at(point.pos.z));
+        }
+    }
+
+    #[test]
+    fn x_offset_wraps_inside_the_texture() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let mut wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        // NOTE: The left wall is 128 units long and the texture is 64 units
+        // wide so the wall repeats it 2 times
+        let left_wall = wad_map.linedefs[0].front_sidedef.unwrap();
+        for (x_offset, start) in [
+            (0, 0.0),
+            (64, 0.0),
+            (65, 1.0 / 64.0),
+            (32767, 63.0 / 64.0),
+            (-1, 63.0 / 64.0),
+        ] {
+            wad_map.sidedefs[left_wall].x_offset = x_offset;
+            let (quads, _) =
+                gen_walls(&mut context, &wad_map, &wad_map.sectors[0]);
+            let quad = quads
+                .iter()
+                .find(|quad| quad.points.iter().all(|p| p.pos.x == 0.0))
+                .unwrap();
+
+            let u = quad.points.map(|p| p.uv.x);
+            assert_eq!(
+                u,
+                [start, start, start + 2.0, start + 2.0],
+                "{}",
+                x_offset
+            );
+        }
+    }
+
+    #[test]
+    fn self_referencing_linedef_has_no_walls() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let mut wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        let portal_quads = |context: &mut Context, wad_map: &wad::Map| {
+            let (quads, _) = gen_walls(context, wad_map, &wad_map.sectors[0]);
+            quads
+                .iter()
+                .filter(|quad| quad.points.iter().all(|p| p.pos.x == 128.0))
+                .count()
+        };
+        // NOTE: The upper and the lower wall between the rooms
+        assert_eq!(portal_quads(&mut context, &wad_map), 2);
+
+        // NOTE: The portal gets the first room on both sides
+        let portal = wad_map
+            .linedefs
+            .iter()
+            .find(|l| l.flags.contains(wad::LinedefFlags::TWO_SIDED))
+            .unwrap();
+        let sides = [portal.front_sidedef, portal.back_sidedef];
+        for side in sides.into_iter().flatten() {
+            wad_map.sidedefs[side].sector = 0;
+        }
+        assert_eq!(portal_quads(&mut context, &wad_map), 0);
+    }
+
+    #[test]
+    fn flats_tile_every_64_units() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        let uv_ranges = |context: &mut Context, sector: usize| {
+            let sector = &wad_map.sectors[sector];
+            let floor = gen_floor(context, &wad_map, sector);
+            let ceiling = gen_ceiling(context, &wad_map, sector);
+            [floor, ceiling].map(|mesh| {
+                let range = |value: fn(&Vertex) -> f32| {
+                    let values = mesh.vertex_buffer.iter().map(value);
+                    let min = values.clone().fold(f32::INFINITY, f32::min);
+                    let max = values.fold(f32::NEG_INFINITY, f32::max);
+                    (min, max)
+                };
+                (range(|v| v.uv.x), range(|v| v.uv.y))
+            })
+        };
+
+        // NOTE: The rooms are 128x128 so a 64x64 flat is repeated twice
+        // along both axes, v is negated so the north of the map is the top
+        // of the flat
+        let first = ((0.0, 2.0), (-2.0, 0.0));
+        assert_eq!(uv_ranges(&mut context, 0), [first; 2]);
+        let second = ((2.0, 4.0), (-2.0, 0.0));
+        assert_eq!(uv_ranges(&mut context, 1), [second; 2]);
+
+        // NOTE: Mapped like a texture both rooms starts at (0, 0)
+        context.options.flat_to_texture = true;
+        let texture = ((0.0, 2.0), (0.0, 2.0));
+        assert_eq!(uv_ranges(&mut context, 0), [texture; 2]);
+        assert_eq!(uv_ranges(&mut context, 1), [texture; 2]);
+    }
+
+    #[test]
+    fn portal_textures_come_from_the_viewer_side() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let mut wad_map = wad::Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let mut context = context(&wad);
+
+        // NOTE: The front side of the portal is in the second room, both
+        // the upper and the lower wall are seen from the first room (the
+        // back side)
+        let portal = *wad_map
+            .linedefs
+            .iter()
+            .find(|l| l.flags.contains(wad::LinedefFlags::TWO_SIDED))
+            .unwrap();
+        let front = portal.front_sidedef.unwrap();
+        let back = portal.back_sidedef.unwrap();
+        for (side, name) in [(front, testing::MASKED), (back, testing::WALL)] {
+            wad_map.sidedefs[side].upper_texture = name.to_string();
+            wad_map.sidedefs[side].lower_texture = name.to_string();
+        }
+
+        let id = |name| context.texture_loader.load_from_name(name).unwrap().0;
+        let (wall, masked) = (id(testing::WALL), id(testing::MASKED));
+
+        let portal_textures = |context: &mut Context, wad_map: &wad::Map| {
+            let (quads, _) = gen_walls(context, wad_map, &wad_map.sectors[0]);
+            quads
+                .iter()
+                .filter(|quad| quad.points.iter().all(|p| p.pos.x == 128.0))
+                .map(|quad| quad.texture_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(portal_textures(&mut context, &wad_map), [wall, wall]);
+
+        // NOTE: Without a texture on the viewer side the other side is used
+        wad_map.sidedefs[back].upper_texture = "-".to_string();
+        let mut textures = portal_textures(&mut context, &wad_map);
+        textures.sort();
+        let mut expected = [wall, masked];
+        expected.sort();
+        assert_eq!(textures, expected);
+    }
+}
@@ -1,7 +1,8 @@
 use serde::{Serialize, Deserialize};
-use crate::math::{Vec2, Vec3, Vec4};
-use crate::Mesh;
-use std::collections::HashMap;
+use crate::math::{Mat4, Vec2, Vec3, Vec4};
+use crate::polygon::Mesh;
+use std::collections::BTreeMap;
+use std::io::Write;
 
 type BufferViewId = usize;
 type MaterialId = usize;
@@ -23,6 +24,66 @@ struct GltfAccessor {
     typ: String,
 }
 
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum SamplerFilter {
+    /// Sharp pixels like DOOM
+    Nearest,
+    /// Smooth pixels
+    Linear,
+}
+
+/// The axis the height of the map is written to
+///
+/// The generated geometry is (x, height, y) in map units, Y up writes it as
+/// is and Z up rotates it 90 degrees around the X axis so the height ends
+/// up on Z, a rotation keeps the winding order of the triangles
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Debug)]
+pub enum UpAxis {
+    /// (x, height, y), the glTF convention (Blender converts it on import)
+    Y,
+    /// (x, -y, height), for engines that wants Z up
+    Z,
+}
+
+impl UpAxis {
+    fn convert(self, v: Vec3) -> Vec3 {
+        match self {
+            UpAxis::Y => v,
+            UpAxis::Z => Vec3::new(v.x, -v.z, v.y),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SamplerWrap {
+    Repeat,
+    ClampToEdge,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerConfig {
+    pub filter: SamplerFilter,
+    pub wrap_s: SamplerWrap,
+    pub wrap_t: SamplerWrap,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            filter: SamplerFilter::Nearest,
+            wrap_s: SamplerWrap::Repeat,
+            wrap_t: SamplerWrap::Repeat,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaMode {
+    Opaque,
+    /// Pixels with an alpha below the cutoff are discarded
+    Mask(f32),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct GltfSampler {
@@ -46,6 +107,10 @@ struct GltfTexture {
 struct GltfAsset {
     generator: String,
     version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    copyright: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extras: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -87,21 +152,43 @@ struct GltfPbr {
     roughness_factor: f32,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfMaterialUnlit {}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GltfMaterialExtensions {
+    #[serde(rename = "KHR_materials_unlit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unlit: Option<GltfMaterialUnlit>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct GltfMaterial {
     name: String,
     double_sided: bool,
     pbr_metallic_roughness: GltfPbr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emissive_factor: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emissive_texture: Option<GltfTextureInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alpha_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alpha_cutoff: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extensions: Option<GltfMaterialExtensions>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct GltfPrimitive {
     mode: usize,
-    attributes: HashMap<String, usize>,
+    attributes: BTreeMap<String, usize>,
     indices: usize,
     material: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extras: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -116,6 +203,10 @@ struct GltfMesh {
 struct GltfNode {
     name: String,
     mesh: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matrix: Option<[f32; 16]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extras: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -136,6 +227,8 @@ struct GltfImage {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct GltfJson {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extensions_used: Vec<String>,
     accessors: Vec<GltfAccessor>,
     asset: GltfAsset,
     buffer_views: Vec<GltfBufferView>,
@@ -159,6 +252,7 @@ enum DataTyp {
 }
 
 pub struct Gltf {
+    extensions_used: Vec<String>,
     data_buffer: Vec<u8>,
     buffer_views: Vec<GltfBufferView>,
     materials: Vec<GltfMaterial>,
@@ -169,11 +263,20 @@ pub struct Gltf {
     images: Vec<GltfImage>,
     meshes: Vec<GltfMesh>,
     nodes: Vec<GltfNode>,
+    asset: GltfAsset,
+    up_axis: UpAxis,
+}
+
+impl Default for Gltf {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Gltf {
     pub fn new() -> Self {
         Self {
+            extensions_used: Vec::new(),
             data_buffer: Vec::new(),
             buffer_views: Vec::new(),
             materials: Vec::new(),
@@ -184,22 +287,73 @@ impl Gltf {
             images: Vec::new(),
             meshes: Vec::new(),
             nodes: Vec::new(),
+            asset: GltfAsset {
+                generator: format!(
+                    "{} {}",
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION")
+                ),
+                version: "2.0".to_string(),
+                copyright: None,
+                extras: None,
+            },
+            up_axis: UpAxis::Y,
         }
     }
 
-    pub fn create_sampler(&mut self, name: String) -> SamplerId {
+    /// Replaces the generator of the asset, the default is the name and
+    /// the version of the crate
+    pub fn set_generator(&mut self, generator: String) {
+        self.asset.generator = generator;
+    }
+
+    pub fn set_copyright(&mut self, copyright: String) {
+        self.asset.copyright = Some(copyright);
+    }
+
+    /// The extras of the asset, used for where the glTF came from (the WAD
+    /// and the map)
+    pub fn set_asset_extras(&mut self, extras: serde_json::Value) {
+        self.asset.extras = Some(extras);
+    }
+
+    /// Sets the up axis used by the positions and normals of the mesh
+    /// primitives added after this
+    pub fn set_up_axis(&mut self, up_axis: UpAxis) {
+        self.up_axis = up_axis;
+    }
+
+    pub fn create_sampler(
+        &mut self,
+        name: String,
+        config: SamplerConfig,
+    ) -> SamplerId {
         let id = self.samplers.len();
 
-        const NEAREST: usize = 9984;
+        const NEAREST: usize = 9728;
+        const LINEAR: usize = 9729;
+        const NEAREST_MIPMAP_NEAREST: usize = 9984;
+        const LINEAR_MIPMAP_LINEAR: usize = 9987;
 
+        const CLAMP_TO_EDGE: usize = 33071;
         const REPEAT: usize = 10497;
 
+        let (mag_filter, min_filter) = match config.filter {
+            SamplerFilter::Nearest => (NEAREST, NEAREST_MIPMAP_NEAREST),
+            SamplerFilter::Linear => (LINEAR, LINEAR_MIPMAP_LINEAR),
+        };
+
+        let wrap = |wrap| match wrap {
+            SamplerWrap::Repeat => REPEAT,
+            SamplerWrap::ClampToEdge => CLAMP_TO_EDGE,
+        };
+
         let sampler = GltfSampler {
             name,
-            mag_filter: NEAREST,
-            min_filter: NEAREST,
-            wrap_s: REPEAT,
-            wrap_t: REPEAT,
+            mag_filter,
+            min_filter,
+            wrap_s: wrap(config.wrap_s),
+            wrap_t: wrap(config.wrap_t),
         };
 
         self.samplers.push(sampler);
@@ -261,12 +415,67 @@ impl Gltf {
                 metallic_factor: 0.0,
                 roughness_factor: 1.0,
             },
+            emissive_factor: None,
+            emissive_texture: None,
+            alpha_mode: None,
+            alpha_cutoff: None,
+            extensions: None,
         };
 
         self.materials.push(material);
         id
     }
 
+    /// Creates a material without lighting (KHR_materials_unlit), the color
+    /// is multiplied with the vertex colors
+    pub fn create_unlit_material(
+        &mut self,
+        name: String,
+        color: Vec4,
+    ) -> MaterialId {
+        const EXTENSION_NAME: &str = "KHR_materials_unlit";
+
+        if !self.extensions_used.iter().any(|e| e == EXTENSION_NAME) {
+            self.extensions_used.push(EXTENSION_NAME.to_string());
+        }
+
+        let id = self.create_material(name, color, None);
+        self.materials[id].extensions = Some(GltfMaterialExtensions {
+            unlit: Some(GltfMaterialUnlit {}),
+        });
+
+        id
+    }
+
+    /// Makes the material emit light, used for surfaces that should be
+    /// drawn at full brightness
+    pub fn set_material_emissive(
+        &mut self,
+        material_id: MaterialId,
+        factor: Vec3,
+        texture: Option<GltfTextureInfo>,
+    ) {
+        let material = &mut self.materials[material_id];
+        material.emissive_factor = Some([factor.x, factor.y, factor.z]);
+        material.emissive_texture = texture;
+    }
+
+    pub fn set_material_alpha_mode(
+        &mut self,
+        material_id: MaterialId,
+        alpha_mode: AlphaMode,
+    ) {
+        // NOTE: OPAQUE is the default in glTF so skip writing it
+        let (mode, cutoff) = match alpha_mode {
+            AlphaMode::Opaque => (None, None),
+            AlphaMode::Mask(cutoff) => (Some("MASK"), Some(cutoff)),
+        };
+
+        let material = &mut self.materials[material_id];
+        material.alpha_mode = mode.map(|m| m.to_string());
+        material.alpha_cutoff = cutoff;
+    }
+
     pub fn create_mesh(&mut self, name: String) -> MeshId {
         let id = self.meshes.len();
         let mesh = GltfMesh {
@@ -298,6 +507,7 @@ impl Gltf {
         let start = self.data_buffer.len();
 
         for vertex in vertices {
+            let vertex = self.up_axis.convert(*vertex);
             let x = vertex.x / 20.0;
             let y = vertex.y / 20.0;
             let z = vertex.z / 20.0;
@@ -318,6 +528,7 @@ impl Gltf {
         let start = self.data_buffer.len();
 
         for normal in normals {
+            let normal = self.up_axis.convert(*normal);
             self.data_buffer.extend_from_slice(&normal.x.to_le_bytes());
             self.data_buffer.extend_from_slice(&normal.y.to_le_bytes());
             self.data_buffer.extend_from_slice(&normal.z.to_le_bytes());
@@ -408,11 +619,14 @@ impl Gltf {
         id
     }
 
+    /// The extras are application specific data about the primitive that
+    /// viewers can show (the surface and the texture of it)
     pub fn add_mesh_primitive(
         &mut self,
         mesh_id: MeshId,
         mesh: &Mesh,
         material_id: MaterialId,
+        extras: Option<serde_json::Value>,
     ) {
         let pos = mesh
             .vertex_buffer
@@ -447,17 +661,26 @@ impl Gltf {
         let uv_buffer_view =
             self.create_accessor(uv_buffer_view, uvs.len(), DataTyp::Vec2f);
 
-        let colors = mesh
-            .vertex_buffer
-            .iter()
-            .map(|v| v.color)
-            .collect::<Vec<Vec4>>();
-        let color_buffer_view = self.add_color_buffer(&colors);
-        let color_buffer_access = self.create_accessor(
-            color_buffer_view,
-            colors.len(),
-            DataTyp::Vec4f,
-        );
+        // NOTE: Meshes without vertex colors renders as white so skip the
+        // color buffer when all the colors are white
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        let has_colors = mesh.vertex_buffer.iter().any(|v| v.color != white);
+
+        let color_buffer_access = if has_colors {
+            let colors = mesh
+                .vertex_buffer
+                .iter()
+                .map(|v| v.color)
+                .collect::<Vec<Vec4>>();
+            let color_buffer_view = self.add_color_buffer(&colors);
+            Some(self.create_accessor(
+                color_buffer_view,
+                colors.len(),
+                DataTyp::Vec4f,
+            ))
+        } else {
+            None
+        };
 
         let index_buffer_view = self.add_index_buffer(&mesh.index_buffer);
         let index_buffer_access = self.create_accessor(
@@ -466,27 +689,47 @@ impl Gltf {
             DataTyp::Uint32,
         );
 
-        let mut attributes = HashMap::new();
+        let mut attributes = BTreeMap::new();
         attributes.insert("POSITION".to_string(), vertex_buffer_access);
         attributes.insert("NORMAL".to_string(), normal_buffer_access);
         attributes.insert("TEXCOORD_0".to_string(), uv_buffer_view);
-        attributes.insert("COLOR_0".to_string(), color_buffer_access);
+        if let Some(color_buffer_access) = color_buffer_access {
+            attributes.insert("COLOR_0".to_string(), color_buffer_access);
+        }
 
         let primitive = GltfPrimitive {
             mode: 4,
             attributes,
             indices: index_buffer_access,
             material: material_id,
+            extras,
         };
 
         self.meshes[mesh_id].primitives.push(primitive);
     }
 
-    pub fn create_node(&mut self, name: String, mesh_id: MeshId) -> NodeId {
+    /// The translation of the transform is in glTF units (the vertices of
+    /// the meshes are already scaled down), the extras are application
+    /// specific data that importers keeps around
+    pub fn create_node(
+        &mut self,
+        name: String,
+        mesh_id: MeshId,
+        transform: Option<Mat4>,
+        extras: Option<serde_json::Value>,
+    ) -> NodeId {
         let id = self.nodes.len();
+
+        // NOTE: Identity is the default in glTF so skip writing it
+        let matrix = transform
+            .filter(|t| !t.is_identity())
+            .map(|t| t.to_cols_array());
+
         let node = GltfNode {
             name,
             mesh: mesh_id,
+            matrix,
+            extras,
         };
 
         self.nodes.push(node);
@@ -508,19 +751,186 @@ impl Gltf {
         self.scenes[scene_id].nodes.push(node_id);
     }
 
+    /// Checks that all the indices between the glTF objects points to
+    /// objects that exists and that the accessors fits inside the buffer
+    /// views, returns all the problems found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        for (index, view) in self.buffer_views.iter().enumerate() {
+            if view.byte_offset + view.byte_length > self.data_buffer.len() {
+                problems.push(format!(
+                    "Buffer view {} is outside of the buffer",
+                    index
+                ));
+            }
+        }
+
+        for (index, accessor) in self.accessors.iter().enumerate() {
+            let view = match self.buffer_views.get(accessor.buffer_view) {
+                Some(view) => view,
+                None => {
+                    problems.push(format!(
+                        "Accessor {} uses the missing buffer view {}",
+                        index, accessor.buffer_view
+                    ));
+                    continue;
+                }
+            };
+
+            // NOTE: All the components are 4 bytes (u32 or f32)
+            let components = match accessor.typ.as_str() {
+                "SCALAR" => 1,
+                "VEC2" => 2,
+                "VEC3" => 3,
+                "VEC4" => 4,
+                _ => {
+                    problems.push(format!(
+                        "Accessor {} has the unknown type '{}'",
+                        index, accessor.typ
+                    ));
+                    continue;
+                }
+            };
+
+            if accessor.count * components * 4 != view.byte_length {
+                problems.push(format!(
+                    "Accessor {} doesn't match the size of buffer view {}",
+                    index, accessor.buffer_view
+                ));
+            }
+        }
+
+        for (index, image) in self.images.iter().enumerate() {
+            if image.buffer_view >= self.buffer_views.len() {
+                problems.push(format!(
+                    "Image {} uses the missing buffer view {}",
+                    index, image.buffer_view
+                ));
+            }
+        }
+
+        for (index, texture) in self.textures.iter().enumerate() {
+            if texture.sampler >= self.samplers.len() {
+                problems.push(format!(
+                    "Texture {} uses the missing sampler {}",
+                    index, texture.sampler
+                ));
+            }
+
+            if texture.source >= self.images.len() {
+                problems.push(format!(
+                    "Texture {} uses the missing image {}",
+                    index, texture.source
+                ));
+            }
+        }
+
+        for material in &self.materials {
+            let textures = [
+                &material.pbr_metallic_roughness.base_color_texture,
+                &material.emissive_texture,
+            ];
+
+            for info in textures.into_iter().flatten() {
+                if info.index >= self.textures.len() {
+                    problems.push(format!(
+                        "Material '{}' uses the missing texture {}",
+                        material.name, info.index
+                    ));
+                }
+            }
+        }
+
+        for mesh in &self.meshes {
+            for primitive in &mesh.primitives {
+                if primitive.material >= self.materials.len() {
+                    problems.push(format!(
+                        "Mesh '{}' uses the missing material {}",
+                        mesh.name, primitive.material
+                    ));
+                }
+
+                if primitive.indices >= self.accessors.len() {
+                    problems.push(format!(
+                        "Mesh '{}' uses the missing accessor {}",
+                        mesh.name, primitive.indices
+                    ));
+                }
+
+                let mut vertex_count = None;
+                for (name, &accessor) in &primitive.attributes {
+                    let count = match self.accessors.get(accessor) {
+                        Some(accessor) => accessor.count,
+                        None => {
+                            problems.push(format!(
+                                "Mesh '{}' uses the missing accessor {} for \
+                                 {}",
+                                mesh.name, accessor, name
+                            ));
+                            continue;
+                        }
+                    };
+
+                    if *vertex_count.get_or_insert(count) != count {
+                        problems.push(format!(
+                            "Mesh '{}' has attributes with different counts",
+                            mesh.name
+                        ));
+                    }
+                }
+            }
+        }
+
+        for node in &self.nodes {
+            if node.mesh >= self.meshes.len() {
+                problems.push(format!(
+                    "Node '{}' uses the missing mesh {}",
+                    node.name, node.mesh
+                ));
+            }
+        }
+
+        for scene in &self.scenes {
+            for &node in &scene.nodes {
+                if node >= self.nodes.len() {
+                    problems.push(format!(
+                        "Scene '{}' uses the missing node {}",
+                        scene.name, node
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Same as write_to but returns the GLB as bytes
     pub fn write_model(self) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.write_to(&mut result)
+            .expect("Writing to a Vec should never fail");
+        result
+    }
+
+    /// Writes the model as a GLB to the writer, the buffer is written
+    /// directly from the data buffer without copying it
+    pub fn write_to<W>(self, mut writer: W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
         let buffer = GltfBuffer {
             byte_length: self.data_buffer.len(),
         };
 
-        let asset = GltfAsset {
-            generator: "Testing".to_string(),
-            version: "2.0".to_string(),
-        };
-
         let gltf_json = GltfJson {
+            extensions_used: self.extensions_used,
             accessors: self.accessors,
-            asset,
+            asset: self.asset,
             buffer_views: self.buffer_views,
             buffers: vec![buffer],
             materials: self.materials,
@@ -537,34 +947,271 @@ impl Gltf {
         // println!("{}", text);
 
         let mut text = serde_json::to_string(&gltf_json).unwrap();
-        // TODO(patrik): Fix?
-        let padding = text.as_bytes().len() % 4;
-        for _ in 0..(4 - padding) {
-            text.push(' ');
-        }
+        pad_json(&mut text);
+
+        let json = text.as_bytes();
+
+        // NOTE: The length of the chunk needs to be a multiple of 4, the
+        // buffer itself keeps the unpadded length
+        let data = &self.data_buffer;
+        let data_padding = data.len().next_multiple_of(4) - data.len();
+        let data_chunk_size = data.len() + data_padding;
 
-        assert_eq!(text.len(), text.as_bytes().len());
+        // NOTE: The header is 12 bytes and the chunk headers 8 bytes each
+        let total_size = 12 + 8 + json.len() + 8 + data_chunk_size;
 
-        let mut bin_buffer: Vec<u8> = Vec::new();
-        bin_buffer.extend_from_slice(&0x46546c67u32.to_le_bytes());
-        bin_buffer.extend_from_slice(&2u32.to_le_bytes());
-        bin_buffer.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_all(&0x46546c67u32.to_le_bytes())?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(total_size as u32).to_le_bytes())?;
 
         // JSON Chunk
-        let data = text.as_bytes();
-        bin_buffer.extend_from_slice(&(data.len() as u32).to_le_bytes());
-        bin_buffer.extend_from_slice(&0x4e4f534au32.to_le_bytes());
-        bin_buffer.extend_from_slice(data);
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(&0x4e4f534au32.to_le_bytes())?;
+        writer.write_all(json)?;
 
         // Binary Buffer Chunk
-        bin_buffer
-            .extend_from_slice(&(self.data_buffer.len() as u32).to_le_bytes());
-        bin_buffer.extend_from_slice(&0x004e4942u32.to_le_bytes());
-        bin_buffer.extend_from_slice(&self.data_buffer);
+        writer.write_all(&(data_chunk_size as u32).to_le_bytes())?;
+        writer.write_all(&0x004e4942u32.to_le_bytes())?;
+        writer.write_all(data)?;
+        writer.write_all(&[0u8; 3][..data_padding])?;
+
+        writer.flush()
+    }
+}
+
+/// Pads the text of the JSON chunk with spaces to a multiple of 4, text
+/// that already is a multiple of 4 is left as it is
+fn pad_json(text: &mut String) {
+    let padding = (4 - text.len() % 4) % 4;
+    for _ in 0..padding {
+        text.push(' ');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polygon::Vertex;
+    use crate::testing;
+
+    #[test]
+    fn node_matrix_is_written_for_transforms() {
+        let mut gltf = Gltf::new();
+        let mesh_id = gltf.create_mesh("Mesh".to_string());
+
+        let transform = Mat4::translation(Vec3::new(1.0, 2.0, 3.0));
+        gltf.create_node("Moved".to_string(), mesh_id, Some(transform), None);
+        gltf.create_node(
+            "Identity".to_string(),
+            mesh_id,
+            Some(Mat4::identity()),
+            None,
+        );
+        gltf.create_node("None".to_string(), mesh_id, None, None);
+
+        let json = testing::glb_json(&gltf.write_model());
+        let nodes = json["nodes"].as_array().unwrap();
+
+        let matrix = nodes[0]["matrix"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_f64().unwrap() as f32)
+            .collect::<Vec<_>>();
+        assert_eq!(matrix, transform.to_cols_array());
+        assert!(nodes[1].get("matrix").is_none());
+        assert!(nodes[2].get("matrix").is_none());
+    }
+
+    #[test]
+    fn sampler_filter_constants() {
+        let mut gltf = Gltf::new();
+        gltf.create_sampler("Nearest".to_string(), SamplerConfig::default());
+        gltf.create_sampler(
+            "Linear".to_string(),
+            SamplerConfig {
+                filter: SamplerFilter::Linear,
+                wrap_s: SamplerWrap::ClampToEdge,
+                wrap_t: SamplerWrap::Repeat,
+            },
+        );
+
+        let json = testing::glb_json(&gltf.write_model());
+        let samplers = json["samplers"].as_array().unwrap();
+        let values = |sampler: &serde_json::Value| {
+            ["magFilter", "minFilter", "wrapS", "wrapT"]
+                .map(|key| sampler[key].as_u64().unwrap())
+        };
+        assert_eq!(values(&samplers[0]), [9728, 9984, 10497, 10497]);
+        assert_eq!(values(&samplers[1]), [9729, 9987, 33071, 10497]);
+    }
+
+    #[test]
+    fn chunk_lengths_are_multiples_of_4() {
+        // NOTE: 5 bytes of image data so the buffer needs padding
+        let mut gltf = Gltf::new();
+        gltf.create_image("Image".to_string(), &[1, 2, 3, 4, 5]);
+        let glb = gltf.write_model();
+
+        let total_size = u32::from_le_bytes(glb[8..12].try_into().unwrap());
+        assert_eq!(total_size as usize, glb.len());
+
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap());
+        let json_length = json_length as usize;
+        assert_eq!(json_length % 4, 0);
+
+        let bin = 20 + json_length;
+        let bin_length =
+            u32::from_le_bytes(glb[bin..bin + 4].try_into().unwrap());
+        let bin_length = bin_length as usize;
+        assert_eq!(bin_length, 8);
+        assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(&glb[bin + 8..], [1, 2, 3, 4, 5, 0, 0, 0]);
+
+        // NOTE: The buffer keeps the length without the padding
+        let json = testing::glb_json(&glb);
+        assert_eq!(json["buffers"][0]["byteLength"], 5);
+    }
+
+    #[test]
+    fn json_padding() {
+        for (length, padded) in [(4, 4), (5, 8), (6, 8), (7, 8), (8, 8)] {
+            let mut text = "a".repeat(length);
+            pad_json(&mut text);
+            assert_eq!(text.len(), padded);
+            assert_eq!(text.trim_end(), "a".repeat(length));
+        }
+    }
+
+    #[test]
+    fn write_to_a_cursor() {
+        let mut gltf = Gltf::new();
+        let mesh_id = gltf.create_mesh("Mesh".to_string());
+        gltf.create_node("Node".to_string(), mesh_id, None, None);
+        gltf.create_image("Image".to_string(), &[1, 2, 3]);
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        gltf.write_to(&mut cursor).unwrap();
+        let glb = cursor.into_inner();
+
+        let word = |offset: usize| {
+            u32::from_le_bytes(glb[offset..offset + 4].try_into().unwrap())
+        };
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8) as usize, glb.len());
+
+        let json = testing::glb_json(&glb);
+        assert_eq!(json["nodes"][0]["name"], "Node");
+        assert_eq!(json["images"][0]["name"], "Image");
+    }
+
+    fn triangle(color: Vec4) -> Mesh {
+        let mut mesh = Mesh::new();
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let verts = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0)].map(|(x, z)| {
+            Vertex::new(Vec3::new(x, 0.0, z), normal, Vec2::default(), color)
+        });
+        mesh.add_vertices(&verts, true);
+        mesh
+    }
 
-        let total_size = bin_buffer.len() as u32;
-        bin_buffer[8..12].copy_from_slice(&total_size.to_le_bytes());
+    #[test]
+    fn white_mesh_has_no_colors() {
+        let mut gltf = Gltf::new();
+        let material_id = gltf.create_material(
+            "Material".to_string(),
+            Vec4::new(1.0, 1.0, 1.0, 1.0),
+            None,
+        );
+        let mesh_id = gltf.create_mesh("Mesh".to_string());
+        let white = triangle(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        gltf.add_mesh_primitive(mesh_id, &white, material_id, None);
+        let red = triangle(Vec4::new(1.0, 0.0, 0.0, 1.0));
+        gltf.add_mesh_primitive(mesh_id, &red, material_id, None);
+
+        let json = testing::glb_json(&gltf.write_model());
+        let primitives = json["meshes"][0]["primitives"].as_array().unwrap();
+        assert!(primitives[0]["attributes"].get("COLOR_0").is_none());
+        assert!(primitives[1]["attributes"].get("COLOR_0").is_some());
+
+        // NOTE: POSITION, NORMAL, TEXCOORD_0 and the indices of both and
+        // COLOR_0 of the red triangle
+        assert_eq!(json["accessors"].as_array().unwrap().len(), 9);
+    }
+
+    /// The first vec3 of the attribute of the first primitive
+    fn first_vec3(glb: &[u8], attribute: &str) -> [f32; 3] {
+        let json = testing::glb_json(glb);
+        let accessor =
+            &json["meshes"][0]["primitives"][0]["attributes"][attribute];
+        let view = json["accessors"][accessor.as_u64().unwrap() as usize]
+            ["bufferView"]
+            .as_u64()
+            .unwrap() as usize;
+        let offset = json["bufferViews"][view]["byteOffset"].as_u64().unwrap();
+
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap());
+        let start = 20 + json_length as usize + 8 + offset as usize;
+        [0, 1, 2].map(|i| {
+            let at = start + i * 4;
+            f32::from_le_bytes(glb[at..at + 4].try_into().unwrap())
+        })
+    }
+
+    #[test]
+    fn y_up_and_z_up_coordinates() {
+        // NOTE: The map point (20, 40) at the height 60
+        let mut mesh = Mesh::new();
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let color = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        let verts =
+            [(20.0, 40.0), (20.0, 60.0), (40.0, 60.0)].map(|(x, z)| {
+                Vertex::new(
+                    Vec3::new(x, 60.0, z),
+                    normal,
+                    Vec2::default(),
+                    color,
+                )
+            });
+        mesh.add_vertices(&verts, true);
+
+        let glb = |up_axis| {
+            let mut gltf = Gltf::new();
+            gltf.set_up_axis(up_axis);
+            let material_id =
+                gltf.create_material("Material".to_string(), color, None);
+            let mesh_id = gltf.create_mesh("Mesh".to_string());
+            gltf.add_mesh_primitive(mesh_id, &mesh, material_id, None);
+            gltf.write_model()
+        };
+
+        // NOTE: The positions are divided by 20
+        let y_up = glb(UpAxis::Y);
+        assert_eq!(first_vec3(&y_up, "POSITION"), [1.0, 3.0, 2.0]);
+        assert_eq!(first_vec3(&y_up, "NORMAL"), [0.0, 1.0, 0.0]);
+
+        let z_up = glb(UpAxis::Z);
+        assert_eq!(first_vec3(&z_up, "POSITION"), [1.0, -2.0, 3.0]);
+        assert_eq!(first_vec3(&z_up, "NORMAL"), [0.0, 0.0, 1.0]);
+    }
 
-        bin_buffer
+    #[test]
+    fn asset_generator_and_metadata() {
+        let json = testing::glb_json(&Gltf::new().write_model());
+        let generator = format!("wad_reader {}", env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["asset"]["generator"], generator);
+        assert_eq!(json["asset"]["version"], "2.0");
+        assert!(json["asset"].get("copyright").is_none());
+        assert!(json["asset"].get("extras").is_none());
+
+        let mut gltf = Gltf::new();
+        gltf.set_generator("Tool".to_string());
+        gltf.set_copyright("Someone".to_string());
+        gltf.set_asset_extras(serde_json::json!({ "map": "E1M1" }));
+        let json = testing::glb_json(&gltf.write_model());
+        assert_eq!(json["asset"]["generator"], "Tool");
+        assert_eq!(json["asset"]["copyright"], "Someone");
+        assert_eq!(json["asset"]["extras"]["map"], "E1M1");
     }
 }
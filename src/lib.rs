@@ -0,0 +1,65 @@
+//! Library to read DOOM WAD files and convert the maps to 3D models
+//!
+//! The wad_reader binary is a command line interface over this library,
+//! the conversion goes through the same steps:
+//!
+//! - [`wad::Wad`] parses the lumps of the WAD file and [`wad::Map`] parses
+//!   a map inside of it
+//! - [`texture::TextureLoader`] decodes the flats, patches and textures
+//!   with the palette and the color map of the WAD
+//! - [`convert::Map::gen_map`] generates the meshes of the floors, ceilings
+//!   and walls with [`gen`]
+//! - [`convert`] writes the meshes as glTF, OBJ or MIME
+//!
+//! ```
+//! use wad_reader::convert::{self, GltfOptions};
+//! use wad_reader::texture::{self, TextureLoader};
+//! use wad_reader::wad::{self, Wad};
+//! use wad_reader::gen;
+//!
+//! # let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/two_rooms.wad");
+//! # let output_dir = std::env::temp_dir();
+//! let data = std::fs::read(path)?;
+//! let wad = Wad::parse(&data)?;
+//!
+//! let palettes = texture::read_all_palettes(&wad)?;
+//! let color_maps = texture::read_all_color_maps(&wad)?;
+//! let texture_loader = TextureLoader::new(
+//!     &wad,
+//!     color_maps[0].clone(),
+//!     palettes[0].clone(),
+//! )?;
+//!
+//! let wad_map = wad::Map::parse_from_wad(&wad, "E1M1")?;
+//! let mut context =
+//!     gen::Context::new(texture_loader, gen::GenOptions::default());
+//! let map = convert::Map::gen_map(&mut context, &wad_map, None);
+//!
+//! let (gltf, _) = convert::build_map_gltf(
+//!     &context,
+//!     &wad_map,
+//!     map,
+//!     None,
+//!     &GltfOptions::default(),
+//! )?;
+//! std::fs::write(output_dir.join("E1M1.glb"), gltf.write_model())?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod animation;
+pub mod atlas;
+pub mod convert;
+pub mod gen;
+pub mod gltf;
+pub mod math;
+pub mod mime;
+pub mod obj;
+pub mod polygon;
+#[cfg(test)]
+mod testing;
+pub mod texture;
+pub mod udmf;
+pub(crate) mod util;
+pub mod wad;
+
+pub use util::{endoom_to_ansi, glob_match};
@@ -1,29 +1,77 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::time::Instant;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use wad_reader::{animation, atlas, convert, gen, gltf, math, obj, texture, wad};
 
 use wad::Wad;
-use math::Vec4;
-use polygon::{Quad, Mesh};
-use texture::TextureLoader;
-use gltf::{Gltf, GltfTextureInfo};
-
-/// TODO(patrik):
-///   - Lazy loading textures
-///   - Debug Dumping Textures
-///   - Add Debug Flags
-///     - View Slopes
-///     - View Normals
-///     - View UVs
-///
-mod gen;
-mod gltf;
-mod math;
-mod polygon;
-mod texture;
-mod util;
-mod wad;
+use atlas::Atlas;
+use math::Vec3;
+use texture::{TextureLoader, TextureTyp};
+use gltf::{SamplerFilter, UpAxis};
+use convert::{
+    GltfOptions, Map, MeshOptions, NodeNaming, Normals, SplitBy,
+    build_map_gltf, build_map_mime, build_map_obj, write_map_gltf,
+    write_map_gltf_split, write_map_mime, write_map_obj,
+};
+
+// TODO(patrik):
+//   - Lazy loading textures
+//   - Debug Dumping Textures
+//
+
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+enum OutputFormat {
+    /// Binary glTF (.glb)
+    Gltf,
+    /// The custom MIME map format (.mime)
+    Mime,
+    /// Wavefront OBJ (.obj) with the materials in a .mtl file and the
+    /// textures as PNGs next to it
+    Obj,
+}
+
+/// The texture types that --dump-type can select
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+enum DumpType {
+    Flat,
+    Patch,
+    Texture,
+}
+
+impl From<DumpType> for TextureTyp {
+    fn from(typ: DumpType) -> Self {
+        match typ {
+            DumpType::Flat => TextureTyp::Flat,
+            DumpType::Patch => TextureTyp::Patch,
+            DumpType::Texture => TextureTyp::Texture,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, PartialEq, Debug)]
+enum Recenter {
+    /// Center all the axes, the middle of the map is at the origin
+    All,
+    /// Only center the map horizontally so the floors keeps their heights
+    Horizontal,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List all the lumps and maps inside the WAD without converting
+    List,
+    /// Write the raw bytes of a lump to a file or to stdout
+    Extract {
+        /// The name of the lump (the first lump with the name is used)
+        lump: String,
+
+        /// Write the lump to <FILE> instead of stdout
+        #[clap(long)]
+        file: Option<String>,
+    },
+}
 
 /// TODO Update commenets
 #[derive(Parser, Debug)]
@@ -32,9 +80,52 @@ struct Args {
     /// The WAD file to convert
     wad_file: String,
 
+    /// The IWAD to load the WAD file on top of, needed for PWADs that uses
+    /// the palette, patches, flats or textures of the game. The lumps of the
+    /// WAD file replaces the lumps with the same name in the IWAD, the
+    /// patches and flats of both are used
+    #[clap(long)]
+    iwad: Option<String>,
+
     #[clap(long)]
     dump_textures: bool,
 
+    /// Only dump the textures with names matching the glob pattern
+    /// (example SKY*)
+    #[clap(long)]
+    dump_filter: Option<String>,
+
+    /// Only dump the textures of the type
+    #[clap(value_enum, long)]
+    dump_type: Option<DumpType>,
+
+    /// Write the DEHACKED lump of the WAD to the output directory
+    #[clap(long)]
+    dump_dehacked: bool,
+
+    /// Print the ENDOOM screen of the WAD (with ANSI colors)
+    #[clap(long)]
+    endoom: bool,
+
+    /// Write the colors of the palette (0 to 13 in DOOM) as a 16x16 image
+    /// to the output directory
+    #[clap(long)]
+    palette_image: Option<usize>,
+
+    /// Downscale the textures wider or taller then the size (in pixels)
+    /// when they are written, no limit by default
+    #[clap(long)]
+    max_texture_size: Option<usize>,
+
+    /// Pack all the textures into a single texture atlas
+    #[clap(long)]
+    atlas: bool,
+
+    /// Size of the gutter around each texture in the atlas, the gutter
+    /// repeats the edge pixels to stop the textures bleeding into each other
+    #[clap(long, default_value_t = 0)]
+    atlas_padding: usize,
+
     /// Which map to convert (example E1M1)
     #[clap(short, long)]
     map: Option<String>,
@@ -42,180 +133,255 @@ struct Args {
     /// Write output file to <OUTPUT>
     #[clap(value_parser, short, long)]
     output_dir: Option<String>,
-}
 
-struct Sector {
-    floor_mesh: Mesh,
-    ceiling_mesh: Mesh,
-    wall_quads: Vec<Quad>,
-    slope_quads: Vec<Quad>,
-}
+    /// The format of the output file
+    #[clap(value_enum, long, default_value_t = OutputFormat::Gltf)]
+    format: OutputFormat,
 
-impl Sector {
-    fn new(
-        floor_mesh: Mesh,
-        ceiling_mesh: Mesh,
-        wall_quads: Vec<Quad>,
-        slope_quads: Vec<Quad>,
-    ) -> Self {
-        Self {
-            floor_mesh,
-            ceiling_mesh,
-            wall_quads,
-            slope_quads,
-        }
-    }
+    /// Replace the textures with debug colors
+    #[clap(value_enum, long)]
+    debug: Option<gen::DebugMode>,
 
-    fn gen_sector(
-        context: &mut gen::Context,
-        wad_map: &wad::Map,
-        wad_sector: &wad::Sector,
-    ) -> Self {
-        let floor_mesh = gen::gen_floor(context, wad_map, wad_sector);
+    /// How the glTF nodes are named
+    #[clap(value_enum, long, default_value_t = NodeNaming::Collision)]
+    node_naming: NodeNaming,
 
-        let ceiling_mesh = gen::gen_ceiling(context, wad_map, wad_sector);
+    /// The texture filtering used by the glTF sampler
+    #[clap(value_enum, long, default_value_t = SamplerFilter::Nearest)]
+    filter: SamplerFilter,
 
-        let (wall_quads, slope_quads) =
-            gen::gen_walls(context, wad_map, wad_sector);
+    /// Write a glTF file for each sector or texture instead of one file for
+    /// the whole map, a json file lists the files
+    #[clap(value_enum, long)]
+    split_by: Option<SplitBy>,
 
-        Sector::new(floor_mesh, ceiling_mesh, wall_quads, slope_quads)
-    }
-}
+    /// The axis the height of the map is written to in the glTF output, y
+    /// writes (x, height, y) and z writes (x, -y, height) where x and y are
+    /// the map coordinates
+    #[clap(value_enum, long, default_value_t = UpAxis::Y)]
+    up: UpAxis,
 
-struct Map {
-    sectors: Vec<Sector>,
-}
-
-impl Map {
-    fn new(sectors: Vec<Sector>) -> Self {
-        Self { sectors }
-    }
+    /// Put the geometry of all the sectors under a single glTF node
+    #[clap(long)]
+    merge_sectors: bool,
 
-    fn gen_map(context: &mut gen::Context, wad_map: &wad::Map) -> Self {
-        let mut sectors = Vec::new();
+    /// Use a placeholder texture for surfaces with a missing texture instead
+    /// of skipping them
+    #[clap(long)]
+    show_missing: bool,
 
-        for wad_sector in &wad_map.sectors {
-            let map_sector = Sector::gen_sector(context, &wad_map, wad_sector);
+    /// Map the flats like wall textures with the uvs starting at 0 in the
+    /// top left corner of each sector, the flats still tiles every 64 units
+    #[clap(long)]
+    flat_to_texture: bool,
 
-            sectors.push(map_sector);
-        }
+    /// Only write the floors, ceilings and walls as untextured meshes under
+    /// "-colonly" nodes (the glTF has no textures or images), for engines
+    /// that imports the collision separately
+    #[clap(long)]
+    collision_only: bool,
 
-        Map::new(sectors)
-    }
-}
+    /// Write the surface (floor, ceiling, wall, slope or collision), sector
+    /// and texture of each glTF primitive to the extras of it
+    #[clap(long)]
+    primitive_extras: bool,
 
-fn write_map_gltf<P>(context: &gen::Context, map: Map, output_file: P)
-where
-    P: AsRef<Path>,
-{
-    let mut gltf = Gltf::new();
+    /// The generator written to the glTF asset, defaults to the name and
+    /// version of the converter
+    #[clap(long)]
+    generator: Option<String>,
 
-    let map_name = "E1M1";
+    /// The copyright written to the glTF asset
+    #[clap(long)]
+    copyright: Option<String>,
 
-    let scene_id = gltf.create_scene(map_name.to_string());
-    let texture_sampler = gltf.create_sampler("Default Sampler".to_string());
+    /// Move the map so the center of it is at the origin, --recenter
+    /// without a value centers all the axes
+    #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "all")]
+    recenter: Option<Recenter>,
 
-    let mut textures = HashMap::new();
-    for &texture_id in &context.texture_queue {
-        if let Some(texture) = context.texture_loader.load_from_id(texture_id)
-        {
-            let name =
-                context.texture_loader.get_name_from_id(texture_id).unwrap();
-            let png = util::write_texture_to_png(texture);
-            let image_id = gltf.create_image(name.clone(), &png);
-            let gltf_texture_id =
-                gltf.create_texture(name.clone(), texture_sampler, image_id);
-
-            textures.insert(texture_id, gltf_texture_id);
-        } else {
-            panic!("Failed to load texture: '{}'", texture_id);
-        }
-    }
+    /// Print what is being done and how long it takes
+    #[clap(long)]
+    verbose: bool,
 
-    for sector_index in 0..map.sectors.len() {
-        let sector = &map.sectors[sector_index];
+    /// Fail the conversion if any problems are found in the WAD
+    #[clap(long)]
+    strict: bool,
+
+    /// Parse the WAD, load the textures, generate the geometry and build
+    /// the selected format in memory without writing any files, the exit
+    /// code is 1 if anything fails or any warnings are found
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "dump_textures",
+            "dump_dehacked",
+            "palette_image",
+            "animations",
+        ]
+    )]
+    check: bool,
+
+    /// Merge the duplicated vertices of the meshes to make the output smaller
+    #[clap(long)]
+    weld: bool,
 
-        let mesh_id = gltf.create_mesh(format!("Sector #{}", sector_index));
+    /// How the normals are written, smooth also welds the vertices
+    #[clap(value_enum, long, default_value_t = Normals::Flat)]
+    normals: Normals,
 
-        let material_id = gltf.create_material(
-            format!("Sector #{} Floor", sector_index),
-            Vec4::new(1.0, 1.0, 1.0, 1.0),
-            Some(GltfTextureInfo::new(
-                textures[&sector.floor_mesh.texture_id.unwrap()],
-            )),
-        );
+    /// Write the frames of the animated textures used by the map and a json
+    /// file describing the animations
+    #[clap(long)]
+    animations: bool,
 
-        gltf.add_mesh_primitive(mesh_id, &sector.floor_mesh, material_id);
+    /// Textures that are drawn at full brightness (comma separated names)
+    #[clap(long, value_delimiter = ',')]
+    fullbright: Vec<String>,
 
-        let material_id = gltf.create_material(
-            format!("Sector #{} Ceiling", sector_index),
-            Vec4::new(1.0, 1.0, 1.0, 1.0),
-            Some(GltfTextureInfo::new(
-                textures[&sector.ceiling_mesh.texture_id.unwrap()],
-            )),
-        );
+    /// Only convert the sector with the index, useful together with --debug
+    /// to find problems with the geometry of a sector
+    #[clap(long)]
+    only_sector: Option<usize>,
 
-        gltf.add_mesh_primitive(mesh_id, &sector.ceiling_mesh, material_id);
+    /// Max number of threads used to decode the textures (needs the
+    /// parallel feature), defaults to the number of cores
+    #[clap(long)]
+    threads: Option<usize>,
 
-        let mut wall_meshes: HashMap<usize, Mesh> = HashMap::new();
-        for quad in &sector.wall_quads {
-            let mesh =
-                if let Some(mesh) = wall_meshes.get_mut(&quad.texture_id) {
-                    mesh
-                } else {
-                    wall_meshes.insert(quad.texture_id, Mesh::new());
-                    wall_meshes.get_mut(&quad.texture_id).unwrap()
-                };
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
 
-            mesh.add_vertices(&quad.points, false);
+/// Writes the data of the lump to the file or to stdout if there is no file
+fn extract_lump(wad: &Wad, lump: &str, file: Option<&str>) {
+    let data = wad.read_dir_by_name(lump).unwrap_or_else(|e| match e {
+        wad::Error::NoDirFound => {
+            exit_with_error(format!("The WAD has no lump named '{}'", lump))
         }
+        e => exit_with_error(format!("Failed to read '{}': {}", lump, e)),
+    });
 
-        for (texture_id, mesh) in wall_meshes {
-            let material_id = gltf.create_material(
-                format!("Sector #{} Walls Tex #{}", sector_index, texture_id),
-                Vec4::new(1.0, 1.0, 1.0, 1.0),
-                Some(GltfTextureInfo::new(textures[&texture_id])),
-                // None,
-            );
+    let result = if let Some(file) = file {
+        std::fs::write(file, data)
+    } else {
+        use std::io::Write;
 
-            gltf.add_mesh_primitive(mesh_id, &mesh, material_id);
-        }
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(data).and_then(|_| stdout.flush())
+    };
 
-        let node_id =
-            gltf.create_node(format!("Sector #{}-col", sector_index), mesh_id);
+    if let Err(e) = result {
+        exit_with_error(format!("Failed to write '{}': {}", lump, e));
+    }
+}
 
-        gltf.add_node_to_scene(scene_id, node_id);
+/// Prints one line about the WAD (IWAD or PWAD, the game and the checksum),
+/// one line for each lump (index, name, size) and one line for each map
+/// (name, format, GL node version, bounding box)
+fn list_wad(wad: &Wad) {
+    let kind = if wad.is_iwad() { "IWAD" } else { "PWAD" };
+    println!(
+        "wad  {} {:<12} {:016x}",
+        kind,
+        wad.iwad_name().unwrap_or("-"),
+        wad.checksum()
+    );
+
+    for index in 0..wad.num_dirs() {
+        let dir_entry = wad.read_dir_entry(index).expect("Failed to read dir");
+        let name = dir_entry.lump_name();
+        println!("lump {:>6} {:<8} {:>10}", index, name, dir_entry.size());
+    }
 
-        let slope_mesh_id =
-            gltf.create_mesh(format!("Sector #{}: Slope Mesh", sector_index));
+    let maps = wad.iter_maps().unwrap_or_else(|e| {
+        exit_with_error(format!("Failed to find the maps: {}", e))
+    });
+
+    for map in maps {
+        let format = match map.format {
+            wad::MapFormat::Doom => "doom",
+            wad::MapFormat::Hexen => "hexen",
+            wad::MapFormat::Udmf => "udmf",
+        };
+
+        let gl_nodes = match map.gl_node_version {
+            Some(wad::GlNodeVersion::V1) => "gl-v1",
+            Some(wad::GlNodeVersion::V2) => "gl-v2",
+            Some(wad::GlNodeVersion::V3) => "gl-v3",
+            Some(wad::GlNodeVersion::V5) => "gl-v5",
+            None => "no-gl-nodes",
+        };
+
+        let bounding_box = wad::Map::parse_from_wad(wad, &map.name)
+            .map(|map| {
+                let (min, max) = map.bounding_box();
+                format!("{} {} {} {}", min.x, min.y, max.x, max.y)
+            })
+            .unwrap_or_else(|_| "-".to_string());
+
+        println!(
+            "map  {:<8} {:<6} {:<11} {}",
+            map.name, format, gl_nodes, bounding_box
+        );
+    }
+}
 
-        let mut slope_mesh = Mesh::new();
-        for quad in &sector.slope_quads {
-            slope_mesh.add_vertices(&quad.points, false);
+/// Returns the name of the map, the map can be the name (E1M1) or the
+/// number of the map (1 is the first map in the WAD), a map named "1" is
+/// picked before the first map
+fn resolve_map_name(wad: &Wad, map: &str) -> String {
+    let maps = wad.iter_maps().unwrap_or_else(|e| {
+        exit_with_error(format!("Failed to find the maps: {}", e))
+    });
+
+    // NOTE: The maps of a PWAD loaded on top of an IWAD shows up twice,
+    // only the first one is used so it's only counted once
+    let mut names = Vec::<String>::new();
+    for entry in maps {
+        if !names.contains(&entry.name) {
+            names.push(entry.name);
         }
+    }
 
-        let material_id = gltf.create_material(
-            format!("Sector #{}: Slope Mesh", sector_index),
-            Vec4::new(1.0, 1.0, 1.0, 1.0),
-            None,
-        );
-
-        gltf.add_mesh_primitive(slope_mesh_id, &slope_mesh, material_id);
+    if names.iter().any(|name| name == map) {
+        return map.to_string();
+    }
 
-        let extra_node_id = gltf.create_node(
-            format!("Sector #{}: Slope Mesh-colonly", sector_index),
-            slope_mesh_id,
-        );
-        gltf.add_node_to_scene(scene_id, extra_node_id);
+    match map.parse::<usize>() {
+        Ok(number) if number >= 1 && number <= names.len() => {
+            names[number - 1].clone()
+        }
+        Ok(number) => exit_with_error(format!(
+            "Map number {} doesn't exist, the WAD has {} map(s)",
+            number,
+            names.len()
+        )),
+        // NOTE: Let the loading of the map report the missing name
+        Err(_) => map.to_string(),
     }
+}
 
-    let data = gltf.write_model();
-    util::write_binary_file(output_file, &data);
+/// The file name part of the path, the whole path if it has none
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path)
+        .to_string()
+}
+
+fn exit_with_error(message: String) -> ! {
+    eprintln!("Error: {}", message);
+    std::process::exit(1);
 }
 
 fn main() {
     let args = Args::parse();
-    println!("Args: {:?}", args);
+    // NOTE: stdout is kept clean for the output of the subcommands
+    if args.verbose {
+        eprintln!("Args: {:?}", args);
+    }
 
     let output_dir = if let Some(output_dir) = args.output_dir {
         PathBuf::from(output_dir)
@@ -223,17 +389,148 @@ fn main() {
         PathBuf::from(".")
     };
 
+    // NOTE: Everything verbose goes to stderr so stdout can be piped
+    let verbose = args.verbose;
+    let mut timer = Instant::now();
+    let mut phase_done = |name: &str| {
+        if verbose {
+            eprintln!("{}: {:.2?}", name, timer.elapsed());
+        }
+        timer = Instant::now();
+    };
+
+    if let Some(threads) = args.threads {
+        #[cfg(feature = "parallel")]
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+        {
+            exit_with_error(format!("Failed to create the threads: {}", e));
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        eprintln!(
+            "Warning: Ignoring --threads {}, built without the parallel \
+             feature",
+            threads
+        );
+    }
+
     // Read the raw wad file
-    let data = util::read_binary_file(args.wad_file);
+    let data = std::fs::read(&args.wad_file).unwrap_or_else(|e| {
+        exit_with_error(format!("Failed to read '{}': {}", args.wad_file, e))
+    });
     // Parse the wad
-    let wad = Wad::parse(&data).expect("Failed to parse WAD file");
+    let wad = Wad::parse(&data).unwrap_or_else(|e| {
+        exit_with_error(format!("Failed to parse '{}': {}", args.wad_file, e))
+    });
+    phase_done("Parsed WAD");
+
+    match &args.command {
+        Some(Command::List) => {
+            list_wad(&wad);
+            return;
+        }
+
+        Some(Command::Extract { lump, file }) => {
+            extract_lump(&wad, lump, file.as_deref());
+            return;
+        }
+
+        None => {}
+    }
+
+    // NOTE: The subcommands above only looks at the WAD file, the
+    // conversion sees the IWAD and the WAD file as one WAD
+    let iwad_data;
+    let stack;
+    let wad = if let Some(iwad_file) = args.iwad.as_ref() {
+        iwad_data = std::fs::read(iwad_file).unwrap_or_else(|e| {
+            exit_with_error(format!("Failed to read '{}': {}", iwad_file, e))
+        });
+        let iwad = Wad::parse(&iwad_data).unwrap_or_else(|e| {
+            exit_with_error(format!("Failed to parse '{}': {}", iwad_file, e))
+        });
+
+        stack = wad::WadStack::new(&iwad, &wad).unwrap_or_else(|e| {
+            exit_with_error(format!(
+                "Failed to load '{}' on top of '{}': {}",
+                args.wad_file, iwad_file, e
+            ))
+        });
+        stack.wad()
+    } else {
+        wad
+    };
+
+    if args.endoom {
+        let text = wad
+            .read_dir_by_name("ENDOOM")
+            .ok()
+            .and_then(wad_reader::endoom_to_ansi);
+        match text {
+            Some(text) => print!("{}", text),
+            None => eprintln!("Warning: The WAD has no valid ENDOOM lump"),
+        }
+    }
 
-    let palettes =
-        texture::read_all_palettes(&wad).expect("Failed to read palettes");
+    if !args.check {
+        if let Err(e) = std::fs::create_dir_all(&output_dir) {
+            exit_with_error(format!(
+                "Failed to create output directory '{}': {}",
+                output_dir.display(),
+                e
+            ));
+        }
+    }
+
+    // NOTE: PWADs usually doesn't have their own PLAYPAL and COLORMAP, they
+    // use the ones from the IWAD they are loaded together with
+    let palettes = texture::read_all_palettes(&wad).unwrap_or_else(|e| {
+        if let texture::Error::PaletteMissing = e {
+            exit_with_error(
+                "No PLAYPAL found, the palette is needed to convert the \
+                 textures (supply a base IWAD with --iwad)"
+                    .to_string(),
+            )
+        }
+        exit_with_error(format!("Failed to read the palettes: {}", e))
+    });
     let final_palette = &palettes[0];
 
-    let color_maps =
-        texture::read_all_color_maps(&wad).expect("Failed to read color maps");
+    if let Some(index) = args.palette_image {
+        let palette = palettes.get(index).unwrap_or_else(|| {
+            exit_with_error(format!(
+                "Palette {} doesn't exist, the WAD has palettes 0 to {}",
+                index,
+                palettes.len() - 1
+            ))
+        });
+
+        let mut path = output_dir.clone();
+        path.push(format!("PLAYPAL{}", index));
+        path.set_extension("png");
+
+        let data = palette.to_swatch().to_png();
+        if let Err(e) = std::fs::write(&path, data) {
+            exit_with_error(format!(
+                "Failed to write the palette image to '{}': {}",
+                path.display(),
+                e
+            ));
+        }
+    }
+
+    let color_maps = texture::read_all_color_maps(&wad).unwrap_or_else(|e| {
+        if let texture::Error::ColorMapMissing = e {
+            exit_with_error(
+                "No COLORMAP found, the color map is needed to convert the \
+                 textures (supply a base IWAD with --iwad)"
+                    .to_string(),
+            )
+        }
+        exit_with_error(format!("Failed to read the color maps: {}", e))
+    });
     let final_color_map = &color_maps[0];
 
     let texture_loader = TextureLoader::new(
@@ -241,18 +538,85 @@ fn main() {
         final_color_map.clone(),
         final_palette.clone(),
     )
-    .expect("Failed to create TextureLoader");
+    .unwrap_or_else(|e| {
+        let hint = if wad.is_iwad() {
+            ""
+        } else {
+            " (the WAD is a PWAD, supply a base IWAD with --iwad)"
+        };
+        exit_with_error(format!("Failed to load the textures: {}{}", e, hint))
+    });
+
+    for warning in texture_loader.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if verbose {
+        eprintln!(
+            "Loaded {} patches, {} flats and {} textures ({} in total)",
+            texture_loader.count_of_type(TextureTyp::Patch),
+            texture_loader.count_of_type(TextureTyp::Flat),
+            texture_loader.count_of_type(TextureTyp::Texture),
+            texture_loader.len(),
+        );
+    }
+    phase_done("Loaded textures");
 
     if args.dump_textures {
         let mut texture_dump_dir = output_dir.clone();
         texture_dump_dir.push("dump");
         texture_dump_dir.push("textures");
-        std::fs::create_dir_all(&texture_dump_dir).unwrap();
-        texture_loader.dump(&texture_dump_dir);
+        let result =
+            std::fs::create_dir_all(&texture_dump_dir).and_then(|_| {
+                texture_loader.dump(&texture_dump_dir, |name, typ| {
+                    let name_matches = args
+                        .dump_filter
+                        .as_ref()
+                        .is_none_or(|p| wad_reader::glob_match(p, name));
+                    let typ_matches = args
+                        .dump_type
+                        .is_none_or(|t| TextureTyp::from(t) == typ);
+                    name_matches && typ_matches
+                })
+            });
+        if let Err(e) = result {
+            exit_with_error(format!(
+                "Failed to dump textures to '{}': {}",
+                texture_dump_dir.display(),
+                e
+            ));
+        }
+    }
+
+    if args.dump_dehacked {
+        let mut path = output_dir.clone();
+        path.push("DEHACKED");
+        path.set_extension("deh");
+
+        match wad.read_text_lump("DEHACKED") {
+            Ok(text) => {
+                if let Err(e) = std::fs::write(&path, text) {
+                    exit_with_error(format!(
+                        "Failed to write the DEHACKED lump to '{}': {}",
+                        path.display(),
+                        e
+                    ));
+                }
+            }
+            Err(wad::Error::NoDirFound) => {
+                eprintln!("Warning: The WAD has no DEHACKED lump")
+            }
+            Err(e) => exit_with_error(format!(
+                "Failed to read the DEHACKED lump: {}",
+                e
+            )),
+        }
     }
 
+    let map_name;
     let map = if let Some(map) = args.map.as_ref() {
-        map.as_str()
+        map_name = resolve_map_name(&wad, map);
+        map_name.as_str()
     } else {
         // TODO(patrik): If args.map is none then we should convert all
         // the maps
@@ -261,16 +625,360 @@ fn main() {
 
     let mut output = output_dir.clone();
     output.push(map);
-    output.set_extension("glb");
+    match args.format {
+        OutputFormat::Gltf => output.set_extension("glb"),
+        OutputFormat::Mime => output.set_extension("mime"),
+        OutputFormat::Obj => output.set_extension("obj"),
+    };
 
-    println!("Converting '{}' to GLTF", map);
+    if args.check {
+        println!("Checking '{}'", map);
+    } else {
+        println!("Converting '{}' to {:?}", map, args.format);
+    }
 
     // Construct an map with map from the wad
-    let wad_map =
-        wad::Map::parse_from_wad(&wad, map).expect("Failed to load wad map");
+    let wad_map = wad::Map::parse_from_wad(&wad, map).unwrap_or_else(|e| {
+        exit_with_error(format!("Failed to load map '{}': {}", map, e))
+    });
+    phase_done("Parsed map");
+
+    if verbose {
+        let stats = wad_map.stats();
+        eprintln!(
+            "Map has {} vertices, {} linedefs ({} one sided, {} two sided), \
+             {} sidedefs and {} sectors",
+            stats.vertices,
+            stats.linedefs,
+            stats.one_sided_linedefs,
+            stats.two_sided_linedefs,
+            stats.sidedefs,
+            stats.sectors,
+        );
+        eprintln!(
+            "Map has {} segments and {} sub sectors",
+            stats.segments, stats.sub_sectors,
+        );
+        eprintln!(
+            "Floor heights {} to {}, ceiling heights {} to {}",
+            stats.min_floor_height,
+            stats.max_floor_height,
+            stats.min_ceiling_height,
+            stats.max_ceiling_height,
+        );
+    }
+
+    let gen_options = gen::GenOptions {
+        debug_mode: args.debug,
+        show_missing: args.show_missing,
+        fullbright_textures: args.fullbright.clone(),
+        flat_to_texture: args.flat_to_texture,
+    };
+    let mut context = gen::Context::new(texture_loader, gen_options);
+
+    if let Some(index) = args.only_sector {
+        if wad_map.sectors.is_empty() {
+            exit_with_error(format!(
+                "Sector {} doesn't exist, the map has no sectors",
+                index
+            ));
+        }
+
+        if index >= wad_map.sectors.len() {
+            exit_with_error(format!(
+                "Sector {} doesn't exist, the map has sectors 0 to {}",
+                index,
+                wad_map.sectors.len().saturating_sub(1)
+            ));
+        }
+    }
+
+    if args.split_by.is_some() && args.format != OutputFormat::Gltf {
+        eprintln!(
+            "Warning: Ignoring --split-by, only the glTF output can be split"
+        );
+    }
 
-    let mut context = gen::Context::new(texture_loader);
+    if args.up != UpAxis::Y && args.format != OutputFormat::Gltf {
+        eprintln!("Warning: Ignoring --up, only used by the glTF output");
+    }
+
+    if args.collision_only && args.format != OutputFormat::Gltf {
+        eprintln!(
+            "Warning: Ignoring --collision-only, only used by the glTF output"
+        );
+    }
+
+    let mut map = Map::gen_map(&mut context, &wad_map, args.only_sector);
+    for warning in &context.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if verbose {
+        let wall_quads = map
+            .sectors
+            .iter()
+            .map(|s| s.wall_quads.len())
+            .sum::<usize>();
+        let slope_quads = map
+            .sectors
+            .iter()
+            .map(|s| s.slope_quads.len())
+            .sum::<usize>();
+
+        eprintln!(
+            "Generated {} sectors with {} wall quads and {} slope quads",
+            map.sectors.len(),
+            wall_quads,
+            slope_quads
+        );
+    }
+    phase_done("Generated geometry");
+
+    if args.animations {
+        // NOTE: Sort the ids so the output is the same between runs
+        let mut texture_ids =
+            context.texture_queue.iter().copied().collect::<Vec<_>>();
+        texture_ids.sort();
+
+        let defs = animation::animation_defs(&wad).unwrap_or_else(|e| {
+            exit_with_error(format!("Failed to read the ANIMATED lump: {}", e))
+        });
+
+        let mut animations: Vec<animation::Animation> = Vec::new();
+        for &texture_id in &texture_ids {
+            let animation = animation::find_animation(
+                &context.texture_loader,
+                &defs,
+                texture_id,
+            );
+
+            if let Some(animation) = animation {
+                if !animations.iter().any(|a| a.frames == animation.frames) {
+                    animations.push(animation);
+                }
+            }
+        }
+
+        // NOTE: Only the switches used by the map are written
+        let switches = match animation::read_switches(&wad) {
+            Ok(switches) => switches,
+            Err(wad::Error::NoDirFound) => Vec::new(),
+            Err(e) => exit_with_error(format!(
+                "Failed to read the SWITCHES lump: {}",
+                e
+            )),
+        };
+        let switches = switches
+            .into_iter()
+            .filter(|s| {
+                [&s.off, &s.on].iter().any(|name| {
+                    context
+                        .texture_loader
+                        .find_id_of_type(name, texture::TextureTyp::Texture)
+                        .is_some_and(|id| texture_ids.contains(&id))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut animation_dir = output_dir.clone();
+        animation_dir.push("animations");
+        let result = std::fs::create_dir_all(&animation_dir).and_then(|_| {
+            animation::dump(
+                &context.texture_loader,
+                &animations,
+                &switches,
+                &animation_dir,
+            )
+        });
+        if let Err(e) = result {
+            exit_with_error(format!(
+                "Failed to write the animations to '{}': {}",
+                animation_dir.display(),
+                e
+            ));
+        }
+    }
+
+    if let Some(recenter) = args.recenter {
+        // NOTE: The map is in (x, height, y), the height is centered
+        // between the lowest floor and the highest ceiling
+        let (min, max) = wad_map.bounding_box();
+        let center_x = (min.x + max.x) * 0.5;
+        let center_y = (min.y + max.y) * 0.5;
+        let center_height = match recenter {
+            Recenter::All => {
+                let stats = wad_map.stats();
+                (stats.min_floor_height + stats.max_ceiling_height) * 0.5
+            }
+            Recenter::Horizontal => 0.0,
+        };
+        map.translate(Vec3::new(-center_x, -center_height, -center_y));
+    }
+
+    // NOTE: The warnings are already printed, only the count is reported
+    let issues =
+        context.texture_loader.warnings().len() + context.warnings.len();
+    if args.strict && issues > 0 {
+        exit_with_error(format!("Found {} problem(s) in strict mode", issues));
+    }
+
+    let atlas = if args.atlas && args.debug.is_none() && !args.collision_only {
+        let atlas = Atlas::build(
+            &context.texture_loader,
+            &context.texture_queue,
+            args.atlas_padding,
+        )
+        .unwrap_or_else(|e| {
+            exit_with_error(format!("Failed to build the atlas: {}", e))
+        });
+
+        if args.dump_textures {
+            let mut atlas_dump_dir = output_dir.clone();
+            atlas_dump_dir.push("dump");
+            let result =
+                std::fs::create_dir_all(&atlas_dump_dir).and_then(|_| {
+                    atlas.dump(&context.texture_loader, &atlas_dump_dir)
+                });
+            if let Err(e) = result {
+                exit_with_error(format!(
+                    "Failed to dump the atlas to '{}': {}",
+                    atlas_dump_dir.display(),
+                    e
+                ));
+            }
+        }
 
-    let map = Map::gen_map(&mut context, &wad_map);
-    write_map_gltf(&context, map, output);
+        Some(atlas)
+    } else {
+        None
+    };
+
+    if verbose {
+        eprintln!(
+            "Writing {} textures to '{}'",
+            context.texture_queue.len(),
+            output.display()
+        );
+    }
+
+    let mesh_options = MeshOptions {
+        weld: args.weld,
+        normals: args.normals,
+    };
+
+    let gltf_options = GltfOptions {
+        node_naming: args.node_naming,
+        merge_sectors: args.merge_sectors,
+        filter: args.filter,
+        mesh: mesh_options,
+        max_texture_size: args.max_texture_size,
+        up_axis: args.up,
+        primitive_extras: args.primitive_extras,
+        collision_only: args.collision_only,
+        generator: args.generator.clone(),
+        copyright: args.copyright.clone(),
+        wad_file: file_name(&args.wad_file),
+        iwad_file: args.iwad.as_deref().map(file_name),
+        validate: args.strict || args.check || cfg!(debug_assertions),
+    };
+
+    // NOTE: The check builds the selected format and encodes it into a
+    // buffer that is thrown away so every step of the conversion runs
+    // except the writing
+    if args.check {
+        let result = match args.format {
+            OutputFormat::Gltf => build_map_gltf(
+                &context,
+                &wad_map,
+                map,
+                atlas.as_ref(),
+                &gltf_options,
+            )
+            .map(|(gltf, _)| drop(gltf.write_model())),
+            OutputFormat::Mime => build_map_mime(&context, map, mesh_options)
+                .map(|(mime_map, _)| drop(mime_map.to_bytes())),
+            OutputFormat::Obj => build_map_obj(
+                &context,
+                map,
+                atlas.as_ref(),
+                mesh_options,
+                args.max_texture_size,
+            )
+            .map(|(model, _)| {
+                drop(model.obj.write_obj("check.mtl"));
+                drop(obj::Obj::write_mtl(&model.materials));
+            }),
+        };
+        if let Err(e) = result {
+            exit_with_error(format!(
+                "Failed to build the {:?} of '{}': {}",
+                args.format, wad_map.name, e
+            ));
+        }
+
+        println!("Checked '{}', {} warning(s)", wad_map.name, issues);
+        if issues > 0 {
+            exit_with_error(format!(
+                "Found {} problem(s) in '{}'",
+                issues, wad_map.name
+            ));
+        }
+        return;
+    }
+
+    let result = match args.format {
+        OutputFormat::Gltf => {
+            let options = gltf_options;
+
+            if let Some(split_by) = args.split_by {
+                write_map_gltf_split(
+                    &context,
+                    &wad_map,
+                    map,
+                    atlas.as_ref(),
+                    &options,
+                    split_by,
+                    &output,
+                )
+            } else {
+                write_map_gltf(
+                    &context,
+                    &wad_map,
+                    map,
+                    atlas.as_ref(),
+                    &options,
+                    &output,
+                )
+            }
+        }
+        OutputFormat::Mime => {
+            write_map_mime(&context, map, mesh_options, &output)
+        }
+        OutputFormat::Obj => write_map_obj(
+            &context,
+            map,
+            atlas.as_ref(),
+            mesh_options,
+            args.max_texture_size,
+            &output,
+        ),
+    };
+
+    let stats = match result {
+        Ok(stats) => stats,
+        Err(e) => exit_with_error(format!(
+            "Failed to write '{}': {}",
+            output.display(),
+            e
+        )),
+    };
+
+    if verbose && (args.weld || args.normals == Normals::Smooth) {
+        eprintln!(
+            "Welded {} vertices down to {}",
+            stats.before_weld, stats.after_weld
+        );
+    }
+    phase_done("Wrote output");
 }
@@ -1,4 +1,4 @@
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
 pub struct Vec2 {
     pub x: f32,
     pub y: f32,
@@ -9,8 +9,27 @@ impl Vec2 {
         Self { x, y }
     }
 
+    pub fn dot(&self, other: Self) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn length_squared(&self) -> f32 {
+        self.dot(*self)
+    }
+
     pub fn length(&self) -> f32 {
-        (self.x * self.x + self.y * self.y).sqrt()
+        self.length_squared().sqrt()
+    }
+
+    /// Returns true if every component is within eps of the other vector
+    pub fn approx_eq(&self, other: Self, eps: f32) -> bool {
+        (self.x - other.x).abs() <= eps && (self.y - other.y).abs() <= eps
+    }
+}
+
+impl std::fmt::Display for Vec2 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({:.3}, {:.3})", self.x, self.y)
     }
 }
 
@@ -92,8 +111,16 @@ impl Vec3 {
         Self { x, y, z }
     }
 
+    pub fn dot(&self, other: Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn length_squared(&self) -> f32 {
+        self.dot(*self)
+    }
+
     pub fn length(&self) -> f32 {
-        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+        self.length_squared().sqrt()
     }
 
     pub fn normalize(&self) -> Self {
@@ -107,6 +134,19 @@ impl Vec3 {
 
         Self::new(x, y, z)
     }
+
+    /// Returns true if every component is within eps of the other vector
+    pub fn approx_eq(&self, other: Self, eps: f32) -> bool {
+        (self.x - other.x).abs() <= eps
+            && (self.y - other.y).abs() <= eps
+            && (self.z - other.z).abs() <= eps
+    }
+}
+
+impl std::fmt::Display for Vec3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({:.3}, {:.3}, {:.3})", self.x, self.y, self.z)
+    }
 }
 
 impl std::ops::Add<Vec3> for Vec3 {
@@ -177,7 +217,7 @@ impl std::ops::Div<f32> for Vec3 {
     }
 }
 
-#[derive(Copy, Clone, Default, Debug)]
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
 pub struct Vec4 {
     pub x: f32,
     pub y: f32,
@@ -190,3 +230,146 @@ impl Vec4 {
         Self { x, y, z, w }
     }
 }
+
+/// 4x4 matrix stored in column-major order (same as glTF)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Mat4 {
+    pub m: [f32; 16],
+}
+
+impl Mat4 {
+    pub const fn identity() -> Self {
+        Self {
+            m: [
+                1.0, 0.0, 0.0, 0.0, //
+                0.0, 1.0, 0.0, 0.0, //
+                0.0, 0.0, 1.0, 0.0, //
+                0.0, 0.0, 0.0, 1.0, //
+            ],
+        }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut result = Self::identity();
+        result.m[12] = offset.x;
+        result.m[13] = offset.y;
+        result.m[14] = offset.z;
+
+        result
+    }
+
+    /// Rotation around the Y axis (up), angle in radians
+    pub fn rotation_y(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+
+        let mut result = Self::identity();
+        result.m[0] = cos;
+        result.m[2] = -sin;
+        result.m[8] = sin;
+        result.m[10] = cos;
+
+        result
+    }
+
+    pub fn scale(scale: Vec3) -> Self {
+        let mut result = Self::identity();
+        result.m[0] = scale.x;
+        result.m[5] = scale.y;
+        result.m[10] = scale.z;
+
+        result
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::identity()
+    }
+
+    pub fn to_cols_array(self) -> [f32; 16] {
+        self.m
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl std::ops::Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [0.0; 16];
+
+        for col in 0..4 {
+            for row in 0..4 {
+                let mut value = 0.0;
+                for k in 0..4 {
+                    value += self.m[k * 4 + row] * rhs.m[col * 4 + k];
+                }
+
+                m[col * 4 + row] = value;
+            }
+        }
+
+        Mat4 { m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_and_length() {
+        let a = Vec2::new(3.0, 4.0);
+        assert_eq!(a.dot(Vec2::new(2.0, -1.0)), 2.0);
+        assert_eq!(a.length_squared(), 25.0);
+        assert_eq!(a.length(), 5.0);
+        assert_eq!(Vec2::new(5.0, 5.0) - a, Vec2::new(2.0, 1.0));
+
+        let b = Vec3::new(2.0, 3.0, 6.0);
+        assert_eq!(b.dot(Vec3::new(1.0, 0.0, -1.0)), -4.0);
+        assert_eq!(b.length_squared(), 49.0);
+        assert_eq!(b.length(), 7.0);
+    }
+
+    #[test]
+    fn mat4_constructors() {
+        let m = Mat4::translation(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotation_y(std::f32::consts::FRAC_PI_2)
+            * Mat4::scale(Vec3::new(2.0, 2.0, 2.0));
+
+        // NOTE: The point (1, 0, 0) is scaled to (2, 0, 0), rotated to
+        // (0, 0, -2) and moved to (1, 2, 1)
+        let point = [1.0, 0.0, 0.0, 1.0];
+        let mut result = [0.0; 4];
+        for (row, value) in result.iter_mut().enumerate() {
+            *value = (0..4).map(|col| m.m[col * 4 + row] * point[col]).sum();
+        }
+
+        let expected = [1.0, 2.0, 1.0, 1.0];
+        for (value, expected) in result.iter().zip(expected) {
+            assert!((value - expected).abs() < 0.0001, "{:?}", result);
+        }
+
+        assert!(Mat4::default().is_identity());
+        assert!(!m.is_identity());
+        assert_eq!(Mat4::identity() * m, m);
+    }
+
+    #[test]
+    fn approx_eq_and_display() {
+        let a = Vec2::new(1.0, 2.0);
+        assert!(a.approx_eq(a, 0.0));
+        assert!(a.approx_eq(Vec2::new(1.0005, 1.9995), 0.001));
+        assert!(!a.approx_eq(Vec2::new(1.0, 2.1), 0.001));
+        assert_eq!(Vec2::new(0.1 + 0.2, -1.0).to_string(), "(0.300, -1.000)");
+
+        let b = Vec3::new(1.0, 2.0, 3.0);
+        assert!(b.approx_eq(b, 0.0));
+        assert!(b.approx_eq(Vec3::new(1.0005, 2.0, 2.9995), 0.001));
+        assert!(!b.approx_eq(Vec3::new(1.0, 2.0, 3.5), 0.001));
+        assert_eq!(b.to_string(), "(1.000, 2.000, 3.000)");
+    }
+}
@@ -0,0 +1,453 @@
+//! Module for the custom MIME map format
+//!
+//! Layout (all values are little-endian):
+//!   magic:          [u8; 4]  "MIME"
+//!   version:        u32      MIME_VERSION
+//!   section count:  u32
+//!   sections:       count * (tag: u32, offset: u32, count: u32)
+//!   section data:   placed at the offset of each section
+//!
+//! Sections:
+//!   SECTION_VERTICES       count * Vertex (x, y, z, r, g, b, a as f32)
+//!   SECTION_INDICES        count * u32
+//!   SECTION_UVS            count * (u, v as f32), one for each vertex
+//!   SECTION_TEXTURE_NAMES  count * [u8; 8], name padded with zeros
+//!   SECTION_PRIMITIVES     count * (index_start: u32, index_count: u32,
+//!                                   texture_id: u32)
+//!
+//! The texture_id of a primitive is an index into the texture name table
+//!
+//! Readers skip sections with tags they don't know about so new sections
+//! can be added without breaking older readers
+//!
+//! The positions are in map units with the same axis layout as the
+//! generated geometry (x, height, y)
+
+use std::path::Path;
+
+use crate::math::{Vec2, Vec3, Vec4};
+use crate::polygon::Mesh;
+use crate::util;
+
+pub const MIME_VERSION: u32 = 3;
+
+const SECTION_VERTICES: u32 = 0;
+const SECTION_INDICES: u32 = 1;
+const SECTION_UVS: u32 = 2;
+const SECTION_TEXTURE_NAMES: u32 = 3;
+const SECTION_PRIMITIVES: u32 = 4;
+
+const SECTION_HEADER_SIZE: usize = 3 * 4;
+const VERTEX_SIZE: usize = 7 * 4;
+const UV_SIZE: usize = 2 * 4;
+const TEXTURE_NAME_SIZE: usize = 8;
+const PRIMITIVE_SIZE: usize = 3 * 4;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    UnknownMagic([u8; 4]),
+    UnsupportedVersion(u32),
+    MissingSection { tag: u32 },
+    Truncated,
+    Io(std::io::ErrorKind),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownMagic(magic) => {
+                write!(f, "unknown magic {:?}", magic)
+            }
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {}", version)
+            }
+            Error::MissingSection { tag } => {
+                write!(f, "the section {} is missing", tag)
+            }
+            Error::Truncated => write!(f, "the data is truncated"),
+            Error::Io(kind) => write!(f, "failed to read the file: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).ok_or(Error::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_f32(data: &[u8], offset: usize) -> Result<f32> {
+    let bytes = data.get(offset..offset + 4).ok_or(Error::Truncated)?;
+    Ok(f32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Section {
+    tag: u32,
+    offset: usize,
+    count: usize,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Vertex {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub color: Vec4,
+    pub uv: Vec2,
+}
+
+impl Vertex {
+    pub fn new(pos: Vec3, color: Vec4, uv: Vec2) -> Self {
+        Self {
+            x: pos.x,
+            y: pos.y,
+            z: pos.z,
+            color,
+            uv,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Primitive {
+    pub index_start: u32,
+    pub index_count: u32,
+    pub texture_id: u32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Map {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub texture_names: Vec<String>,
+    pub primitives: Vec<Primitive>,
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            texture_names: Vec::new(),
+            primitives: Vec::new(),
+        }
+    }
+
+    fn texture_id(&mut self, texture_name: &str) -> u32 {
+        let id = self.texture_names.iter().position(|n| n == texture_name);
+        let id = if let Some(id) = id {
+            id
+        } else {
+            self.texture_names.push(texture_name.to_string());
+            self.texture_names.len() - 1
+        };
+
+        id as u32
+    }
+
+    pub fn add_mesh(&mut self, mesh: &Mesh, texture_name: &str) {
+        let texture_id = self.texture_id(texture_name);
+
+        let index_offset = self.vertices.len() as u32;
+        let index_start = self.indices.len() as u32;
+
+        for v in &mesh.vertex_buffer {
+            self.vertices.push(Vertex::new(v.pos, v.color, v.uv));
+        }
+
+        for i in &mesh.index_buffer {
+            self.indices.push(i + index_offset);
+        }
+
+        self.primitives.push(Primitive {
+            index_start,
+            index_count: mesh.index_buffer.len() as u32,
+            texture_id,
+        });
+    }
+
+    fn write_vertices(&self, data: &mut Vec<u8>) {
+        for v in &self.vertices {
+            data.extend_from_slice(&v.x.to_le_bytes());
+            data.extend_from_slice(&v.y.to_le_bytes());
+            data.extend_from_slice(&v.z.to_le_bytes());
+
+            data.extend_from_slice(&v.color.x.to_le_bytes());
+            data.extend_from_slice(&v.color.y.to_le_bytes());
+            data.extend_from_slice(&v.color.z.to_le_bytes());
+            data.extend_from_slice(&v.color.w.to_le_bytes());
+        }
+    }
+
+    fn write_indices(&self, data: &mut Vec<u8>) {
+        for i in &self.indices {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+    }
+
+    fn write_uvs(&self, data: &mut Vec<u8>) {
+        for v in &self.vertices {
+            data.extend_from_slice(&v.uv.x.to_le_bytes());
+            data.extend_from_slice(&v.uv.y.to_le_bytes());
+        }
+    }
+
+    fn write_texture_names(&self, data: &mut Vec<u8>) {
+        for name in &self.texture_names {
+            let mut bytes = [0u8; TEXTURE_NAME_SIZE];
+            let len = name.len().min(TEXTURE_NAME_SIZE);
+            bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+            data.extend_from_slice(&bytes);
+        }
+    }
+
+    fn write_primitives(&self, data: &mut Vec<u8>) {
+        for p in &self.primitives {
+            data.extend_from_slice(&p.index_start.to_le_bytes());
+            data.extend_from_slice(&p.index_count.to_le_bytes());
+            data.extend_from_slice(&p.texture_id.to_le_bytes());
+        }
+    }
+
+    /// Writes the map in the MIME format, the result can be read back with
+    /// parse
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut section_data = Vec::new();
+        let mut sections = Vec::new();
+
+        let start = section_data.len();
+        self.write_vertices(&mut section_data);
+        sections.push((SECTION_VERTICES, start, self.vertices.len()));
+
+        let start = section_data.len();
+        self.write_indices(&mut section_data);
+        sections.push((SECTION_INDICES, start, self.indices.len()));
+
+        let start = section_data.len();
+        self.write_uvs(&mut section_data);
+        sections.push((SECTION_UVS, start, self.vertices.len()));
+
+        let start = section_data.len();
+        self.write_texture_names(&mut section_data);
+        sections.push((
+            SECTION_TEXTURE_NAMES,
+            start,
+            self.texture_names.len(),
+        ));
+
+        let start = section_data.len();
+        self.write_primitives(&mut section_data);
+        sections.push((SECTION_PRIMITIVES, start, self.primitives.len()));
+
+        let header_size = 4 + 4 + 4 + sections.len() * SECTION_HEADER_SIZE;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"MIME");
+        data.extend_from_slice(&MIME_VERSION.to_le_bytes());
+        data.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+
+        for (tag, offset, count) in sections {
+            let offset = header_size + offset;
+
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            data.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+
+        data.extend_from_slice(&section_data);
+
+        data
+    }
+
+    pub fn save_to_file<P>(&self, path: P) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        util::write_binary_file(path, &self.to_bytes())
+    }
+
+    pub fn load_from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let data = std::fs::read(path).map_err(|e| Error::Io(e.kind()))?;
+        Self::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let magic = data.get(0..4).ok_or(Error::Truncated)?;
+        if magic != b"MIME" {
+            return Err(Error::UnknownMagic(magic.try_into().unwrap()));
+        }
+
+        let version = read_u32(data, 4)?;
+        if version != MIME_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let section_count = read_u32(data, 8)? as usize;
+
+        let mut sections = Vec::new();
+        for index in 0..section_count {
+            let start = 12 + index * SECTION_HEADER_SIZE;
+
+            let tag = read_u32(data, start)?;
+            let offset = read_u32(data, start + 4)? as usize;
+            let count = read_u32(data, start + 8)? as usize;
+
+            sections.push(Section { tag, offset, count });
+        }
+
+        let find_section = |tag: u32, element_size: usize| {
+            let section = sections
+                .iter()
+                .find(|s| s.tag == tag)
+                .ok_or(Error::MissingSection { tag })?;
+
+            // NOTE: Check the size before allocating so a broken header
+            // can't make us allocate huge buffers
+            if section.offset + section.count * element_size > data.len() {
+                return Err(Error::Truncated);
+            }
+
+            Ok(*section)
+        };
+
+        let section = find_section(SECTION_VERTICES, VERTEX_SIZE)?;
+        let mut vertices = Vec::with_capacity(section.count);
+        for index in 0..section.count {
+            let start = section.offset + index * VERTEX_SIZE;
+
+            let x = read_f32(data, start)?;
+            let y = read_f32(data, start + 4)?;
+            let z = read_f32(data, start + 8)?;
+
+            let r = read_f32(data, start + 12)?;
+            let g = read_f32(data, start + 16)?;
+            let b = read_f32(data, start + 20)?;
+            let a = read_f32(data, start + 24)?;
+
+            vertices.push(Vertex {
+                x,
+                y,
+                z,
+                color: Vec4::new(r, g, b, a),
+                uv: Vec2::default(),
+            });
+        }
+
+        let section = find_section(SECTION_UVS, UV_SIZE)?;
+        if section.count != vertices.len() {
+            return Err(Error::Truncated);
+        }
+
+        for (index, vertex) in vertices.iter_mut().enumerate() {
+            let start = section.offset + index * UV_SIZE;
+
+            let u = read_f32(data, start)?;
+            let v = read_f32(data, start + 4)?;
+
+            vertex.uv = Vec2::new(u, v);
+        }
+
+        let section = find_section(SECTION_INDICES, 4)?;
+        let mut indices = Vec::with_capacity(section.count);
+        for index in 0..section.count {
+            let start = section.offset + index * 4;
+            indices.push(read_u32(data, start)?);
+        }
+
+        let section = find_section(SECTION_TEXTURE_NAMES, TEXTURE_NAME_SIZE)?;
+        let mut texture_names = Vec::with_capacity(section.count);
+        for index in 0..section.count {
+            let start = section.offset + index * TEXTURE_NAME_SIZE;
+            let name = &data[start..start + TEXTURE_NAME_SIZE];
+
+            texture_names.push(util::array_to_string(name));
+        }
+
+        let section = find_section(SECTION_PRIMITIVES, PRIMITIVE_SIZE)?;
+        let mut primitives = Vec::with_capacity(section.count);
+        for index in 0..section.count {
+            let start = section.offset + index * PRIMITIVE_SIZE;
+
+            let index_start = read_u32(data, start)?;
+            let index_count = read_u32(data, start + 4)?;
+            let texture_id = read_u32(data, start + 8)?;
+
+            primitives.push(Primitive {
+                index_start,
+                index_count,
+                texture_id,
+            });
+        }
+
+        Ok(Self {
+            vertices,
+            indices,
+            texture_names,
+            primitives,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polygon;
+    use crate::testing;
+
+    #[test]
+    fn write_and_read_back() {
+        let vertex = |x: f32, u: f32| {
+            polygon::Vertex::new(
+                Vec3::new(x, 2.0, 3.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec2::new(u, 0.5),
+                Vec4::new(1.0, 0.5, 0.25, 1.0),
+            )
+        };
+
+        let mut mesh = Mesh::new();
+        mesh.add_triangles(
+            &[vertex(0.0, 0.0), vertex(1.0, 1.0), vertex(2.0, 0.25)],
+            &[0, 1, 2],
+        );
+
+        let mut map = Map::new();
+        map.add_mesh(&mesh, "FLOOR");
+        map.add_mesh(&mesh, "STARTAN3");
+        map.add_mesh(&mesh, "FLOOR");
+
+        let path = testing::temp_dir("mime_round_trip").join("map.mime");
+        map.save_to_file(&path).unwrap();
+
+        let result = Map::load_from_file(&path).unwrap();
+        assert_eq!(result, map);
+        assert_eq!(result.texture_names, ["FLOOR", "STARTAN3"]);
+        assert_eq!(result.primitives[2].texture_id, 0);
+        assert_eq!(result.primitives[1].index_start, 3);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut data = Map::new().to_bytes();
+        data[4..8].copy_from_slice(&99u32.to_le_bytes());
+
+        assert!(matches!(
+            Map::parse(&data),
+            Err(Error::UnsupportedVersion(99))
+        ));
+    }
+}
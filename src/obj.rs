@@ -0,0 +1,164 @@
+//! Module to write Wavefront OBJ files with a MTL material library
+//!
+//! All the meshes with the same material are written as one group
+//! (usemtl <name>) so there is one group for each texture like the glTF
+//! materials, the materials are written to a .mtl file next to the .obj
+//! file that references the dumped PNG textures
+//!
+//! The positions are in map units with the same axis layout as the
+//! generated geometry (x, height, y)
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::math::Vec4;
+use crate::polygon::{Mesh, Vertex};
+use crate::util;
+
+struct Group {
+    material: String,
+    /// Indices into the vertices, three for each triangle
+    indices: Vec<u32>,
+}
+
+pub struct Material {
+    pub name: String,
+    /// Path of the texture image relative to the .mtl file
+    pub texture_path: Option<String>,
+}
+
+pub struct Obj {
+    vertices: Vec<Vertex>,
+    groups: Vec<Group>,
+}
+
+impl Default for Obj {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Obj {
+    pub fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn add_mesh(&mut self, mesh: &Mesh, material: &str) {
+        let index_offset = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&mesh.vertex_buffer);
+
+        let group =
+            match self.groups.iter().position(|g| g.material == material) {
+                Some(index) => &mut self.groups[index],
+                None => {
+                    self.groups.push(Group {
+                        material: material.to_string(),
+                        indices: Vec::new(),
+                    });
+                    self.groups.last_mut().unwrap()
+                }
+            };
+
+        group
+            .indices
+            .extend(mesh.index_buffer.iter().map(|i| i + index_offset));
+    }
+
+    /// The text of the .obj file, mtl_name is the .mtl file it uses
+    pub fn write_obj(&self, mtl_name: &str) -> String {
+        let mut text = String::new();
+        writeln!(text, "mtllib {}", mtl_name).unwrap();
+
+        // NOTE: The vertex colors are only written when they are used (the
+        // debug modes), most readers understands the extra r g b values
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        let has_colors = self.vertices.iter().any(|v| v.color != white);
+
+        for v in &self.vertices {
+            if has_colors {
+                writeln!(
+                    text,
+                    "v {} {} {} {} {} {}",
+                    v.pos.x, v.pos.y, v.pos.z, v.color.x, v.color.y, v.color.z
+                )
+                .unwrap();
+            } else {
+                writeln!(text, "v {} {} {}", v.pos.x, v.pos.y, v.pos.z)
+                    .unwrap();
+            }
+        }
+
+        // NOTE: The uvs are generated for glTF where v goes down the image,
+        // in OBJ it goes up
+        for v in &self.vertices {
+            writeln!(text, "vt {} {}", v.uv.x, 1.0 - v.uv.y).unwrap();
+        }
+
+        for v in &self.vertices {
+            writeln!(text, "vn {} {} {}", v.normal.x, v.normal.y, v.normal.z)
+                .unwrap();
+        }
+
+        for group in &self.groups {
+            writeln!(text, "usemtl {}", group.material).unwrap();
+
+            // NOTE: The position, uv and normal of a vertex has the same
+            // index and OBJ indices starts at 1
+            for triangle in group.indices.chunks_exact(3) {
+                let [a, b, c] =
+                    [triangle[0], triangle[1], triangle[2]].map(|i| i + 1);
+                writeln!(
+                    text,
+                    "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}",
+                    a, b, c
+                )
+                .unwrap();
+            }
+        }
+
+        text
+    }
+
+    /// The text of the .mtl file
+    pub fn write_mtl(materials: &[Material]) -> String {
+        let mut text = String::new();
+
+        for material in materials {
+            writeln!(text, "newmtl {}", material.name).unwrap();
+            writeln!(text, "Kd 1 1 1").unwrap();
+            if let Some(path) = &material.texture_path {
+                writeln!(text, "map_Kd {}", path).unwrap();
+            }
+            writeln!(text).unwrap();
+        }
+
+        text
+    }
+
+    /// Writes the .obj file and the .mtl file with the same name next to
+    /// it
+    pub fn save_to_file<P>(
+        &self,
+        path: P,
+        materials: &[Material],
+    ) -> std::io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let obj_path = path.as_ref();
+        let mtl_path = obj_path.with_extension("mtl");
+        let mtl_name = mtl_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("materials.mtl");
+
+        let text = self.write_obj(mtl_name);
+        util::write_binary_file(obj_path, text.as_bytes())?;
+
+        let text = Self::write_mtl(materials);
+        util::write_binary_file(&mtl_path, text.as_bytes())
+    }
+}
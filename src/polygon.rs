@@ -1,6 +1,11 @@
+use std::collections::HashMap;
+
 use crate::util;
 use crate::math::{Vec2, Vec3, Vec4};
 
+/// Vertices closer than 1 / WELD_PRECISION are merged when welding
+const WELD_PRECISION: f32 = 1024.0;
+
 #[derive(Copy, Clone, Default, Debug)]
 pub struct Vertex {
     pub pos: Vec3,
@@ -26,6 +31,12 @@ pub struct Mesh {
     pub texture_id: Option<usize>,
 }
 
+impl Default for Mesh {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Mesh {
     pub fn new() -> Self {
         Self {
@@ -35,18 +46,108 @@ impl Mesh {
         }
     }
 
+    pub fn with_capacity(vertex_count: usize, index_count: usize) -> Self {
+        Self {
+            vertex_buffer: Vec::with_capacity(vertex_count),
+            index_buffer: Vec::with_capacity(index_count),
+            texture_id: None,
+        }
+    }
+
     pub fn add_vertices(&mut self, vertices: &[Vertex], clockwise: bool) {
-        let triangles = util::triangulate(&vertices, clockwise);
+        // NOTE: Nothing to triangulate so don't add any unused vertices
+        if vertices.len() < 3 {
+            return;
+        }
 
-        let index_offset = self.vertex_buffer.len();
+        let triangles = util::triangulate(vertices, clockwise);
+        self.add_triangles(vertices, &triangles);
+    }
+
+    /// Adds vertices that already has been triangulated, the indices are
+    /// relative to the first vertex in vertices
+    pub fn add_triangles(&mut self, vertices: &[Vertex], indices: &[u32]) {
+        let index_offset = self.vertex_buffer.len() as u32;
+
+        self.vertex_buffer.extend_from_slice(vertices);
+        self.index_buffer
+            .extend(indices.iter().map(|i| i + index_offset));
+    }
+
+    /// Appends the vertices and the triangles of the other mesh, the
+    /// indices of the other mesh are rebased to the end of the vertices
+    ///
+    /// NOTE: Both meshes needs to have the same texture, an empty mesh
+    /// without a texture takes the texture of the other mesh
+    pub fn merge(&mut self, other: &Mesh) {
+        if self.vertex_buffer.is_empty() && self.texture_id.is_none() {
+            self.texture_id = other.texture_id;
+        }
+
+        assert_eq!(
+            self.texture_id, other.texture_id,
+            "Can't merge meshes with different textures"
+        );
+
+        self.add_triangles(&other.vertex_buffer, &other.index_buffer);
+    }
+
+    /// Replaces the normals with the average normal of all the vertices
+    /// at the same position, after this the vertices shared by the faces
+    /// can be welded together
+    ///
+    /// NOTE: Only the position is compared so the shading is smooth across
+    /// the uv seams at the corners of the walls
+    pub fn smooth_normals(&mut self) {
+        let quantize = |value: f32| (value * WELD_PRECISION).round() as i32;
+        let key = |v: &Vertex| [v.pos.x, v.pos.y, v.pos.z].map(quantize);
+
+        let mut sums: HashMap<_, Vec3> = HashMap::new();
+        for v in &self.vertex_buffer {
+            *sums.entry(key(v)).or_default() += v.normal;
+        }
 
-        for v in vertices {
-            self.vertex_buffer.push(*v);
+        for v in &mut self.vertex_buffer {
+            let sum = sums[&key(v)];
+            // NOTE: Opposite normals cancels out, keep the face normal
+            // instead of writing a zero normal
+            if sum.length_squared() > f32::EPSILON {
+                v.normal = sum.normalize();
+            }
         }
+    }
+
+    /// Merges the vertices that has the same position, normal, uv and color
+    /// and remaps the indices to the merged vertices
+    pub fn weld(&mut self) {
+        let quantize = |value: f32| (value * WELD_PRECISION).round() as i32;
+
+        let mut lookup = HashMap::new();
+        let mut vertex_buffer = Vec::with_capacity(self.vertex_buffer.len());
 
-        for i in &triangles {
-            self.index_buffer.push(i + index_offset as u32);
+        let remap = self
+            .vertex_buffer
+            .iter()
+            .map(|v| {
+                let key = [
+                    v.pos.x, v.pos.y, v.pos.z, v.normal.x, v.normal.y,
+                    v.normal.z, v.uv.x, v.uv.y, v.color.x, v.color.y,
+                    v.color.z, v.color.w,
+                ]
+                .map(quantize);
+
+                *lookup.entry(key).or_insert_with(|| {
+                    vertex_buffer.push(*v);
+                    (vertex_buffer.len() - 1) as u32
+                })
+            })
+            .collect::<Vec<u32>>();
+
+        for index in &mut self.index_buffer {
+            *index = remap[*index as usize];
         }
+
+        self.vertex_buffer = vertex_buffer;
     }
 }
 
@@ -56,6 +157,12 @@ pub struct Quad {
     pub texture_id: usize,
 }
 
+impl Default for Quad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Quad {
     pub fn new() -> Self {
         Self {
@@ -64,3 +171,58 @@ impl Quad {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, z: f32) -> Vertex {
+        let pos = Vec3::new(x, 0.0, z);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let color = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        Vertex::new(pos, normal, Vec2::default(), color)
+    }
+
+    #[test]
+    fn two_vertices_adds_nothing() {
+        let mut mesh = Mesh::new();
+        mesh.add_vertices(&[vertex(0.0, 0.0), vertex(64.0, 0.0)], true);
+        assert!(mesh.vertex_buffer.is_empty());
+        assert!(mesh.index_buffer.is_empty());
+
+        mesh.add_vertices(
+            &[vertex(0.0, 0.0), vertex(64.0, 0.0), vertex(64.0, 64.0)],
+            true,
+        );
+        assert_eq!(mesh.vertex_buffer.len(), 3);
+        assert_eq!(mesh.index_buffer, [0, 1, 2]);
+    }
+
+    fn triangle(texture_id: Option<usize>, indices: &[u32]) -> Mesh {
+        let mut mesh = Mesh::new();
+        let vertices =
+            [vertex(0.0, 0.0), vertex(64.0, 0.0), vertex(0.0, 64.0)];
+        mesh.add_triangles(&vertices, indices);
+        mesh.texture_id = texture_id;
+        mesh
+    }
+
+    #[test]
+    fn merge_two_triangles() {
+        let mut mesh = Mesh::new();
+        mesh.merge(&triangle(Some(3), &[0, 1, 2]));
+        mesh.merge(&triangle(Some(3), &[2, 1, 0]));
+
+        // NOTE: The empty mesh takes the texture of the first triangle
+        assert_eq!(mesh.texture_id, Some(3));
+        assert_eq!(mesh.vertex_buffer.len(), 6);
+        assert_eq!(mesh.index_buffer, [0, 1, 2, 5, 4, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't merge meshes with different textures")]
+    fn merge_with_different_textures() {
+        let mut mesh = triangle(Some(1), &[0, 1, 2]);
+        mesh.merge(&triangle(Some(2), &[0, 1, 2]));
+    }
+}
@@ -0,0 +1,791 @@
+//! Builders for the small WAD files used by the tests
+//!
+//! Nothing from the crate is used so the integration tests can include the
+//! module with #[path]
+
+use std::path::PathBuf;
+
+pub const WALL: &str = "WALL";
+pub const MASKED: &str = "MASKED";
+pub const FLOOR: &str = "FLOOR";
+pub const CEIL: &str = "CEIL";
+
+/// Set on the vertex indices of the segs that are GL vertices
+pub const GL_VERTEX: usize = 1 << 31;
+
+pub type Lumps = Vec<(String, Vec<u8>)>;
+
+/// An empty directory for the files written by the test
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "wad_reader_{}_{}",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The name padded with zeros to 8 bytes
+pub fn name8(name: &str) -> [u8; 8] {
+    let mut result = [0; 8];
+    result[..name.len()].copy_from_slice(name.as_bytes());
+    result
+}
+
+/// The JSON chunk of a binary glTF
+pub fn glb_json(glb: &[u8]) -> serde_json::Value {
+    assert_eq!(&glb[0..4], b"glTF");
+    let length = u32::from_le_bytes(glb[12..16].try_into().unwrap());
+    assert_eq!(&glb[16..20], b"JSON");
+    serde_json::from_slice(&glb[20..20 + length as usize]).unwrap()
+}
+
+#[derive(Default)]
+pub struct WadBuilder {
+    lumps: Lumps,
+}
+
+impl WadBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lumps(mut self, lumps: Lumps) -> Self {
+        self.lumps.extend(lumps);
+        self
+    }
+
+    /// The WAD file with the magic (IWAD or PWAD), the lumps are followed
+    /// by the directory
+    pub fn build(&self, magic: &[u8; 4]) -> Vec<u8> {
+        let data_size = self.lumps.iter().map(|(_, d)| d.len()).sum::<usize>();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(magic);
+        bytes.extend_from_slice(&(self.lumps.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&(12 + data_size as i32).to_le_bytes());
+
+        let mut directory = Vec::new();
+        for (name, data) in &self.lumps {
+            directory.extend_from_slice(&(bytes.len() as i32).to_le_bytes());
+            directory.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            directory.extend_from_slice(&name8(name));
+            bytes.extend_from_slice(data);
+        }
+
+        bytes.extend_from_slice(&directory);
+        bytes
+    }
+}
+
+/// The color of the palette index inside palette 0 of playpal
+pub fn palette_color(index: u8) -> [u8; 3] {
+    [index, 255 - index, 0]
+}
+
+/// PLAYPAL with the palettes, the blue channel is the number of the
+/// palette
+pub fn playpal(count: usize) -> Vec<u8> {
+    let mut result = Vec::new();
+    for palette in 0..count {
+        for index in 0..=255 {
+            let [r, g, _] = palette_color(index);
+            result.extend_from_slice(&[r, g, palette as u8]);
+        }
+    }
+
+    result
+}
+
+/// COLORMAP where every color map keeps the colors as they are
+pub fn colormap(count: usize) -> Vec<u8> {
+    (0..count).flat_map(|_| 0..=255u8).collect()
+}
+
+/// A patch with the posts (topdelta, pixels) of each column
+pub fn patch(
+    width: usize,
+    height: usize,
+    column: impl Fn(usize) -> Vec<(u8, Vec<u8>)>,
+) -> Vec<u8> {
+    let mut columns = Vec::new();
+    let mut offsets = Vec::new();
+    let start = 8 + width * 4;
+    for x in 0..width {
+        offsets.push(start + columns.len());
+        for (topdelta, pixels) in column(x) {
+            columns.extend_from_slice(&[topdelta, pixels.len() as u8, 0]);
+            columns.extend_from_slice(&pixels);
+            columns.push(0);
+        }
+        columns.push(0xff);
+    }
+
+    let mut result = Vec::new();
+    result.extend_from_slice(&(width as u16).to_le_bytes());
+    result.extend_from_slice(&(height as u16).to_le_bytes());
+    result.extend_from_slice(&[0; 4]);
+    for offset in offsets {
+        result.extend_from_slice(&(offset as u32).to_le_bytes());
+    }
+    result.extend_from_slice(&columns);
+
+    result
+}
+
+/// A patch without any transparent pixels
+pub fn solid_patch(
+    width: usize,
+    height: usize,
+    pixel: impl Fn(usize, usize) -> u8,
+) -> Vec<u8> {
+    patch(width, height, |x| {
+        vec![(0, (0..height).map(|y| pixel(x, y)).collect())]
+    })
+}
+
+/// A 64x64 flat
+pub fn flat(pixel: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+    (0..64 * 64).map(|i| pixel(i % 64, i / 64)).collect()
+}
+
+pub fn pnames(names: &[&str]) -> Vec<u8> {
+    let mut result = (names.len() as u32).to_le_bytes().to_vec();
+    for name in names {
+        result.extend_from_slice(&name8(name));
+    }
+
+    result
+}
+
+/// A texture definition of TEXTURE1, the patches are (origin x, origin y,
+/// index into PNAMES)
+pub struct TextureDef<'a> {
+    pub name: &'a str,
+    pub masked: bool,
+    pub width: u16,
+    pub height: u16,
+    pub patches: &'a [(i16, i16, i16)],
+}
+
+pub fn texture_lump(defs: &[TextureDef]) -> Vec<u8> {
+    let mut result = (defs.len() as u32).to_le_bytes().to_vec();
+
+    let mut data = Vec::new();
+    let start = 4 + defs.len() * 4;
+    for def in defs {
+        result.extend_from_slice(&((start + data.len()) as u32).to_le_bytes());
+
+        data.extend_from_slice(&name8(def.name));
+        data.extend_from_slice(&(def.masked as u32).to_le_bytes());
+        data.extend_from_slice(&def.width.to_le_bytes());
+        data.extend_from_slice(&def.height.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(def.patches.len() as u16).to_le_bytes());
+        for &(x, y, patch) in def.patches {
+            data.extend_from_slice(&x.to_le_bytes());
+            data.extend_from_slice(&y.to_le_bytes());
+            data.extend_from_slice(&patch.to_le_bytes());
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&0u16.to_le_bytes());
+        }
+    }
+
+    result.extend_from_slice(&data);
+    result
+}
+
+/// The palette, color map, patches, flats and textures needed to convert
+/// the maps of the builder
+///
+/// WALL is 64x64 and solid, MASKED is 16x16 with a transparent bottom half,
+/// FLOOR and CEIL are flats
+pub fn game_lumps() -> Lumps {
+    let texture1 = texture_lump(&[
+        // NOTE: DOOM never draws the first texture
+        TextureDef {
+            name: "AASHITTY",
+            masked: false,
+            width: 64,
+            height: 64,
+            patches: &[(0, 0, 0)],
+        },
+        TextureDef {
+            name: WALL,
+            masked: false,
+            width: 64,
+            height: 64,
+            patches: &[(0, 0, 0)],
+        },
+        TextureDef {
+            name: MASKED,
+            masked: true,
+            width: 16,
+            height: 16,
+            patches: &[(0, 0, 1)],
+        },
+    ]);
+
+    vec![
+        ("PLAYPAL".to_string(), playpal(1)),
+        ("COLORMAP".to_string(), colormap(1)),
+        ("PNAMES".to_string(), pnames(&["PATCH1", "PATCH2"])),
+        ("TEXTURE1".to_string(), texture1),
+        ("P_START".to_string(), Vec::new()),
+        (
+            "PATCH1".to_string(),
+            solid_patch(64, 64, |x, y| (x + y) as u8),
+        ),
+        (
+            "PATCH2".to_string(),
+            patch(16, 16, |_| vec![(0, vec![7; 8])]),
+        ),
+        ("P_END".to_string(), Vec::new()),
+        ("F_START".to_string(), Vec::new()),
+        (FLOOR.to_string(), flat(|x, y| (x * 2 + y) as u8)),
+        (CEIL.to_string(), flat(|_, _| 200)),
+        ("F_END".to_string(), Vec::new()),
+    ]
+}
+
+/// The BLOCKMAP lump with the linedefs of each block, vanilla node builders
+/// starts every list with a 0
+pub fn blockmap(
+    columns: u16,
+    rows: u16,
+    blocks: &[Vec<u16>],
+    leading_zero: bool,
+) -> Vec<u8> {
+    let mut words = vec![0, 0, columns, rows];
+    let mut lists = Vec::new();
+    let start = 4 + blocks.len();
+    for block in blocks {
+        words.push((start + lists.len()) as u16);
+        if leading_zero {
+            lists.push(0);
+        }
+        lists.extend_from_slice(block);
+        lists.push(0xffff);
+    }
+    words.extend_from_slice(&lists);
+
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Format {
+    Doom,
+    Hexen,
+}
+
+#[derive(Clone, Debug)]
+pub struct Linedef {
+    pub v1: usize,
+    pub v2: usize,
+    pub flags: u16,
+    pub special: u16,
+    pub tag: u16,
+    pub front: Option<usize>,
+    pub back: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sidedef {
+    pub x_offset: i16,
+    pub y_offset: i16,
+    pub upper: String,
+    pub lower: String,
+    pub middle: String,
+    pub sector: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sector {
+    pub floor: i16,
+    pub ceiling: i16,
+    pub floor_texture: String,
+    pub ceiling_texture: String,
+    pub light: i16,
+    pub special: u16,
+    pub tag: u16,
+}
+
+/// A GL seg, the vertices with GL_VERTEX set are GL vertices and a None
+/// linedef is a miniseg
+#[derive(Clone, Debug)]
+pub struct Seg {
+    pub v1: usize,
+    pub v2: usize,
+    pub linedef: Option<usize>,
+    pub side: u16,
+    pub partner: Option<usize>,
+}
+
+/// The lumps of a map with GL nodes
+pub struct MapBuilder {
+    pub name: String,
+    pub format: Format,
+    /// The version of the GL nodes (1, 2, 3 or 5)
+    pub gl_version: u32,
+
+    pub vertices: Vec<(i16, i16)>,
+    pub gl_vertices: Vec<(f32, f32)>,
+    pub linedefs: Vec<Linedef>,
+    pub sidedefs: Vec<Sidedef>,
+    pub sectors: Vec<Sector>,
+    pub segs: Vec<Seg>,
+    /// The first seg and the number of segs of each sub sector
+    pub sub_sectors: Vec<(usize, usize)>,
+
+    pub blockmap: Vec<u8>,
+}
+
+impl MapBuilder {
+    /// A row of rooms along x where each room is a 128x128 sector with the
+    /// floor and ceiling height, the rooms next to each other shares a two
+    /// sided linedef and every room is one sub sector
+    ///
+    /// Linedef 0 is the left wall of the first room, the bottom vertices are
+    /// 0 to rooms and the top vertices follows them
+    pub fn rooms(name: &str, heights: &[(i16, i16)]) -> Self {
+        let count = heights.len();
+        let mut map = Self {
+            name: name.to_string(),
+            format: Format::Doom,
+            gl_version: 2,
+
+            vertices: Vec::new(),
+            gl_vertices: Vec::new(),
+            linedefs: Vec::new(),
+            sidedefs: Vec::new(),
+            sectors: Vec::new(),
+            segs: Vec::new(),
+            sub_sectors: Vec::new(),
+
+            blockmap: Vec::new(),
+        };
+
+        for y in [0, 128] {
+            for index in 0..=count {
+                map.vertices.push((index as i16 * 128, y));
+            }
+        }
+        let bottom = |index: usize| index;
+        let top = |index: usize| count + 1 + index;
+
+        for &(floor, ceiling) in heights {
+            map.sectors.push(Sector {
+                floor,
+                ceiling,
+                floor_texture: FLOOR.to_string(),
+                ceiling_texture: CEIL.to_string(),
+                light: 160,
+                special: 0,
+                tag: 0,
+            });
+        }
+
+        // NOTE: The segs of each room goes clockwise (left, top, right and
+        // bottom) with the room on the right side
+        let mut left = (map.wall(bottom(0), top(0), 0), 0);
+        for index in 0..count {
+            let top_line = map.wall(top(index), top(index + 1), index);
+            let bottom_line =
+                map.wall(bottom(index + 1), bottom(index), index);
+            let right = if index + 1 < count {
+                let line =
+                    map.portal(bottom(index + 1), top(index + 1), index + 1);
+                (line, 1)
+            } else {
+                (map.wall(top(index + 1), bottom(index + 1), index), 0)
+            };
+
+            let first = map.segs.len();
+            for (v1, v2, (linedef, side)) in [
+                (bottom(index), top(index), left),
+                (top(index), top(index + 1), (top_line, 0)),
+                (top(index + 1), bottom(index + 1), right),
+                (bottom(index + 1), bottom(index), (bottom_line, 0)),
+            ] {
+                map.segs.push(Seg {
+                    v1,
+                    v2,
+                    linedef: Some(linedef),
+                    side,
+                    partner: None,
+                });
+            }
+            map.sub_sectors.push((first, 4));
+
+            if index > 0 {
+                map.segs[first - 2].partner = Some(first);
+                map.segs[first].partner = Some(first - 2);
+            }
+
+            left = (right.0, 0);
+        }
+
+        let all = (0..map.linedefs.len() as u16).collect::<Vec<_>>();
+        map.blockmap = blockmap(1, 1, &[all], true);
+
+        map
+    }
+
+    /// Adds a one sided linedef with the sector on the right side
+    fn wall(&mut self, v1: usize, v2: usize, sector: usize) -> usize {
+        self.sidedefs.push(Sidedef {
+            x_offset: 0,
+            y_offset: 0,
+            upper: "-".to_string(),
+            lower: "-".to_string(),
+            middle: WALL.to_string(),
+            sector,
+        });
+
+        self.linedefs.push(Linedef {
+            v1,
+            v2,
+            flags: 1,
+            special: 0,
+            tag: 0,
+            front: Some(self.sidedefs.len() - 1),
+            back: None,
+        });
+        self.linedefs.len() - 1
+    }
+
+    /// Adds a two sided linedef with the front sector on the right side and
+    /// the sector before it on the left side
+    fn portal(&mut self, v1: usize, v2: usize, front: usize) -> usize {
+        for sector in [front, front - 1] {
+            self.sidedefs.push(Sidedef {
+                x_offset: 0,
+                y_offset: 0,
+                upper: WALL.to_string(),
+                lower: WALL.to_string(),
+                middle: "-".to_string(),
+                sector,
+            });
+        }
+
+        self.linedefs.push(Linedef {
+            v1,
+            v2,
+            flags: 4,
+            special: 0,
+            tag: 0,
+            front: Some(self.sidedefs.len() - 2),
+            back: Some(self.sidedefs.len() - 1),
+        });
+        self.linedefs.len() - 1
+    }
+
+    fn gl_vertex(&self, index: usize) -> u32 {
+        if index & GL_VERTEX == 0 {
+            return index as u32;
+        }
+
+        let flag = match self.gl_version {
+            1 | 2 => 1 << 15,
+            3 => 1 << 30,
+            _ => 1 << 31,
+        };
+        (index & !GL_VERTEX) as u32 | flag
+    }
+
+    /// The map marker followed by the lumps of the map and the GL lumps
+    pub fn lumps(&self) -> Lumps {
+        let side = |side: Option<usize>| side.map_or(-1, |s| s as i16);
+
+        let mut linedefs = Vec::new();
+        for line in &self.linedefs {
+            linedefs.extend_from_slice(&(line.v1 as u16).to_le_bytes());
+            linedefs.extend_from_slice(&(line.v2 as u16).to_le_bytes());
+            linedefs.extend_from_slice(&line.flags.to_le_bytes());
+            match self.format {
+                Format::Doom => {
+                    linedefs.extend_from_slice(&line.special.to_le_bytes());
+                    linedefs.extend_from_slice(&line.tag.to_le_bytes());
+                }
+                Format::Hexen => {
+                    linedefs.extend_from_slice(&[
+                        line.special as u8,
+                        line.tag as u8,
+                        0,
+                        0,
+                        0,
+                        0,
+                    ]);
+                }
+            }
+            linedefs.extend_from_slice(&side(line.front).to_le_bytes());
+            linedefs.extend_from_slice(&side(line.back).to_le_bytes());
+        }
+
+        let mut sidedefs = Vec::new();
+        for sidedef in &self.sidedefs {
+            sidedefs.extend_from_slice(&sidedef.x_offset.to_le_bytes());
+            sidedefs.extend_from_slice(&sidedef.y_offset.to_le_bytes());
+            sidedefs.extend_from_slice(&name8(&sidedef.upper));
+            sidedefs.extend_from_slice(&name8(&sidedef.lower));
+            sidedefs.extend_from_slice(&name8(&sidedef.middle));
+            sidedefs.extend_from_slice(&(sidedef.sector as u16).to_le_bytes());
+        }
+
+        let vertices = self
+            .vertices
+            .iter()
+            .flat_map(|&(x, y)| [x.to_le_bytes(), y.to_le_bytes()])
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let mut sectors = Vec::new();
+        for sector in &self.sectors {
+            sectors.extend_from_slice(&sector.floor.to_le_bytes());
+            sectors.extend_from_slice(&sector.ceiling.to_le_bytes());
+            sectors.extend_from_slice(&name8(&sector.floor_texture));
+            sectors.extend_from_slice(&name8(&sector.ceiling_texture));
+            sectors.extend_from_slice(&sector.light.to_le_bytes());
+            sectors.extend_from_slice(&sector.special.to_le_bytes());
+            sectors.extend_from_slice(&sector.tag.to_le_bytes());
+        }
+
+        let reject = vec![0; (self.sectors.len().pow(2)).div_ceil(8)];
+
+        let mut lumps = vec![
+            (self.name.clone(), Vec::new()),
+            ("THINGS".to_string(), Vec::new()),
+            ("LINEDEFS".to_string(), linedefs),
+            ("SIDEDEFS".to_string(), sidedefs),
+            ("VERTEXES".to_string(), vertices),
+            ("SEGS".to_string(), Vec::new()),
+            ("SSECTORS".to_string(), Vec::new()),
+            ("NODES".to_string(), Vec::new()),
+            ("SECTORS".to_string(), sectors),
+            ("REJECT".to_string(), reject),
+            ("BLOCKMAP".to_string(), self.blockmap.clone()),
+        ];
+        if self.format == Format::Hexen {
+            lumps.push(("BEHAVIOR".to_string(), Vec::new()));
+        }
+
+        let magic: &[u8] = match self.gl_version {
+            1 => b"",
+            2 => b"gNd2",
+            3 => b"gNd3",
+            5 => b"gNd5",
+            version => panic!("Unknown GL node version {}", version),
+        };
+        let wide = self.gl_version >= 3;
+
+        let mut gl_vert = magic.to_vec();
+        for &(x, y) in &self.gl_vertices {
+            if self.gl_version == 1 {
+                gl_vert.extend_from_slice(&(x as i16).to_le_bytes());
+                gl_vert.extend_from_slice(&(y as i16).to_le_bytes());
+            } else {
+                let fixed = |v: f32| ((v * 65536.0) as i32).to_le_bytes();
+                gl_vert.extend_from_slice(&fixed(x));
+                gl_vert.extend_from_slice(&fixed(y));
+            }
+        }
+
+        // NOTE: Only version 3 starts the segs and sub sectors with the
+        // magic
+        let mut gl_segs = Vec::new();
+        let mut gl_ssect = Vec::new();
+        if self.gl_version == 3 {
+            gl_segs.extend_from_slice(b"gNd3");
+            gl_ssect.extend_from_slice(b"gNd3");
+        }
+
+        for seg in &self.segs {
+            let linedef = seg.linedef.map_or(0xffff, |l| l as u16);
+            let v1 = self.gl_vertex(seg.v1);
+            let v2 = self.gl_vertex(seg.v2);
+            if wide {
+                let partner = seg.partner.map_or(u32::MAX, |p| p as u32);
+                gl_segs.extend_from_slice(&v1.to_le_bytes());
+                gl_segs.extend_from_slice(&v2.to_le_bytes());
+                gl_segs.extend_from_slice(&linedef.to_le_bytes());
+                gl_segs.extend_from_slice(&seg.side.to_le_bytes());
+                gl_segs.extend_from_slice(&partner.to_le_bytes());
+            } else {
+                let partner = seg.partner.map_or(u16::MAX, |p| p as u16);
+                gl_segs.extend_from_slice(&(v1 as u16).to_le_bytes());
+                gl_segs.extend_from_slice(&(v2 as u16).to_le_bytes());
+                gl_segs.extend_from_slice(&linedef.to_le_bytes());
+                gl_segs.extend_from_slice(&seg.side.to_le_bytes());
+                gl_segs.extend_from_slice(&partner.to_le_bytes());
+            }
+        }
+
+        for &(first, count) in &self.sub_sectors {
+            if wide {
+                gl_ssect.extend_from_slice(&(count as u32).to_le_bytes());
+                gl_ssect.extend_from_slice(&(first as u32).to_le_bytes());
+            } else {
+                gl_ssect.extend_from_slice(&(count as u16).to_le_bytes());
+                gl_ssect.extend_from_slice(&(first as u16).to_le_bytes());
+            }
+        }
+
+        // NOTE: Names longer than 5 characters doesn't fit after GL_
+        let marker = if self.name.len() <= 5 {
+            format!("GL_{}", self.name)
+        } else {
+            "GL_LEVEL".to_string()
+        };
+
+        lumps.extend([
+            (marker, Vec::new()),
+            ("GL_VERT".to_string(), gl_vert),
+            ("GL_SEGS".to_string(), gl_segs),
+            ("GL_SSECT".to_string(), gl_ssect),
+            ("GL_NODES".to_string(), Vec::new()),
+        ]);
+
+        lumps
+    }
+
+    /// The map marker followed by the TEXTMAP of the map, the ZNODES with
+    /// the magic (XGLN, XGL2 or XGL3 and the compressed ZGLN, ZGL2 or ZGL3)
+    /// and ENDMAP
+    pub fn udmf_lumps(&self, namespace: &str, magic: &[u8; 4]) -> Lumps {
+        let mut text = format!("namespace = \"{}\";\n", namespace);
+
+        for &(x, y) in &self.vertices {
+            text += &format!("vertex {{ x = {}.0; y = {}.0; }}\n", x, y);
+        }
+
+        for line in &self.linedefs {
+            text += &format!(
+                "linedef {{ v1 = {}; v2 = {}; sidefront = {}; ",
+                line.v1,
+                line.v2,
+                line.front.map_or(-1, |s| s as i64)
+            );
+            if let Some(back) = line.back {
+                text += &format!("sideback = {}; ", back);
+            }
+            for (bit, name) in [
+                (1, "blocking"),
+                (4, "twosided"),
+                (8, "dontpegtop"),
+                (16, "dontpegbottom"),
+            ] {
+                if line.flags & bit != 0 {
+                    text += &format!("{} = true; ", name);
+                }
+            }
+            text += &format!(
+                "special = {}; id = {}; }}\n",
+                line.special, line.tag
+            );
+        }
+
+        for sidedef in &self.sidedefs {
+            text += &format!(
+                "sidedef {{ offsetx = {}; offsety = {}; texturetop = \"{}\"; \
+                 texturebottom = \"{}\"; texturemiddle = \"{}\"; \
+                 sector = {}; }}\n",
+                sidedef.x_offset,
+                sidedef.y_offset,
+                sidedef.upper,
+                sidedef.lower,
+                sidedef.middle,
+                sidedef.sector
+            );
+        }
+
+        for sector in &self.sectors {
+            text += &format!(
+                "sector {{ heightfloor = {}; heightceiling = {}; \
+                 texturefloor = \"{}\"; textureceiling = \"{}\"; \
+                 lightlevel = {}; special = {}; id = {}; }}\n",
+                sector.floor,
+                sector.ceiling,
+                sector.floor_texture,
+                sector.ceiling_texture,
+                sector.light,
+                sector.special,
+                sector.tag
+            );
+        }
+
+        // NOTE: The GL vertices follows the vertices of the TEXTMAP
+        let vertex = |index: usize| {
+            if index & GL_VERTEX == 0 {
+                index as u32
+            } else {
+                (self.vertices.len() + (index & !GL_VERTEX)) as u32
+            }
+        };
+        let wide = magic[3] != b'N';
+
+        let mut nodes = Vec::new();
+        nodes.extend_from_slice(&(self.vertices.len() as u32).to_le_bytes());
+        nodes
+            .extend_from_slice(&(self.gl_vertices.len() as u32).to_le_bytes());
+        for &(x, y) in &self.gl_vertices {
+            nodes.extend_from_slice(&((x * 65536.0) as i32).to_le_bytes());
+            nodes.extend_from_slice(&((y * 65536.0) as i32).to_le_bytes());
+        }
+
+        nodes
+            .extend_from_slice(&(self.sub_sectors.len() as u32).to_le_bytes());
+        for &(_, count) in &self.sub_sectors {
+            nodes.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+
+        nodes.extend_from_slice(&(self.segs.len() as u32).to_le_bytes());
+        for seg in &self.segs {
+            let partner = seg.partner.map_or(u32::MAX, |p| p as u32);
+            nodes.extend_from_slice(&vertex(seg.v1).to_le_bytes());
+            nodes.extend_from_slice(&partner.to_le_bytes());
+            if wide {
+                let linedef = seg.linedef.map_or(u32::MAX, |l| l as u32);
+                nodes.extend_from_slice(&linedef.to_le_bytes());
+            } else {
+                let linedef = seg.linedef.map_or(u16::MAX, |l| l as u16);
+                nodes.extend_from_slice(&linedef.to_le_bytes());
+            }
+            nodes.push(seg.side as u8);
+        }
+
+        let mut znodes = magic.to_vec();
+        if magic[0] == b'Z' {
+            let mut encoder = flate2::write::ZlibEncoder::new(
+                znodes,
+                flate2::Compression::default(),
+            );
+            std::io::Write::write_all(&mut encoder, &nodes).unwrap();
+            znodes = encoder.finish().unwrap();
+        } else {
+            znodes.extend_from_slice(&nodes);
+        }
+
+        vec![
+            (self.name.clone(), Vec::new()),
+            ("TEXTMAP".to_string(), text.into_bytes()),
+            ("ZNODES".to_string(), znodes),
+            ("ENDMAP".to_string(), Vec::new()),
+        ]
+    }
+}
+
+/// The WAD at tests/data/two_rooms.wad, an IWAD with the game lumps and
+/// E1M1 with a room at floor 0 and ceiling 128 next to a room at floor 16
+/// and ceiling 112
+pub fn two_rooms_wad() -> Vec<u8> {
+    let map = MapBuilder::rooms("E1M1", &[(0, 128), (16, 112)]);
+    WadBuilder::new()
+        .lumps(game_lumps())
+        .lumps(map.lumps())
+        .build(b"IWAD")
+}
+
+pub const TWO_ROOMS_WAD: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/two_rooms.wad");
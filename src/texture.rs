@@ -1,14 +1,101 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use crate::wad::Wad;
+use crate::wad::{self, Wad};
 use crate::util;
 
 const MAX_PALETTE_COLORS: usize = 256;
-const MAX_COLOR_MAPS: usize = 34;
 
 const FLAT_TEXTURE_WIDTH: usize = 64;
 const FLAT_TEXTURE_HEIGHT: usize = 64;
 
+#[derive(Clone, Debug)]
+pub enum Error {
+    Wad(wad::Error),
+
+    MissingMarker(&'static str),
+    PaletteMissing,
+    ColorMapMissing,
+    PatchNamesMissing,
+
+    PatchDecode { name: String },
+    FlatDecode { name: String },
+    InvalidPalette,
+    InvalidColorMap,
+    InvalidPatchNames,
+    InvalidTextureLump(&'static str),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Wad(e) => write!(f, "{}", e),
+
+            Error::MissingMarker(marker) => {
+                write!(f, "the '{}' marker lump is missing", marker)
+            }
+            Error::PaletteMissing => write!(f, "the PLAYPAL lump is missing"),
+            Error::ColorMapMissing => {
+                write!(f, "the COLORMAP lump is missing")
+            }
+            Error::PatchNamesMissing => {
+                write!(f, "the PNAMES lump is missing")
+            }
+
+            Error::PatchDecode { name } => {
+                write!(f, "the patch '{}' is broken", name)
+            }
+            Error::FlatDecode { name } => {
+                write!(f, "the flat '{}' is broken", name)
+            }
+            Error::InvalidPalette => {
+                write!(f, "the PLAYPAL lump doesn't have any palettes")
+            }
+            Error::InvalidColorMap => {
+                write!(f, "the COLORMAP lump doesn't have any color maps")
+            }
+            Error::InvalidPatchNames => write!(f, "the PNAMES lump is broken"),
+            Error::InvalidTextureLump(name) => {
+                write!(f, "the {} lump is broken", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Wad(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<wad::Error> for Error {
+    fn from(e: wad::Error) -> Self {
+        Error::Wad(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads the zero padded 8 character name at the offset
+fn read_name(data: &[u8], offset: usize) -> Option<String> {
+    let name = data.get(offset..offset.checked_add(8)?)?;
+    let null_pos = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    let name = std::str::from_utf8(&name[..null_pos]).ok()?;
+    Some(name.to_string())
+}
+
 struct Patch {
     name: String,
     origin_x: isize,
@@ -19,7 +106,7 @@ struct TextureComposition {
     patches: Vec<Patch>,
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum TextureTyp {
     Flat,
     Patch,
@@ -31,6 +118,10 @@ pub struct Texture {
     width: usize,
     height: usize,
     pixels: Vec<u8>,
+    has_alpha: bool,
+    /// The masked flag of the TEXTURE1/TEXTURE2 definition, set on the
+    /// textures meant to be see-through (fences, grates)
+    masked: bool,
     composition: Option<TextureComposition>,
 }
 
@@ -41,11 +132,15 @@ impl Texture {
         height: usize,
         pixels: Vec<u8>,
     ) -> Self {
+        let has_alpha = pixels_have_alpha(&pixels);
+
         Self {
             typ,
             width,
             height,
             pixels,
+            has_alpha,
+            masked: false,
             composition: None,
         }
     }
@@ -65,6 +160,157 @@ impl Texture {
     pub fn pixels(&self) -> &[u8] {
         &self.pixels
     }
+
+    /// Returns true if the texture should be drawn with transparency, the
+    /// definition is masked or any of the pixels are transparent (the holes
+    /// in patches and the gaps between the patches of a texture)
+    pub fn is_transparent(&self) -> bool {
+        self.masked || self.has_alpha
+    }
+
+    /// Returns the RGBA color of the pixel, the pixels are stored row by row
+    /// with 4 bytes per pixel
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let index = (x + y * self.width) * 4;
+        self.pixels[index..index + 4].try_into().ok()
+    }
+
+    /// Encodes the texture as a PNG
+    pub fn to_png(&self) -> Vec<u8> {
+        util::write_texture_to_png(self)
+    }
+
+    /// Copies the pixels into an image from the image crate so it can be
+    /// processed further (resized, encoded to other formats, ...)
+    #[cfg(feature = "image")]
+    pub fn to_rgba_image(&self) -> image::RgbaImage {
+        image::RgbaImage::from_raw(
+            self.width as u32,
+            self.height as u32,
+            self.pixels.clone(),
+        )
+        .expect("Pixel buffer doesn't match the size of the texture")
+    }
+
+    /// Sets the RGBA color of the pixel, pixels outside the texture are
+    /// ignored
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let index = (x + y * self.width) * 4;
+        self.pixels[index..index + 4].copy_from_slice(&color);
+    }
+
+    /// Returns a copy of the rectangle of the texture, None if the
+    /// rectangle is empty or goes outside of the texture
+    pub fn crop(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<Texture> {
+        if width == 0
+            || height == 0
+            || x.checked_add(width)? > self.width
+            || y.checked_add(height)? > self.height
+        {
+            return None;
+        }
+
+        let row_size = width * 4;
+        let mut pixels = Vec::with_capacity(row_size * height);
+        for row in y..y + height {
+            let start = (x + row * self.width) * 4;
+            pixels.extend_from_slice(&self.pixels[start..start + row_size]);
+        }
+
+        Some(Texture::new(self.typ, width, height, pixels))
+    }
+
+    /// Returns a downscaled copy of the texture if the width or height is
+    /// larger then max_size, the aspect ratio is kept. None if the texture
+    /// already fits
+    pub fn fit_within(&self, max_size: usize) -> Option<Texture> {
+        let largest = self.width.max(self.height);
+        if largest <= max_size {
+            return None;
+        }
+
+        let max_size = max_size.max(1);
+        let width = (self.width * max_size / largest).max(1);
+        let height = (self.height * max_size / largest).max(1);
+        Some(self.resize_nearest(width, height))
+    }
+
+    /// Returns a copy of the texture with a border of pad pixels around it,
+    /// the border repeats the closest edge pixel of the texture
+    pub fn extend_edges(&self, pad: usize) -> Texture {
+        let width = self.width + pad * 2;
+        let height = self.height + pad * 2;
+
+        // NOTE: Nothing to repeat so the border is transparent
+        if self.width == 0 || self.height == 0 {
+            let pixels = vec![0; width * height * 4];
+            return Texture::new(self.typ, width, height, pixels);
+        }
+
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let src_y = y.saturating_sub(pad).min(self.height - 1);
+            for x in 0..width {
+                let src_x = x.saturating_sub(pad).min(self.width - 1);
+
+                let index = (src_x + src_y * self.width) * 4;
+                pixels.extend_from_slice(&self.pixels[index..index + 4]);
+            }
+        }
+
+        Texture::new(self.typ, width, height, pixels)
+    }
+
+    /// Returns a copy of the texture scaled to the size, nearest neighbor
+    /// is used so the pixels stay sharp
+    pub fn resize_nearest(&self, width: usize, height: usize) -> Texture {
+        // NOTE: Nothing to sample from so the result is transparent
+        if self.width == 0 || self.height == 0 {
+            let pixels = vec![0; width * height * 4];
+            return Texture::new(self.typ, width, height, pixels);
+        }
+
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let src_y = y * self.height / height;
+            for x in 0..width {
+                let src_x = x * self.width / width;
+
+                let index = (src_x + src_y * self.width) * 4;
+                pixels.extend_from_slice(&self.pixels[index..index + 4]);
+            }
+        }
+
+        Texture::new(self.typ, width, height, pixels)
+    }
+}
+
+/// Textures are equal if they have the same size and pixels, the type and
+/// the patches it was made from isn't compared
+impl PartialEq for Texture {
+    fn eq(&self, other: &Self) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.pixels == other.pixels
+    }
+}
+
+fn pixels_have_alpha(pixels: &[u8]) -> bool {
+    pixels.chunks_exact(4).any(|pixel| pixel[3] != 0xff)
 }
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -83,6 +329,17 @@ impl Palette {
     pub fn get(&self, index: usize) -> PaletteColor {
         self.colors[index]
     }
+
+    /// Returns a 16x16 texture with one pixel for each color, the colors
+    /// are laid out row by row so color i is at (i % 16, i / 16)
+    pub fn to_swatch(&self) -> Texture {
+        let mut pixels = Vec::with_capacity(MAX_PALETTE_COLORS * 4);
+        for color in &self.colors {
+            pixels.extend_from_slice(&[color.r, color.g, color.b, 0xff]);
+        }
+
+        Texture::new(TextureTyp::Texture, 16, 16, pixels)
+    }
 }
 
 #[derive(Clone)]
@@ -105,60 +362,74 @@ impl ColorMap {
     }
 }
 
-pub fn read_all_palettes(wad: &Wad) -> Option<Vec<Palette>> {
-    if let Ok(index) = wad.find_dir("PLAYPAL") {
-        let playpal = wad.read_dir(index).expect("Failed to get PLAYPAL data");
-        // One palette entry (R, G, B) 3 bytes
-        let num_colors = playpal.len() / 3;
-        // 256 palette entries per palette
-        let palette_count = num_colors / MAX_PALETTE_COLORS;
+/// Sidedefs use "-" (and sometimes an empty name) to say that there is no
+/// texture
+pub fn is_missing_name(name: &str) -> bool {
+    name.is_empty() || name == "-"
+}
 
-        let mut palettes = Vec::new();
+pub fn read_all_palettes(wad: &Wad) -> Result<Vec<Palette>> {
+    let playpal = wad.read_dir_by_name("PLAYPAL").map_err(|e| match e {
+        wad::Error::NoDirFound => Error::PaletteMissing,
+        e => Error::Wad(e),
+    })?;
+
+    // One palette entry (R, G, B) 3 bytes
+    let num_colors = playpal.len() / 3;
+    // 256 palette entries per palette
+    let palette_count = num_colors / MAX_PALETTE_COLORS;
+    if palette_count == 0 {
+        return Err(Error::InvalidPalette);
+    }
 
-        for palette in 0..palette_count {
-            let mut colors = [PaletteColor::default(); MAX_PALETTE_COLORS];
+    let mut palettes = Vec::new();
 
-            let data_start = palette * (256 * 3);
-            for color_index in 0..256 {
-                let start = color_index * 3 + data_start;
-                let r = playpal[start + 0];
-                let g = playpal[start + 1];
-                let b = playpal[start + 2];
-                colors[color_index] = PaletteColor { r, g, b };
-            }
+    for palette in 0..palette_count {
+        let mut colors = [PaletteColor::default(); MAX_PALETTE_COLORS];
 
-            palettes.push(Palette { colors });
+        let data_start = palette * (256 * 3);
+        for (color_index, color) in colors.iter_mut().enumerate() {
+            let start = color_index * 3 + data_start;
+            let r = playpal[start];
+            let g = playpal[start + 1];
+            let b = playpal[start + 2];
+            *color = PaletteColor { r, g, b };
         }
 
-        return Some(palettes);
+        palettes.push(Palette { colors });
     }
 
-    None
+    Ok(palettes)
 }
 
-pub fn read_all_color_maps(wad: &Wad) -> Option<Vec<ColorMap>> {
-    if let Ok(index) = wad.find_dir("COLORMAP") {
-        let color_map_table =
-            wad.read_dir(index).expect("Failed to get COLORMAP data");
+pub fn read_all_color_maps(wad: &Wad) -> Result<Vec<ColorMap>> {
+    let color_map_table =
+        wad.read_dir_by_name("COLORMAP").map_err(|e| match e {
+            wad::Error::NoDirFound => Error::ColorMapMissing,
+            e => Error::Wad(e),
+        })?;
+
+    // NOTE: Vanilla has 34 color maps but source ports (Boom) can have
+    // more or less, use all the complete ones inside the lump
+    let count = color_map_table.len() / MAX_PALETTE_COLORS;
+    if count == 0 {
+        return Err(Error::InvalidColorMap);
+    }
 
-        let mut color_maps = Vec::with_capacity(MAX_COLOR_MAPS);
+    let mut color_maps = Vec::with_capacity(count);
 
-        for color_map_index in 0..MAX_COLOR_MAPS {
-            let data_start = color_map_index * MAX_PALETTE_COLORS;
-            let mut color_map = [0usize; MAX_PALETTE_COLORS];
-            for index in 0..MAX_PALETTE_COLORS {
-                let start = index + data_start;
-                let palette_index = color_map_table[start] as usize;
-                color_map[index] = palette_index;
-            }
-
-            color_maps.push(ColorMap { map: color_map });
+    for table in color_map_table.chunks_exact(MAX_PALETTE_COLORS) {
+        let mut color_map = [0usize; MAX_PALETTE_COLORS];
+        for (index, &palette_index) in table.iter().enumerate() {
+            color_map[index] = palette_index as usize;
         }
 
-        return Some(color_maps);
+        color_maps.push(ColorMap { map: color_map });
     }
 
-    None
+    debug_assert_eq!(color_maps.len(), count);
+
+    Ok(color_maps)
 }
 
 pub fn read_flat_texture(
@@ -166,115 +437,185 @@ pub fn read_flat_texture(
     name: &str,
     color_map: &ColorMap,
     palette: &Palette,
-) -> Option<Texture> {
-    if let Ok(index) = wad.find_dir(name) {
-        let texture_data = wad.read_dir(index).ok()?;
-
-        let mut pixels =
-            vec![0u8; FLAT_TEXTURE_WIDTH * FLAT_TEXTURE_HEIGHT * 4];
-
-        for x in 0..FLAT_TEXTURE_WIDTH {
-            for y in 0..FLAT_TEXTURE_HEIGHT {
-                let start = x + y * FLAT_TEXTURE_WIDTH;
-                let index = texture_data[start];
-                let index = index as usize;
-
-                let color = color_map.get_color_from_palette(palette, index);
-
-                let img_index = x + y * FLAT_TEXTURE_WIDTH;
-                pixels[img_index * 4 + 0] = color.r;
-                pixels[img_index * 4 + 1] = color.g;
-                pixels[img_index * 4 + 2] = color.b;
-                pixels[img_index * 4 + 3] = 0xffu8;
-            }
-        }
+) -> Result<Texture> {
+    let texture_data = wad.read_dir_by_name(name)?;
 
-        return Some(Texture::new(
-            TextureTyp::Flat,
-            FLAT_TEXTURE_WIDTH,
-            FLAT_TEXTURE_HEIGHT,
-            pixels,
-        ));
+    if texture_data.len() < FLAT_TEXTURE_WIDTH * FLAT_TEXTURE_HEIGHT {
+        return Err(Error::FlatDecode {
+            name: name.to_string(),
+        });
     }
 
-    None
-}
+    let mut pixels = vec![0u8; FLAT_TEXTURE_WIDTH * FLAT_TEXTURE_HEIGHT * 4];
 
-pub fn read_patch_texture(
-    wad: &Wad,
-    name: &str,
-    color_map: &ColorMap,
-    palette: &Palette,
-) -> Option<Texture> {
-    if let Ok(index) = wad.find_dir(name) {
-        let texture_data = wad.read_dir(index).ok()?;
+    for x in 0..FLAT_TEXTURE_WIDTH {
+        for y in 0..FLAT_TEXTURE_HEIGHT {
+            let start = x + y * FLAT_TEXTURE_WIDTH;
+            let index = texture_data[start];
+            let index = index as usize;
 
-        let width = u16::from_le_bytes(texture_data[0..2].try_into().unwrap());
-        let height =
-            u16::from_le_bytes(texture_data[2..4].try_into().unwrap());
+            let color = color_map.get_color_from_palette(palette, index);
 
-        let _left_offset =
-            i16::from_le_bytes(texture_data[4..6].try_into().unwrap());
-        let _top_offset =
-            i16::from_le_bytes(texture_data[6..8].try_into().unwrap());
+            let img_index = x + y * FLAT_TEXTURE_WIDTH;
+            pixels[img_index * 4] = color.r;
+            pixels[img_index * 4 + 1] = color.g;
+            pixels[img_index * 4 + 2] = color.b;
+            pixels[img_index * 4 + 3] = 0xffu8;
+        }
+    }
 
-        let width = width as usize;
-        let height = height as usize;
+    Ok(Texture::new(
+        TextureTyp::Flat,
+        FLAT_TEXTURE_WIDTH,
+        FLAT_TEXTURE_HEIGHT,
+        pixels,
+    ))
+}
+
+/// A run of pixels inside a column of a patch, the pixels are indices into
+/// the palette
+#[derive(Clone, Debug)]
+pub struct Post {
+    /// The row of the first pixel
+    pub topdelta: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// A patch in the DOOM picture format, the columns are lists of posts and
+/// the pixels between the posts are transparent
+#[derive(Clone, Debug)]
+pub struct PatchData {
+    pub width: usize,
+    pub height: usize,
+    /// The offsets are only used by sprites and the status bar
+    pub left_offset: i16,
+    pub top_offset: i16,
+    pub columns: Vec<Vec<Post>>,
+}
 
-        let mut pixels = vec![0u8; width * height * 4];
+impl PatchData {
+    /// Parses the patch, None if the data is broken
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let width = read_u16(data, 0)? as usize;
+        let height = read_u16(data, 2)? as usize;
+        let left_offset = read_u16(data, 4)? as i16;
+        let top_offset = read_u16(data, 6)? as i16;
 
-        let start_offset = 8;
+        let mut columns = Vec::with_capacity(width);
         for x in 0..width {
-            let start = x * 4 + start_offset;
-            let offset = u32::from_le_bytes(
-                texture_data[start..start + 4].try_into().unwrap(),
-            );
-            let offset = offset as usize;
+            let mut offset = read_u32(data, 8 + x * 4)? as usize;
 
-            let mut new_offset = offset;
-            let mut y_offset = 0;
+            let mut posts = Vec::new();
+            let mut last_top = None;
             loop {
-                // TODO(patrik): Should we use topdelta to correct the offset
-                // inside the pixel buffer
-                let topdelta = texture_data[new_offset];
+                let topdelta = *data.get(offset)?;
                 if topdelta == 0xff {
                     break;
                 }
 
-                let length = texture_data[new_offset + 1];
-                let length = length as usize;
+                // NOTE: Tall patches (DeePsea) uses a topdelta that isn't
+                // below the last post to mean that it's relative to it
+                let topdelta = match last_top {
+                    Some(last) if topdelta as usize <= last => {
+                        last + topdelta as usize
+                    }
+                    _ => topdelta as usize,
+                };
+                last_top = Some(topdelta);
+
+                // NOTE: The post is the topdelta, the length, one unused
+                // byte, the pixels and one more unused byte
+                let length = *data.get(offset + 1)? as usize;
+                let start = offset + 3;
+                let pixels = data.get(start..start + length)?.to_vec();
+                posts.push(Post { topdelta, pixels });
+
+                offset += length + 4;
+            }
 
-                let start = new_offset + 2;
-                for data_offset in 0..length {
-                    let index = texture_data[start + data_offset];
-                    let index = index as usize;
+            columns.push(posts);
+        }
 
-                    let color =
-                        color_map.get_color_from_palette(palette, index);
+        Some(Self {
+            width,
+            height,
+            left_offset,
+            top_offset,
+            columns,
+        })
+    }
 
-                    let y = y_offset;
-                    let img_index = x + y * width;
-                    pixels[img_index * 4 + 0] = color.r;
-                    pixels[img_index * 4 + 1] = color.g;
-                    pixels[img_index * 4 + 2] = color.b;
-                    pixels[img_index * 4 + 3] = 0xffu8;
+    /// Draws the posts onto the texture with the top left corner of the
+    /// patch at (x, y), only the pixels covered by the posts are written so
+    /// the transparent parts of the patch doesn't overwrite the texture
+    fn draw(
+        &self,
+        texture: &mut Texture,
+        x: isize,
+        y: isize,
+        color_map: &ColorMap,
+        palette: &Palette,
+    ) {
+        for (column_x, posts) in self.columns.iter().enumerate() {
+            let dst_x = column_x as isize + x;
+            if dst_x < 0 {
+                continue;
+            }
 
-                    y_offset += 1;
-                }
+            for post in posts {
+                for (i, &index) in post.pixels.iter().enumerate() {
+                    // NOTE: Broken posts can go past the bottom of the patch
+                    let src_y = post.topdelta + i;
+                    if src_y >= self.height {
+                        break;
+                    }
+
+                    let dst_y = src_y as isize + y;
+                    if dst_y < 0 {
+                        continue;
+                    }
 
-                new_offset += length + 4;
+                    let color = color_map
+                        .get_color_from_palette(palette, index as usize);
+
+                    // NOTE: set_pixel skips the pixels outside the texture
+                    texture.set_pixel(
+                        dst_x as usize,
+                        dst_y as usize,
+                        [color.r, color.g, color.b, 0xff],
+                    );
+                }
             }
         }
-
-        return Some(Texture::new(TextureTyp::Patch, width, height, pixels));
     }
+}
 
-    None
+pub fn read_patch_data(wad: &Wad, name: &str) -> Result<PatchData> {
+    let data = wad.read_dir_by_name(name)?;
+    PatchData::parse(data).ok_or_else(|| Error::PatchDecode {
+        name: name.to_string(),
+    })
+}
+
+fn patch_to_texture(
+    patch: &PatchData,
+    color_map: &ColorMap,
+    palette: &Palette,
+) -> Texture {
+    let pixels = vec![0u8; patch.width * patch.height * 4];
+    let mut texture =
+        Texture::new(TextureTyp::Patch, patch.width, patch.height, pixels);
+    patch.draw(&mut texture, 0, 0, color_map, palette);
+    texture.has_alpha = pixels_have_alpha(&texture.pixels);
+
+    texture
 }
 
 #[derive(Copy, Clone, Debug)]
 struct PatchDef {
-    patch: usize,
+    /// Index into PNAMES, DOOM reads it as a signed short so broken WADs
+    /// can have negative indices
+    patch: i16,
     origin_x: i16,
     origin_y: i16,
 }
@@ -282,6 +623,7 @@ struct PatchDef {
 #[derive(Clone, Debug)]
 struct TextureDef {
     name: String,
+    masked: bool,
     width: usize,
     height: usize,
     patches: Vec<PatchDef>,
@@ -290,46 +632,38 @@ struct TextureDef {
 fn process_texture_lump(
     wad: &Wad,
     texture_defs: &mut Vec<TextureDef>,
+    lump_name: &'static str,
     index: usize,
-) -> Option<()> {
-    let data = wad.read_dir(index).unwrap();
+) -> Result<()> {
+    let data = wad.read_dir(index)?;
 
-    let num_textures = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let decode_error = || Error::InvalidTextureLump(lump_name);
+
+    let num_textures = read_u32(data, 0).ok_or_else(decode_error)?;
     let num_textures = num_textures as usize;
 
     let data_offset = 4;
     for i in 0..num_textures {
         let start = i * 4 + data_offset;
 
-        let offset =
-            u32::from_le_bytes(data[start..start + 4].try_into().unwrap());
+        let offset = read_u32(data, start).ok_or_else(decode_error)?;
         let offset = offset as usize;
 
-        let name = &data[offset + 0..offset + 8];
-        let null_pos = name.iter().position(|&c| c == 0).unwrap_or(name.len());
-        let name = &name[..null_pos];
-        let name = std::str::from_utf8(name).ok()?;
-        let name = String::from(name);
+        let name = read_name(data, offset).ok_or_else(decode_error)?;
 
-        let _masked =
-            u32::from_le_bytes(data[offset + 8..offset + 12].try_into().ok()?);
+        let masked = read_u32(data, offset + 8).ok_or_else(decode_error)?;
+        let masked = masked != 0;
 
-        let width = u16::from_le_bytes(
-            data[offset + 12..offset + 14].try_into().ok()?,
-        );
+        let width = read_u16(data, offset + 12).ok_or_else(decode_error)?;
         let width = width as usize;
-        let height = u16::from_le_bytes(
-            data[offset + 14..offset + 16].try_into().ok()?,
-        );
+        let height = read_u16(data, offset + 14).ok_or_else(decode_error)?;
         let height = height as usize;
 
-        let _column_directory = u32::from_le_bytes(
-            data[offset + 16..offset + 20].try_into().ok()?,
-        );
+        let _column_directory =
+            read_u32(data, offset + 16).ok_or_else(decode_error)?;
 
-        let patch_count = u16::from_le_bytes(
-            data[offset + 20..offset + 22].try_into().ok()?,
-        );
+        let patch_count =
+            read_u16(data, offset + 20).ok_or_else(decode_error)?;
         let patch_count = patch_count as usize;
 
         let mut patches = Vec::with_capacity(patch_count);
@@ -338,26 +672,20 @@ fn process_texture_lump(
         for pi in 0..patch_count {
             let start = pi * 10 + offset;
 
-            let origin_x = i16::from_le_bytes(
-                data[start + 0..start + 2].try_into().ok()?,
-            );
+            let origin_x =
+                read_u16(data, start).ok_or_else(decode_error)? as i16;
 
-            let origin_y = i16::from_le_bytes(
-                data[start + 2..start + 4].try_into().ok()?,
-            );
+            let origin_y =
+                read_u16(data, start + 2).ok_or_else(decode_error)? as i16;
 
-            let patch = u16::from_le_bytes(
-                data[start + 4..start + 6].try_into().ok()?,
-            );
-            let patch = patch as usize;
+            let patch =
+                read_u16(data, start + 4).ok_or_else(decode_error)? as i16;
 
-            let _step_dir = u16::from_le_bytes(
-                data[start + 6..start + 8].try_into().ok()?,
-            );
+            let _step_dir =
+                read_u16(data, start + 6).ok_or_else(decode_error)?;
 
-            let _color_map = u16::from_le_bytes(
-                data[start + 8..start + 10].try_into().ok()?,
-            );
+            let _color_map =
+                read_u16(data, start + 8).ok_or_else(decode_error)?;
 
             patches.push(PatchDef {
                 patch,
@@ -368,46 +696,76 @@ fn process_texture_lump(
 
         texture_defs.push(TextureDef {
             name,
+            masked,
             width,
             height,
             patches,
         });
     }
 
-    Some(())
+    Ok(())
 }
 
-fn read_texture_defs(wad: &Wad) -> Option<Vec<TextureDef>> {
+fn read_texture_defs(wad: &Wad) -> Result<Vec<TextureDef>> {
     let mut texture_defs = Vec::new();
 
     if let Ok(index) = wad.find_dir("TEXTURE1") {
-        process_texture_lump(wad, &mut texture_defs, index)?;
+        process_texture_lump(wad, &mut texture_defs, "TEXTURE1", index)?;
     }
 
     if let Ok(index) = wad.find_dir("TEXTURE2") {
-        process_texture_lump(wad, &mut texture_defs, index)?;
+        process_texture_lump(wad, &mut texture_defs, "TEXTURE2", index)?;
     }
 
-    Some(texture_defs)
+    Ok(texture_defs)
 }
 
 fn process_texture_defs(
     texture_loader: &TextureLoader,
-    patch_names: &Vec<String>,
-    texture_defs: &Vec<TextureDef>,
-) -> HashMap<String, Texture> {
-    let mut result = HashMap::new();
+    patch_names: &[String],
+    texture_defs: &[TextureDef],
+    warnings: &mut Vec<String>,
+) -> Vec<(String, Texture)> {
+    // NOTE: Keep the order of the definitions, the animations uses it to
+    // find the frames between the first and last frame
+    let mut result = Vec::new();
 
     for def in texture_defs {
         let mut patches = Vec::new();
-        let mut pixels = vec![0u8; def.width * def.height * 4];
+        let pixels = vec![0u8; def.width * def.height * 4];
+        let mut new_texture =
+            Texture::new(TextureTyp::Texture, def.width, def.height, pixels);
 
         for patch in &def.patches {
-            let patch_name = &patch_names[patch.patch];
+            // NOTE: Broken PWADs can reference patches that doesn't exist,
+            // skip those patches and build the rest of the texture
+            let patch_name = usize::try_from(patch.patch)
+                .ok()
+                .and_then(|index| patch_names.get(index));
+            let patch_name = if let Some(name) = patch_name {
+                name
+            } else {
+                warnings.push(format!(
+                    "Texture '{}' references patch #{} but there are only \
+                     {} patches",
+                    def.name,
+                    patch.patch,
+                    patch_names.len()
+                ));
+                continue;
+            };
 
-            let (_patch_texture_id, patch_texture) = texture_loader
-                .load_from_name(&patch_name)
-                .expect("Failed to read patch texture");
+            let patch_data = if let Some(patch_data) =
+                texture_loader.patches.get(&texture_key(patch_name))
+            {
+                patch_data
+            } else {
+                warnings.push(format!(
+                    "Texture '{}' uses the missing patch '{}'",
+                    def.name, patch_name
+                ));
+                continue;
+            };
 
             let patch_def = Patch {
                 name: patch_name.clone(),
@@ -416,90 +774,93 @@ fn process_texture_defs(
             };
             patches.push(patch_def);
 
-            let xoff = patch.origin_x as isize;
-            let yoff = patch.origin_y as isize;
-            for sy in 0..patch_texture.height() {
-                for sx in 0..patch_texture.width() {
-                    let source_index = sx + sy * patch_texture.width();
-
-                    let x = sx as isize + xoff;
-                    let y = sy as isize + yoff;
-
-                    if x < 0 || y < 0 {
-                        continue;
-                    }
-
-                    if x >= def.width as isize || y >= def.height as isize {
-                        continue;
-                    }
-
-                    let dest_index = (x as usize) + (y as usize) * def.width;
-
-                    let texture_pixels = patch_texture.pixels();
-                    pixels[dest_index * 4 + 0] =
-                        texture_pixels[source_index * 4 + 0];
-                    pixels[dest_index * 4 + 1] =
-                        texture_pixels[source_index * 4 + 1];
-                    pixels[dest_index * 4 + 2] =
-                        texture_pixels[source_index * 4 + 2];
-                    pixels[dest_index * 4 + 3] =
-                        texture_pixels[source_index * 4 + 3];
-                }
-            }
+            patch_data.draw(
+                &mut new_texture,
+                patch.origin_x as isize,
+                patch.origin_y as isize,
+                &texture_loader.color_map,
+                &texture_loader.palette,
+            );
         }
 
+        // NOTE: The texture starts out transparent so check again now when
+        // the patches covers it
+        new_texture.has_alpha = pixels_have_alpha(&new_texture.pixels);
+        new_texture.masked = def.masked;
+
         let composition = TextureComposition { patches };
-        let mut new_texture =
-            Texture::new(TextureTyp::Texture, def.width, def.height, pixels);
         new_texture.composition = Some(composition);
-        result.insert(def.name.clone(), new_texture);
+        result.push((def.name.clone(), new_texture));
     }
 
     result
 }
 
-fn read_patch_names(wad: &Wad) -> Option<Vec<String>> {
+fn read_patch_names(wad: &Wad) -> Result<Vec<String>> {
     if let Ok(index) = wad.find_dir("PNAMES") {
-        let data = wad.read_dir(index).ok()?;
+        let data = wad.read_dir(index)?;
 
         // NOTE(patrik):
         // https://doomwiki.org/wiki/PNAMES
         // "All integers are 4 bytes long in x86-style little-endian order.
         // Their values can never exceed 231-1,
         // since Doom reads them as signed ints."
-        let num_map_patches = u32::from_le_bytes(data[0..4].try_into().ok()?);
-        let num_map_patches = num_map_patches as usize;
+        let num_map_patches =
+            read_u32(data, 0).ok_or(Error::InvalidPatchNames)? as i32;
+        let num_map_patches = usize::try_from(num_map_patches)
+            .map_err(|_| Error::InvalidPatchNames)?;
 
-        let mut names = Vec::with_capacity(num_map_patches);
+        let mut names = Vec::with_capacity(num_map_patches.min(data.len()));
 
         let offset = 4;
         for i in 0..num_map_patches {
             const NAME_LENGTH: usize = 8;
             let start = i * NAME_LENGTH + offset;
-            let end = start + NAME_LENGTH;
 
-            let name = &data[start..end];
+            let name =
+                read_name(data, start).ok_or(Error::InvalidPatchNames)?;
 
-            // Find the first occurance of a null-terminator/0
-            let null_pos =
-                name.iter().position(|&c| c == 0).unwrap_or(name.len());
+            // NOTE: Some WADs has lowercase names (w94_1 instead of W94_1),
+            // the TextureLoader lookups are case insensitive
+            names.push(name);
+        }
 
-            // Name without the null-terminator
-            let name = &name[..null_pos];
+        return Ok(names);
+    }
 
-            // Convert to str
-            let name = std::str::from_utf8(&name[..null_pos]).ok()?;
+    Err(Error::PatchNamesMissing)
+}
 
-            // Add to the list
-            // TODO(patrik): Think this is a bug?
-            // Error because W94_1 was w94_1
-            names.push(String::from(name).to_uppercase());
-        }
+/// Decodes the textures with the names, the results are in the same order as
+/// the names so the output doesn't depend on the number of threads
+#[cfg(feature = "parallel")]
+fn decode_all<T, F>(names: &[String], decode: F) -> Vec<Result<T>>
+where
+    T: Send,
+    F: Fn(&str) -> Result<T> + Sync,
+{
+    use rayon::prelude::*;
+
+    names.par_iter().map(|name| decode(name)).collect()
+}
 
-        return Some(names);
-    }
+#[cfg(not(feature = "parallel"))]
+fn decode_all<T, F>(names: &[String], decode: F) -> Vec<Result<T>>
+where
+    F: Fn(&str) -> Result<T>,
+{
+    names.iter().map(|name| decode(name)).collect()
+}
 
-    None
+struct LoadedTexture {
+    name: String,
+    texture: Texture,
+}
+
+/// Texture names are case insensitive, the key is the uppercase version of
+/// the name used for the lookups and the name is kept for the output
+fn texture_key(name: &str) -> String {
+    name.to_ascii_uppercase()
 }
 
 pub struct TextureLoader {
@@ -507,7 +868,17 @@ pub struct TextureLoader {
     palette: Palette,
 
     missing_texture_id: usize,
-    textures: Vec<(String, Texture)>,
+    /// The key of the first texture in TEXTURE1, DOOM uses texture 0 for
+    /// "no texture" so it's never drawn (AASHITTY, AASTINKY)
+    placeholder_key: Option<String>,
+    textures: Vec<LoadedTexture>,
+    /// The posts of the patches used to build the composite textures
+    patches: HashMap<String, PatchData>,
+    /// Maps the type and the key of the texture names to the index inside
+    /// textures, a flat, a patch and a texture can have the same name
+    texture_index: HashMap<(TextureTyp, String), usize>,
+
+    warnings: Vec<String>,
 }
 
 impl TextureLoader {
@@ -515,30 +886,35 @@ impl TextureLoader {
         wad: &Wad,
         color_map: ColorMap,
         palette: Palette,
-    ) -> Option<Self> {
-        assert!(!wad.find_dir("P3_START").is_ok());
+    ) -> Result<Self> {
+        assert!(wad.find_dir("P3_START").is_err());
 
         let mut result = Self {
             color_map,
             palette,
 
             missing_texture_id: 0,
+            placeholder_key: None,
             textures: Vec::new(),
+            patches: HashMap::new(),
+            texture_index: HashMap::new(),
+
+            warnings: Vec::new(),
         };
 
         result.create_missing_texture();
-        result.load_all_patches(wad);
-        result.load_all_flats(wad);
-        result.load_all_textures(wad);
+        result.load_all_patches(wad)?;
+        result.load_all_flats(wad)?;
+        result.load_all_textures(wad)?;
 
-        Some(result)
+        Ok(result)
     }
 
     fn create_missing_texture(&mut self) {
         let mut pixels = vec![0; 2 * 2 * std::mem::size_of::<u32>()];
 
         let mut set_pixel = |index: usize, r, g, b| {
-            pixels[index * 4 + 0] = r;
+            pixels[index * 4] = r;
             pixels[index * 4 + 1] = g;
             pixels[index * 4 + 2] = b;
             pixels[index * 4 + 3] = 0xff;
@@ -555,15 +931,18 @@ impl TextureLoader {
         self.missing_texture_id = id;
     }
 
-    fn load_all_patches(&mut self, wad: &Wad) {
-        let start = wad.find_dir("P_START").unwrap();
+    fn load_all_patches(&mut self, wad: &Wad) -> Result<()> {
+        let start = wad
+            .find_dir("P_START")
+            .map_err(|_| Error::MissingMarker("P_START"))?;
         let start = start + 1;
-        let end = wad.find_dir("P_END").unwrap();
-        assert!(start < end);
+        let end = wad
+            .find_dir("P_END")
+            .map_err(|_| Error::MissingMarker("P_END"))?;
 
+        let mut names = Vec::new();
         for index in start..end {
-            // TODO(patrik): Remove unwarp
-            let entry = wad.read_dir_entry(index).unwrap();
+            let entry = wad.read_dir_entry(index)?;
 
             let null_pos = entry
                 .name
@@ -571,38 +950,52 @@ impl TextureLoader {
                 .position(|&c| c == 0)
                 .unwrap_or(entry.name.len());
             let entry_name = &entry.name[..null_pos];
-            let entry_name = std::str::from_utf8(&entry_name)
-                .expect("Failed to convert floor texture name to str");
+            let entry_name = std::str::from_utf8(entry_name)
+                .map_err(|_| wad::Error::BytesToStrFailed)?;
 
             let skip = ["P1_START", "P1_END", "P2_START", "P2_END"]
-                .iter()
-                .any(|s| *s == entry_name);
+                .contains(&entry_name);
             if skip {
                 continue;
             }
 
-            // TODO(patrik): Remove unwarp
-            let texture = read_patch_texture(
-                wad,
-                entry_name,
-                &self.color_map,
-                &self.palette,
-            )
-            .unwrap();
+            names.push(entry_name.to_string());
+        }
+
+        let patches = decode_all(&names, |name| {
+            let patch = read_patch_data(wad, name)?;
+            let texture =
+                patch_to_texture(&patch, &self.color_map, &self.palette);
+            Ok((patch, texture))
+        });
 
-            self.add_texture(entry_name, texture);
+        for (name, patch) in names.iter().zip(patches) {
+            match patch {
+                Ok((patch, texture)) => {
+                    self.patches.entry(texture_key(name)).or_insert(patch);
+                    self.add_texture(name, texture);
+                }
+                Err(e) => {
+                    self.warn(format!("Skipping the patch '{}': {}", name, e))
+                }
+            }
         }
+
+        Ok(())
     }
 
-    fn load_all_flats(&mut self, wad: &Wad) {
-        let start = wad.find_dir("F_START").unwrap();
+    fn load_all_flats(&mut self, wad: &Wad) -> Result<()> {
+        let start = wad
+            .find_dir("F_START")
+            .map_err(|_| Error::MissingMarker("F_START"))?;
         let start = start + 1;
-        let end = wad.find_dir("F_END").unwrap();
-        assert!(start < end);
+        let end = wad
+            .find_dir("F_END")
+            .map_err(|_| Error::MissingMarker("F_END"))?;
 
+        let mut names = Vec::new();
         for index in start..end {
-            // TODO(patrik): Remove unwarp
-            let entry = wad.read_dir_entry(index).unwrap();
+            let entry = wad.read_dir_entry(index)?;
 
             let null_pos = entry
                 .name
@@ -610,51 +1003,117 @@ impl TextureLoader {
                 .position(|&c| c == 0)
                 .unwrap_or(entry.name.len());
             let entry_name = &entry.name[..null_pos];
-            let entry_name = std::str::from_utf8(&entry_name)
-                .expect("Failed to convert floor texture name to str");
+            let entry_name = std::str::from_utf8(entry_name)
+                .map_err(|_| wad::Error::BytesToStrFailed)?;
 
             let skip = ["F1_START", "F1_END", "F2_START", "F2_END"]
-                .iter()
-                .any(|s| *s == entry_name);
+                .contains(&entry_name);
             if skip {
                 continue;
             }
 
-            // TODO(patrik): Remove unwarp
-            let texture = read_flat_texture(
-                wad,
-                entry_name,
-                &self.color_map,
-                &self.palette,
-            )
-            .unwrap();
+            names.push(entry_name.to_string());
+        }
 
-            self.add_texture(entry_name, texture);
+        let textures = decode_all(&names, |name| {
+            read_flat_texture(wad, name, &self.color_map, &self.palette)
+        });
+
+        for (name, texture) in names.iter().zip(textures) {
+            match texture {
+                Ok(texture) => self.add_texture(name, texture),
+                Err(e) => {
+                    self.warn(format!("Skipping the flat '{}': {}", name, e))
+                }
+            }
         }
-    }
 
-    fn load_all_textures(&mut self, wad: &Wad) {
-        let patch_names =
-            read_patch_names(&wad).expect("Failed to load patch names");
+        Ok(())
+    }
 
-        let texture_defs =
-            read_texture_defs(&wad).expect("Failed to read texture defs");
+    fn load_all_textures(&mut self, wad: &Wad) -> Result<()> {
+        let patch_names = read_patch_names(wad)?;
+        let texture_defs = read_texture_defs(wad)?;
+        self.placeholder_key =
+            texture_defs.first().map(|def| texture_key(&def.name));
+
+        let mut warnings = Vec::new();
+        let textures = process_texture_defs(
+            self,
+            &patch_names,
+            &texture_defs,
+            &mut warnings,
+        );
 
-        let textures = process_texture_defs(self, &patch_names, &texture_defs);
+        for warning in warnings {
+            self.warn(warning);
+        }
 
         for (name, texture) in textures {
             self.add_texture(&name, texture);
         }
+
+        Ok(())
     }
 
     fn add_texture(&mut self, name: &str, texture: Texture) {
-        if self.textures.iter().any(|t| t.0 == name) {
-            // TODO(patrik): Check texture if they are the same?
-            eprintln!("Warning: Duplicate texture '{}'", name);
+        let key = (texture.typ(), texture_key(name));
+        if let Some(&index) = self.texture_index.get(&key) {
+            // NOTE: The same texture can be defined more then once (an IWAD
+            // and PWAD with the same texture), only warn if they differ
+            if self.textures[index].texture != texture {
+                self.warn(format!("Duplicate texture '{}'", name));
+            }
             return;
         }
 
-        self.textures.push((name.to_string(), texture));
+        self.texture_index.insert(key, self.textures.len());
+        self.textures.push(LoadedTexture {
+            name: name.to_string(),
+            texture,
+        });
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+
+    /// Returns true if the name is the placeholder texture (the first
+    /// texture in TEXTURE1), sidedefs using it has no texture
+    pub fn is_placeholder(&self, name: &str) -> bool {
+        self.placeholder_key.as_deref() == Some(texture_key(name).as_str())
+    }
+
+    /// The names of all the textures of the type in the same order as the
+    /// ids, the missing texture is listed with the textures
+    pub fn names_of_type(&self, typ: TextureTyp) -> Vec<&str> {
+        self.textures
+            .iter()
+            .filter(|t| t.texture.typ() == typ)
+            .map(|t| t.name.as_str())
+            .collect()
+    }
+
+    /// The number of textures of all the types (the missing texture
+    /// included), the ids goes from 0 to len
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    pub fn count_of_type(&self, typ: TextureTyp) -> usize {
+        self.textures
+            .iter()
+            .filter(|t| t.texture.typ() == typ)
+            .count()
+    }
+
+    /// All the problems found while loading the textures
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
     }
 
     pub fn missing_texture(&self) -> (usize, &Texture) {
@@ -665,50 +1124,74 @@ impl TextureLoader {
     }
 
     pub fn load_from_id(&self, id: usize) -> Option<&Texture> {
-        self.textures.get(id).map(|o| &o.1)
+        self.textures.get(id).map(|o| &o.texture)
     }
 
     pub fn get_name_from_id(&self, id: usize) -> Option<&String> {
-        self.textures.get(id).map(|o| &o.0)
+        self.textures.get(id).map(|o| &o.name)
     }
 
+    /// Finds the texture with the name whatever the type is, when more
+    /// then one type has the name the type loaded first wins (patches, flats
+    /// and then textures)
     pub fn load_from_name(&self, name: &str) -> Option<(usize, &Texture)> {
-        for (index, t) in self.textures.iter().enumerate() {
-            if t.0 == name {
-                return Some((index, &t.1));
-            }
-        }
+        let key = texture_key(name);
+        let index = [TextureTyp::Patch, TextureTyp::Flat, TextureTyp::Texture]
+            .into_iter()
+            .find_map(|typ| self.texture_index.get(&(typ, key.clone())))?;
+        Some((*index, &self.textures[*index].texture))
+    }
 
-        None
+    /// Same as load_from_name but only looks at the textures of the type,
+    /// flats and textures can have the same name
+    pub fn find_id_of_type(
+        &self,
+        name: &str,
+        typ: TextureTyp,
+    ) -> Option<usize> {
+        self.texture_index.get(&(typ, texture_key(name))).copied()
     }
 
-    pub fn dump<P>(&self, output_dir: P)
+    /// Writes the textures the filter returns true for as images and a
+    /// json file with the composition of the textures
+    pub fn dump<P, F>(&self, output_dir: P, filter: F) -> std::io::Result<()>
     where
         P: AsRef<Path>,
+        F: Fn(&str, TextureTyp) -> bool,
     {
+        // NOTE: The caller creates the directory, writing textures.json
+        // fails when it's missing
         let output_dir = PathBuf::from(output_dir.as_ref());
-        assert!(output_dir.exists());
 
         use serde_json::{Value, json};
 
-        let mut result = Vec::new();
-        for texture in &self.textures {
-            if let Some(comp) = texture.1.composition.as_ref() {
-                let patches = comp
-                    .patches
-                    .iter()
-                    .map(|patch| {
-                        json!({
-                            "texture_name": patch.name,
-                            "origin_x": patch.origin_x,
-                            "origin_y": patch.origin_y,
-                        })
+        let textures = self
+            .textures
+            .iter()
+            .filter(|t| filter(&t.name, t.texture.typ()))
+            .collect::<Vec<_>>();
+
+        let patches_json = |comp: &TextureComposition| {
+            comp.patches
+                .iter()
+                .map(|patch| {
+                    json!({
+                        "texture_name": patch.name,
+                        "origin_x": patch.origin_x,
+                        "origin_y": patch.origin_y,
                     })
-                    .collect::<Value>();
+                })
+                .collect::<Value>()
+        };
+
+        let mut result = Vec::new();
+        for texture in &textures {
+            if let Some(comp) = texture.texture.composition.as_ref() {
+                let patches = patches_json(comp);
                 result.push(json!({
-                    "name": texture.0,
-                    "width": texture.1.width(),
-                    "height": texture.1.height(),
+                    "name": texture.name,
+                    "width": texture.texture.width(),
+                    "height": texture.texture.height(),
                     "patches": patches
                 }));
             }
@@ -718,34 +1201,601 @@ impl TextureLoader {
         let mut path = output_dir.clone();
         path.push("textures");
         path.set_extension("json");
-        util::write_binary_file(path, text.as_bytes());
+        util::write_binary_file(path, text.as_bytes())?;
 
-        let mut flat_output_dir = output_dir.clone();
-        flat_output_dir.push("flats");
+        let type_dir = |typ| match typ {
+            TextureTyp::Flat => "flats",
+            TextureTyp::Patch => "patches",
+            TextureTyp::Texture => "textures",
+        };
 
-        let mut patch_output_dir = output_dir.clone();
-        patch_output_dir.push("patches");
+        for typ in [TextureTyp::Flat, TextureTyp::Patch, TextureTyp::Texture] {
+            std::fs::create_dir_all(output_dir.join(type_dir(typ)))?;
+        }
 
-        let mut texture_output_dir = output_dir.clone();
-        texture_output_dir.push("textures");
+        // NOTE: The manifest lists every image written so tools doesn't
+        // need to guess the paths, the paths are relative to the dump
+        // directory
+        let mut manifest = Vec::new();
+        for texture in &textures {
+            let typ = texture.texture.typ();
+            let file_path = format!("{}/{}.png", type_dir(typ), texture.name);
+
+            let data = util::write_texture_to_png(&texture.texture);
+            util::write_binary_file(output_dir.join(&file_path), &data)?;
+
+            let mut entry = json!({
+                "name": texture.name,
+                "type": format!("{:?}", typ).to_lowercase(),
+                "width": texture.texture.width(),
+                "height": texture.texture.height(),
+                "path": file_path,
+            });
 
-        std::fs::create_dir_all(&flat_output_dir).unwrap();
-        std::fs::create_dir_all(&patch_output_dir).unwrap();
-        std::fs::create_dir_all(&texture_output_dir).unwrap();
+            if let Some(comp) = texture.texture.composition.as_ref() {
+                entry["patches"] = patches_json(comp);
+            }
 
-        for texture in &self.textures {
-            let output_dir = match texture.1.typ() {
-                TextureTyp::Flat => &flat_output_dir,
-                TextureTyp::Patch => &patch_output_dir,
-                TextureTyp::Texture => &texture_output_dir,
-            };
+            manifest.push(entry);
+        }
+
+        let text = serde_json::to_string_pretty(&manifest).unwrap();
+        let mut path = output_dir;
+        path.push("manifest");
+        path.set_extension("json");
+        util::write_binary_file(path, text.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Lumps, WadBuilder};
+
+    /// The game lumps with the lump replaced
+    fn replace_lump(mut lumps: Lumps, name: &str, data: Vec<u8>) -> Lumps {
+        let lump = lumps.iter_mut().find(|(n, _)| n == name).unwrap();
+        lump.1 = data;
+        lumps
+    }
+
+    fn texture_loader(wad: &Wad) -> Result<TextureLoader> {
+        let palettes = read_all_palettes(wad)?;
+        let color_maps = read_all_color_maps(wad)?;
+        TextureLoader::new(wad, color_maps[0].clone(), palettes[0].clone())
+    }
+
+    #[test]
+    fn get_and_set_pixel() {
+        let pixels = (0..2 * 3 * 4).map(|i| i as u8).collect();
+        let mut texture = Texture::new(TextureTyp::Flat, 2, 3, pixels);
+
+        assert_eq!(texture.get_pixel(0, 0), Some([0, 1, 2, 3]));
+        assert_eq!(texture.get_pixel(1, 2), Some([20, 21, 22, 23]));
+        assert_eq!(texture.get_pixel(2, 0), None);
+        assert_eq!(texture.get_pixel(0, 3), None);
+
+        texture.set_pixel(1, 0, [9, 9, 9, 9]);
+        assert_eq!(texture.get_pixel(1, 0), Some([9, 9, 9, 9]));
+
+        // NOTE: Outside of the texture so nothing changes
+        texture.set_pixel(2, 0, [0xff; 4]);
+        texture.set_pixel(0, 3, [0xff; 4]);
+        assert_eq!(texture.pixels().len(), 2 * 3 * 4);
+        assert!(texture.pixels().iter().all(|&p| p != 0xff));
+    }
+
+    #[test]
+    fn patch_index_out_of_range_is_skipped() {
+        let texture1 = testing::texture_lump(&[testing::TextureDef {
+            name: "BROKEN",
+            masked: false,
+            width: 64,
+            height: 64,
+            patches: &[(0, 0, 9999), (0, 0, 0)],
+        }]);
+        let lumps = replace_lump(testing::game_lumps(), "TEXTURE1", texture1);
+        let data = WadBuilder::new().lumps(lumps).build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        assert!(loader
+            .warnings()
+            .iter()
+            .any(|w| w.contains("BROKEN") && w.contains("#9999")));
+
+        // NOTE: The patch after the broken one is still drawn
+        let (_, texture) = loader.load_from_name("BROKEN").unwrap();
+        let patch = loader.load_from_name("PATCH1").unwrap().1;
+        assert!(texture == patch);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn rgba_image_has_the_pixels() {
+        let pixels = (0..3 * 2 * 4).map(|i| i as u8).collect();
+        let texture = Texture::new(TextureTyp::Flat, 3, 2, pixels);
+
+        let image = texture.to_rgba_image();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.get_pixel(2, 1).0, [20, 21, 22, 23]);
+        assert_eq!(image.get_pixel(2, 1).0, texture.get_pixel(2, 1).unwrap());
+    }
+
+    #[test]
+    fn colormap_with_40_entries() {
+        // NOTE: Each map moves the colors up by the number of the map so
+        // the maps can be told apart
+        let colormap = (0..40)
+            .flat_map(|map| (0..=255u8).map(move |i| i.wrapping_add(map)))
+            .collect::<Vec<_>>();
+        let lumps = replace_lump(testing::game_lumps(), "COLORMAP", colormap);
+        let data = WadBuilder::new().lumps(lumps).build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        let color_maps = read_all_color_maps(&wad).unwrap();
+        assert_eq!(color_maps.len(), 40);
+        assert_eq!(color_maps[0].get(10), 10);
+        assert_eq!(color_maps[39].get(10), 49);
+        assert_eq!(color_maps[39].get(255), 38);
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let data = WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let (id, _) = loader.load_from_name(testing::WALL).unwrap();
+        let (lower_id, _) = loader.load_from_name("wall").unwrap();
+        assert_eq!(lower_id, id);
+        let (floor_id, _) = loader.load_from_name(testing::FLOOR).unwrap();
+        assert_eq!(loader.load_from_name("Floor").unwrap().0, floor_id);
+
+        // NOTE: The name is kept as it was stored
+        assert_eq!(loader.get_name_from_id(id).unwrap(), testing::WALL);
+    }
+
+    /// A texture where the red and green of each pixel is its x and y
+    fn numbered(width: usize, height: usize) -> Texture {
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend_from_slice(&[x as u8, y as u8, 0, 0xff]);
+            }
+        }
+
+        Texture::new(TextureTyp::Texture, width, height, pixels)
+    }
+
+    #[test]
+    fn crop_and_upscale() {
+        let texture = numbered(4, 3);
+
+        let cropped = texture.crop(1, 2, 3, 1).unwrap();
+        assert_eq!((cropped.width(), cropped.height()), (3, 1));
+        assert_eq!(cropped.get_pixel(0, 0), Some([1, 2, 0, 0xff]));
+        assert_eq!(cropped.get_pixel(2, 0), Some([3, 2, 0, 0xff]));
+
+        assert!(texture.crop(2, 0, 3, 1).is_none());
+        assert!(texture.crop(0, 3, 1, 1).is_none());
+        assert!(texture.crop(0, 0, 0, 1).is_none());
+
+        let scaled = texture.resize_nearest(8, 6);
+        assert_eq!((scaled.width(), scaled.height()), (8, 6));
+        for y in 0..6 {
+            for x in 0..8 {
+                assert_eq!(
+                    scaled.get_pixel(x, y),
+                    texture.get_pixel(x / 2, y / 2)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn negative_and_past_the_end_patch_indices() {
+        // NOTE: PNAMES has 2 patches so 2 is one past the end
+        let texture1 = testing::texture_lump(&[testing::TextureDef {
+            name: "BROKEN",
+            masked: false,
+            width: 16,
+            height: 16,
+            patches: &[(0, 0, -1), (0, 0, 2)],
+        }]);
+        let lumps = replace_lump(testing::game_lumps(), "TEXTURE1", texture1);
+        let data = WadBuilder::new().lumps(lumps).build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let warnings = loader.warnings();
+        assert!(warnings.iter().any(|w| w.contains("#-1")));
+        assert!(warnings.iter().any(|w| w.contains("#2 ")));
+
+        // NOTE: Nothing is drawn so the texture is transparent
+        let (_, texture) = loader.load_from_name("BROKEN").unwrap();
+        assert!(texture.pixels().iter().all(|&p| p == 0));
+    }
+
+    /// The PNGs inside the type directories of a dump
+    fn dumped_pngs(dir: &Path) -> Vec<PathBuf> {
+        let mut result = Vec::new();
+        for typ in ["flats", "patches", "textures"] {
+            for entry in std::fs::read_dir(dir.join(typ)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|e| e == "png") {
+                    result.push(path);
+                }
+            }
+        }
 
-            let mut path = output_dir.clone();
-            path.push(&texture.0);
-            path.set_extension("png");
+        result.sort();
+        result
+    }
+
+    #[test]
+    fn dump_to_a_missing_directory_is_an_error() {
+        let data = WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let dir = testing::temp_dir("dump_missing").join("missing");
+        assert!(loader.dump(&dir, |_, _| true).is_err());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn dump_filter_matching_two_names() {
+        let data = WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let dir = testing::temp_dir("dump_filter");
+        loader
+            .dump(&dir, |name, _| util::glob_match("PATCH?", name))
+            .unwrap();
+
+        let pngs = dumped_pngs(&dir);
+        assert_eq!(
+            pngs,
+            [
+                dir.join("patches").join("PATCH1.png"),
+                dir.join("patches").join("PATCH2.png")
+            ]
+        );
+    }
 
-            let data = util::write_texture_to_png(&texture.1);
-            util::write_binary_file(path, &data);
+    #[test]
+    fn manifest_lists_every_png() {
+        let data = WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let dir = testing::temp_dir("dump_manifest");
+        loader.dump(&dir, |_, _| true).unwrap();
+
+        let text = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+        let manifest: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let manifest = manifest.as_array().unwrap();
+
+        let pngs = dumped_pngs(&dir);
+        assert_eq!(manifest.len(), pngs.len());
+        for entry in manifest {
+            let path = entry["path"].as_str().unwrap();
+            assert!(pngs.contains(&dir.join(path)), "{}", path);
         }
     }
+
+    #[test]
+    fn palette_swatch_pixels() {
+        let lumps = replace_lump(
+            testing::game_lumps(),
+            "PLAYPAL",
+            testing::playpal(3),
+        );
+        let data = WadBuilder::new().lumps(lumps).build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let palettes = read_all_palettes(&wad).unwrap();
+        assert_eq!(palettes.len(), 3);
+
+        let swatch = palettes[2].to_swatch();
+        assert_eq!((swatch.width(), swatch.height()), (16, 16));
+        for index in 0..=255u8 {
+            let [r, g, _] = testing::palette_color(index);
+            let (x, y) = (index as usize % 16, index as usize / 16);
+            assert_eq!(swatch.get_pixel(x, y), Some([r, g, 2, 0xff]));
+        }
+    }
+
+    #[test]
+    fn missing_playpal_and_colormap_are_errors() {
+        let lumps = testing::game_lumps()
+            .into_iter()
+            .filter(|(name, _)| name != "PLAYPAL" && name != "COLORMAP")
+            .collect();
+        let data = WadBuilder::new().lumps(lumps).build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        assert!(matches!(
+            read_all_palettes(&wad),
+            Err(Error::PaletteMissing)
+        ));
+        assert!(matches!(
+            read_all_color_maps(&wad),
+            Err(Error::ColorMapMissing)
+        ));
+    }
+
+    #[test]
+    fn duplicate_textures_only_warns_when_they_differ() {
+        let def = |name, patch| testing::TextureDef {
+            name,
+            masked: false,
+            width: 64,
+            height: 64,
+            patches: if patch == 0 {
+                &[(0, 0, 0)]
+            } else {
+                &[(0, 0, 1)]
+            },
+        };
+        let texture1 = testing::texture_lump(&[
+            def("AASHITTY", 0),
+            def("SAME", 0),
+            def("SAME", 0),
+            def("CONFLICT", 0),
+            def("CONFLICT", 1),
+        ]);
+        let lumps = replace_lump(testing::game_lumps(), "TEXTURE1", texture1);
+        let data = WadBuilder::new().lumps(lumps).build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let duplicates = loader
+            .warnings()
+            .iter()
+            .filter(|w| w.starts_with("Duplicate texture"))
+            .collect::<Vec<_>>();
+        assert_eq!(duplicates, ["Duplicate texture 'CONFLICT'"]);
+
+        // NOTE: The first definition is kept
+        let conflict = loader.load_from_name("CONFLICT").unwrap().1;
+        let patch = loader.load_from_name("PATCH1").unwrap().1;
+        assert!(conflict == patch);
+    }
+
+    #[test]
+    fn fit_within_the_max_size() {
+        let texture = numbered(512, 512);
+        let fitted = texture.fit_within(256).unwrap();
+        assert_eq!((fitted.width(), fitted.height()), (256, 256));
+        assert_eq!(fitted.get_pixel(10, 20), texture.get_pixel(20, 40));
+
+        let fitted = numbered(512, 128).fit_within(256).unwrap();
+        assert_eq!((fitted.width(), fitted.height()), (256, 64));
+
+        assert!(numbered(256, 100).fit_within(256).is_none());
+    }
+
+    #[test]
+    fn two_post_column() {
+        let data =
+            testing::patch(1, 8, |_| vec![(1, vec![10, 11]), (5, vec![20])]);
+        let patch = PatchData::parse(&data).unwrap();
+        let posts = &patch.columns[0];
+        assert_eq!(posts.len(), 2);
+        assert_eq!(
+            (posts[0].topdelta, &posts[0].pixels[..]),
+            (1, &[10, 11][..])
+        );
+        assert_eq!((posts[1].topdelta, &posts[1].pixels[..]), (5, &[20][..]));
+
+        let wad_data = WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .build(b"IWAD");
+        let wad = Wad::parse(&wad_data).unwrap();
+        let palettes = read_all_palettes(&wad).unwrap();
+        let color_maps = read_all_color_maps(&wad).unwrap();
+        let texture = patch_to_texture(&patch, &color_maps[0], &palettes[0]);
+        assert!(texture.has_alpha);
+
+        let opaque = |index| {
+            let [r, g, b] = testing::palette_color(index);
+            Some([r, g, b, 0xff])
+        };
+        let column =
+            (0..8).map(|y| texture.get_pixel(0, y)).collect::<Vec<_>>();
+        let transparent = Some([0; 4]);
+        assert_eq!(
+            column,
+            [
+                transparent,
+                opaque(10),
+                opaque(11),
+                transparent,
+                transparent,
+                opaque(20),
+                transparent,
+                transparent,
+            ]
+        );
+    }
+
+    #[test]
+    fn deepsea_topdelta_is_relative() {
+        // NOTE: 10 isn't below 200 so the second post starts at 210
+        let data =
+            testing::patch(1, 256, |_| vec![(200, vec![1]), (10, vec![2])]);
+        let patch = PatchData::parse(&data).unwrap();
+        let tops = patch.columns[0]
+            .iter()
+            .map(|p| p.topdelta)
+            .collect::<Vec<_>>();
+        assert_eq!(tops, [200, 210]);
+    }
+
+    #[test]
+    fn masked_definition_is_transparent() {
+        // NOTE: PATCH1 is solid so only the flag makes it transparent
+        let texture1 = testing::texture_lump(&[
+            testing::TextureDef {
+                name: "SOLID",
+                masked: false,
+                width: 16,
+                height: 16,
+                patches: &[(0, 0, 0)],
+            },
+            testing::TextureDef {
+                name: "FLAGGED",
+                masked: true,
+                width: 16,
+                height: 16,
+                patches: &[(0, 0, 0)],
+            },
+        ]);
+        let lumps = replace_lump(testing::game_lumps(), "TEXTURE1", texture1);
+        let data = WadBuilder::new().lumps(lumps).build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let (_, solid) = loader.load_from_name("SOLID").unwrap();
+        let (_, flagged) = loader.load_from_name("FLAGGED").unwrap();
+        assert!(!solid.is_transparent());
+        assert!(flagged.is_transparent());
+        assert_eq!(flagged.pixels(), solid.pixels());
+    }
+
+    #[test]
+    fn names_and_counts_of_each_type() {
+        let data = testing::two_rooms_wad();
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+
+        let names = |typ| {
+            let mut names = loader.names_of_type(typ);
+            names.sort();
+            names
+        };
+        assert_eq!(names(TextureTyp::Flat), ["CEIL", "FLOOR"]);
+        assert_eq!(names(TextureTyp::Patch), ["PATCH1", "PATCH2"]);
+        // NOTE: The missing texture is listed with the textures
+        assert_eq!(
+            names(TextureTyp::Texture),
+            ["AASHITTY", "MASKED", "MISSING_TEXTURE", "WALL"]
+        );
+
+        for typ in [TextureTyp::Flat, TextureTyp::Patch, TextureTyp::Texture] {
+            assert_eq!(loader.count_of_type(typ), names(typ).len());
+        }
+        assert_eq!(loader.len(), 8);
+    }
+
+    #[test]
+    fn flat_and_texture_with_the_same_name() {
+        let texture1 = testing::texture_lump(&[
+            testing::TextureDef {
+                name: "AASHITTY",
+                masked: false,
+                width: 64,
+                height: 64,
+                patches: &[(0, 0, 0)],
+            },
+            testing::TextureDef {
+                name: testing::FLOOR,
+                masked: false,
+                width: 16,
+                height: 16,
+                patches: &[(0, 0, 1)],
+            },
+        ]);
+        let lumps = replace_lump(testing::game_lumps(), "TEXTURE1", texture1);
+        let data = WadBuilder::new().lumps(lumps).build(b"IWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let loader = texture_loader(&wad).unwrap();
+        assert!(loader.warnings().is_empty(), "{:?}", loader.warnings());
+
+        let flat = loader.find_id_of_type("floor", TextureTyp::Flat).unwrap();
+        let texture = loader
+            .find_id_of_type("floor", TextureTyp::Texture)
+            .unwrap();
+        assert_ne!(flat, texture);
+        assert_eq!(loader.load_from_id(flat).unwrap().width(), 64);
+        assert_eq!(loader.load_from_id(texture).unwrap().width(), 16);
+        assert!(loader.find_id_of_type("floor", TextureTyp::Patch).is_none());
+
+        // NOTE: The flats are loaded before the textures
+        assert_eq!(loader.load_from_name("FLOOR").unwrap().0, flat);
+    }
+
+    /// An identity color map and a palette where color i is (i, i, i)
+    fn gray_palette() -> (ColorMap, Palette) {
+        let map = std::array::from_fn(|i| i);
+        let colors = std::array::from_fn(|i| {
+            let i = i as u8;
+            PaletteColor { r: i, g: i, b: i }
+        });
+
+        (ColorMap { map }, Palette { colors })
+    }
+
+    /// A 4x4 patch where every column has one post of the color from the
+    /// top and down to the length
+    fn column_patch(color: u8, length: usize) -> PatchData {
+        let post = Post {
+            topdelta: 0,
+            pixels: vec![color; length],
+        };
+
+        PatchData {
+            width: 4,
+            height: 4,
+            left_offset: 0,
+            top_offset: 0,
+            columns: vec![vec![post]; 4],
+        }
+    }
+
+    #[test]
+    fn transparent_patch_pixels_keep_the_patch_below() {
+        let (color_map, palette) = gray_palette();
+
+        // NOTE: The top patch is drawn last and its bottom half is a hole
+        let pixels = vec![0; 4 * 4 * 4];
+        let mut texture = Texture::new(TextureTyp::Texture, 4, 4, pixels);
+        column_patch(1, 4).draw(&mut texture, 0, 0, &color_map, &palette);
+        column_patch(2, 2).draw(&mut texture, 0, 0, &color_map, &palette);
+
+        assert_eq!(texture.get_pixel(1, 1), Some([2, 2, 2, 0xff]));
+        assert_eq!(texture.get_pixel(1, 3), Some([1, 1, 1, 0xff]));
+    }
+
+    #[test]
+    fn dumped_patch_keeps_the_transparency() {
+        let (color_map, palette) = gray_palette();
+        let texture =
+            patch_to_texture(&column_patch(5, 2), &color_map, &palette);
+
+        let data = util::write_texture_to_png(&texture);
+        let mut reader =
+            png::Decoder::new(data.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!((info.width, info.height), (4, 4));
+
+        // NOTE: The posts only covers the top half of the patch
+        let alpha = |x: usize, y: usize| pixels[(y * 4 + x) * 4 + 3];
+        assert_eq!(alpha(1, 0), 0xff);
+        assert_eq!(alpha(1, 1), 0xff);
+        assert_eq!(alpha(1, 2), 0);
+        assert_eq!(alpha(1, 3), 0);
+    }
 }
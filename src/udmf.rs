@@ -0,0 +1,381 @@
+//! Module to parse UDMF (TEXTMAP) lumps
+//!
+//! A TEXTMAP is a list of global assignments (namespace = "doom";) and
+//! blocks (vertex { x = 0.0; y = 0.0; }), the blocks are returned in the
+//! same order as they are defined so the index of a block is the same as
+//! the index the other blocks use to refer to it
+
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    UnexpectedCharacter { line: usize },
+    UnexpectedToken { line: usize },
+    InvalidNumber { line: usize },
+    UnterminatedString { line: usize },
+    UnexpectedEnd,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedCharacter { line } => {
+                write!(f, "unexpected character on line {}", line)
+            }
+            Error::UnexpectedToken { line } => {
+                write!(f, "unexpected token on line {}", line)
+            }
+            Error::InvalidNumber { line } => {
+                write!(f, "invalid number on line {}", line)
+            }
+            Error::UnterminatedString { line } => {
+                write!(f, "unterminated string on line {}", line)
+            }
+            Error::UnexpectedEnd => write!(f, "unexpected end of the text"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+}
+
+impl Value {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Value::Int(i) => Some(*i as f32),
+            Value::Float(f) => Some(*f as f32),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    /// The lowercase block type (vertex, linedef, sidedef, sector, ...)
+    pub kind: String,
+    /// The fields of the block, the keys are lowercase
+    pub fields: HashMap<String, Value>,
+}
+
+impl Block {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TextMap {
+    pub namespace: Option<String>,
+    pub blocks: Vec<Block>,
+}
+
+impl TextMap {
+    pub fn blocks_of_kind<'a>(
+        &'a self,
+        kind: &'a str,
+    ) -> impl Iterator<Item = &'a Block> + 'a {
+        self.blocks.iter().filter(move |b| b.kind == kind)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Identifier(String),
+    Value(Value),
+    Symbol(char),
+}
+
+struct Tokenizer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+            line: 1,
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match self.chars.peek() {
+                Some('\n') => {
+                    self.line += 1;
+                    self.chars.next();
+                }
+
+                Some(c) if c.is_whitespace() => {
+                    self.chars.next();
+                }
+
+                Some('/') => {
+                    let mut ahead = self.chars.clone();
+                    ahead.next();
+
+                    match ahead.peek() {
+                        Some('/') => {
+                            while let Some(c) = self.chars.peek() {
+                                if *c == '\n' {
+                                    break;
+                                }
+                                self.chars.next();
+                            }
+                        }
+
+                        Some('*') => {
+                            self.chars.next();
+                            self.chars.next();
+
+                            let mut prev = '\0';
+                            for c in self.chars.by_ref() {
+                                if c == '\n' {
+                                    self.line += 1;
+                                }
+
+                                if prev == '*' && c == '/' {
+                                    break;
+                                }
+
+                                prev = c;
+                            }
+                        }
+
+                        _ => return,
+                    }
+                }
+
+                _ => return,
+            }
+        }
+    }
+
+    fn read_number(&mut self) -> Result<Value> {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.peek() {
+            let is_exponent_sign = (c == '+' || c == '-')
+                && !text.starts_with("0x")
+                && text.ends_with(['e', 'E']);
+
+            if c.is_ascii_alphanumeric()
+                || c == '.'
+                || is_exponent_sign
+                || (text.is_empty() && (c == '+' || c == '-'))
+            {
+                text.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let error = Error::InvalidNumber { line: self.line };
+
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(&text)),
+        };
+
+        if let Some(hex) = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            let value = i64::from_str_radix(hex, 16).map_err(|_| error)?;
+            Ok(Value::Int(if negative { -value } else { value }))
+        } else if digits.contains(['.', 'e', 'E']) {
+            let value = text.parse::<f64>().map_err(|_| error)?;
+            Ok(Value::Float(value))
+        } else {
+            let value = text.parse::<i64>().map_err(|_| error)?;
+            Ok(Value::Int(value))
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        // Skip the '"'
+        self.chars.next();
+
+        let mut result = String::new();
+        loop {
+            let c = self
+                .chars
+                .next()
+                .ok_or(Error::UnterminatedString { line: self.line })?;
+
+            match c {
+                '"' => break,
+                '\\' => {
+                    let c = self.chars.next().ok_or(
+                        Error::UnterminatedString { line: self.line },
+                    )?;
+                    result.push(c);
+                }
+                '\n' => {
+                    self.line += 1;
+                    result.push(c);
+                }
+                _ => result.push(c),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>> {
+        self.skip_whitespace_and_comments();
+
+        let c = if let Some(&c) = self.chars.peek() {
+            c
+        } else {
+            return Ok(None);
+        };
+
+        let token = match c {
+            '{' | '}' | '=' | ';' => {
+                self.chars.next();
+                Token::Symbol(c)
+            }
+
+            '"' => Token::Value(Value::String(self.read_string()?)),
+
+            c if c.is_ascii_digit() || c == '+' || c == '-' || c == '.' => {
+                Token::Value(self.read_number()?)
+            }
+
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' {
+                        name.push(c);
+                        self.chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                // NOTE: Identifiers are case insensitive
+                let name = name.to_lowercase();
+                match name.as_str() {
+                    "true" => Token::Value(Value::Bool(true)),
+                    "false" => Token::Value(Value::Bool(false)),
+                    _ => Token::Identifier(name),
+                }
+            }
+
+            _ => return Err(Error::UnexpectedCharacter { line: self.line }),
+        };
+
+        Ok(Some(token))
+    }
+
+    fn expect_token(&mut self) -> Result<Token> {
+        self.next_token()?.ok_or(Error::UnexpectedEnd)
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<()> {
+        if self.expect_token()? == Token::Symbol(symbol) {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedToken { line: self.line })
+        }
+    }
+
+    fn expect_value(&mut self) -> Result<Value> {
+        match self.expect_token()? {
+            Token::Value(value) => Ok(value),
+            _ => Err(Error::UnexpectedToken { line: self.line }),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<TextMap> {
+    let mut tokenizer = Tokenizer::new(text);
+
+    let mut namespace = None;
+    let mut blocks = Vec::new();
+
+    while let Some(token) = tokenizer.next_token()? {
+        let name = match token {
+            Token::Identifier(name) => name,
+            _ => {
+                return Err(Error::UnexpectedToken {
+                    line: tokenizer.line,
+                })
+            }
+        };
+
+        match tokenizer.expect_token()? {
+            Token::Symbol('=') => {
+                let value = tokenizer.expect_value()?;
+                tokenizer.expect_symbol(';')?;
+
+                if name == "namespace" {
+                    namespace = value.as_str().map(|s| s.to_lowercase());
+                }
+            }
+
+            Token::Symbol('{') => {
+                let mut fields = HashMap::new();
+
+                loop {
+                    let field = match tokenizer.expect_token()? {
+                        Token::Symbol('}') => break,
+                        Token::Identifier(field) => field,
+                        _ => {
+                            return Err(Error::UnexpectedToken {
+                                line: tokenizer.line,
+                            })
+                        }
+                    };
+
+                    tokenizer.expect_symbol('=')?;
+                    let value = tokenizer.expect_value()?;
+                    tokenizer.expect_symbol(';')?;
+
+                    fields.insert(field, value);
+                }
+
+                blocks.push(Block { kind: name, fields });
+            }
+
+            _ => {
+                return Err(Error::UnexpectedToken {
+                    line: tokenizer.line,
+                })
+            }
+        }
+    }
+
+    Ok(TextMap { namespace, blocks })
+}
@@ -2,7 +2,7 @@
 
 use std::path::Path;
 use std::fs::File;
-use std::io::{Read, Write, BufWriter};
+use std::io::{Write, BufWriter};
 
 use crate::polygon::Vertex;
 use crate::texture::Texture;
@@ -10,35 +10,135 @@ use crate::texture::Texture;
 pub fn array_to_string(arr: &[u8]) -> String {
     let null_pos = arr.iter().position(|&c| c == 0).unwrap_or(arr.len());
     let s = &arr[..null_pos];
-    let s = std::str::from_utf8(&s).expect("Failed to convert array to str");
+    let s = std::str::from_utf8(s).expect("Failed to convert array to str");
 
-    s.to_string()
+    // NOTE: Some editors pads the names with spaces instead of zeros, the
+    // sidedefs then has "-       " instead of "-" for no texture
+    s.trim_end_matches(' ').to_string()
 }
 
-pub fn read_binary_file<P>(path: P) -> Vec<u8>
+/// 64-bit FNV-1a hash of the bytes, fast and good enough to notice that a
+/// file has changed (not for security)
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+pub fn write_binary_file<P>(path: P, data: &[u8]) -> std::io::Result<()>
 where
     P: AsRef<Path>,
 {
-    let mut file = File::open(path).unwrap();
+    let mut file = File::create(path)?;
+    file.write_all(data)
+}
 
-    let mut result = Vec::new();
-    file.read_to_end(&mut result).unwrap();
+/// Matches the name against a glob pattern where * matches any number of
+/// characters and ? matches one character, the match ignores the case like
+/// the lump names
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+
+    let mut p = 0;
+    let mut n = 0;
+    // NOTE: The position of the last * and the name position it matched
+    // to, used to backtrack when the rest doesn't match
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
 
-    result
+    pattern[p..].iter().all(|&c| c == b'*')
 }
 
-pub fn write_binary_file<P>(path: P, data: &[u8])
-where
-    P: AsRef<Path>,
-{
-    let mut file = File::create(path).unwrap();
-    file.write_all(data).unwrap();
+/// The characters of code page 437 (the VGA text mode font), the first 32
+/// are the glyphs shown for the control characters
+const CP437: &str = "\0☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼ !\"#$%&'()*+,-./\
+    0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmno\
+    pqrstuvwxyz{|}~⌂ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩\
+    ≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+pub const ENDOOM_WIDTH: usize = 80;
+pub const ENDOOM_HEIGHT: usize = 25;
+
+/// Converts the ENDOOM lump (80x25 VGA text cells, a character and an
+/// attribute byte each) to text with ANSI color escapes, None if the lump
+/// is too small
+pub fn endoom_to_ansi(data: &[u8]) -> Option<String> {
+    // NOTE: The VGA colors are in the order black, blue, green, cyan, red,
+    // magenta, brown and gray but ANSI has red and blue swapped (and the
+    // same for cyan and brown)
+    const VGA_TO_ANSI: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+    let data = data.get(0..ENDOOM_WIDTH * ENDOOM_HEIGHT * 2)?;
+
+    let cp437 = CP437.chars().collect::<Vec<_>>();
+    debug_assert_eq!(cp437.len(), 256);
+
+    let mut result = String::new();
+    for row in data.chunks_exact(ENDOOM_WIDTH * 2) {
+        let mut last_attribute = None;
+
+        for cell in row.chunks_exact(2) {
+            let (c, attribute) = (cell[0], cell[1]);
+
+            if last_attribute != Some(attribute) {
+                // NOTE: The low 4 bits are the foreground (the top bit is
+                // the bright version), the next 3 bits are the background
+                // and the top bit makes the text blink
+                let fg = attribute & 0x0f;
+                let bg = (attribute >> 4) & 0x07;
+
+                let fg_code = if fg & 0x08 != 0 { 90 } else { 30 }
+                    + VGA_TO_ANSI[(fg & 0x07) as usize];
+                let bg_code = 40 + VGA_TO_ANSI[bg as usize];
+
+                result.push_str(&format!("\x1b[0;{};{}", fg_code, bg_code));
+                if attribute & 0x80 != 0 {
+                    result.push_str(";5");
+                }
+                result.push('m');
+
+                last_attribute = Some(attribute);
+            }
+
+            // NOTE: The null character is shown as empty space
+            result.push(if c == 0 { ' ' } else { cp437[c as usize] });
+        }
+
+        result.push_str("\x1b[0m\n");
+    }
+
+    Some(result)
 }
 
 pub fn write_texture_to_png(texture: &Texture) -> Vec<u8> {
     let mut result = Vec::new();
     {
-        let ref mut file_writer = BufWriter::new(&mut result);
+        let file_writer = BufWriter::new(&mut result);
 
         let mut encoder = png::Encoder::new(
             file_writer,
@@ -49,7 +149,7 @@ pub fn write_texture_to_png(texture: &Texture) -> Vec<u8> {
         encoder.set_depth(png::BitDepth::Eight);
 
         let mut writer = encoder.write_header().unwrap();
-        writer.write_image_data(&texture.pixels()).unwrap();
+        writer.write_image_data(texture.pixels()).unwrap();
     }
 
     result
@@ -93,7 +193,7 @@ pub fn line_angle(a: &Vertex, b: &Vertex) -> f32 {
 }
 
 pub fn point_on_line(a: &Vertex, b: &Vertex, c: &Vertex) -> bool {
-    return (line_angle(a, b) - line_angle(b, c)).abs() < 0.05;
+    (line_angle(a, b) - line_angle(b, c)).abs() < 0.05
 }
 
 pub fn cleanup_lines(verts: &mut Vec<Vertex>) {
@@ -107,3 +207,36 @@ pub fn cleanup_lines(verts: &mut Vec<Vertex>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endoom_colors_and_characters() {
+        let mut data = [b' ', 0x07].repeat(ENDOOM_WIDTH * ENDOOM_HEIGHT);
+        // NOTE: Bright yellow on blue, then a blinking red smiley
+        data[0..6].copy_from_slice(&[b'H', 0x1e, b'i', 0x1e, 0x01, 0x84]);
+
+        let gray_row = format!("\x1b[0;37;40m{}\x1b[0m\n", " ".repeat(80));
+        let first_row = format!(
+            "\x1b[0;93;44mHi\x1b[0;31;40;5m☺\x1b[0;37;40m{}\x1b[0m\n",
+            " ".repeat(77)
+        );
+        let expected = first_row + &gray_row.repeat(24);
+        assert_eq!(endoom_to_ansi(&data).unwrap(), expected);
+
+        assert!(endoom_to_ansi(&data[..data.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn dash_name_is_kept() {
+        assert_eq!(array_to_string(b"-\0\0\0\0\0\0\0"), "-");
+        assert_eq!(array_to_string(b"-       "), "-");
+        assert_eq!(array_to_string(b"\0\0\0\0\0\0\0\0"), "");
+        assert_eq!(array_to_string(b"STARTAN3"), "STARTAN3");
+        assert!(crate::texture::is_missing_name(&array_to_string(
+            b"-\0\0\0\0\0\0\0"
+        )));
+    }
+}
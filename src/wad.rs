@@ -2,41 +2,202 @@
 
 #![allow(dead_code)]
 
+use std::io::Read;
+
+use crate::udmf;
 use crate::util;
 use bitflags::bitflags;
 
 bitflags! {
-    pub struct LinedefFlags: usize {
+    pub struct LinedefFlags: u16 {
         const IMPASSABLE = 0x0001;
+        const BLOCKS_MONSTERS = 0x0002;
         const TWO_SIDED = 0x0004;
         const UPPER_TEXTURE_UNPEGGED = 0x0008;
         const LOWER_TEXTURE_UNPEGGED = 0x0010;
         const SECRET = 0x0020;
-        const BLOCKS_SOUND = 0x0020;
+        const BLOCKS_SOUND = 0x0040;
         const NEVER_SHOW_ON_AUTOMAP = 0x0080;
         const ALWAYS_SHOWS_ON_AUTOMAP = 0x0100;
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum Error {
     ArrayConvertionFailed,
     ConvertToUsizeFailed,
     ConvertToF32Failed,
     BytesToStrFailed,
 
+    TooSmall,
     UnknownMagic([u8; 4]),
     NoDirFound,
+    MissingGlNodes,
     IndexOutOfRange,
+    LumpOutOfBounds {
+        index: usize,
+    },
+
+    FrontSideMismatch {
+        side: usize,
+    },
+    BackSideMismatch {
+        side: usize,
+    },
+    UnknownSide {
+        side: usize,
+    },
+
+    InvalidBlockMap,
+    InvalidLumpSize {
+        lump: &'static str,
+        size: usize,
+        record_size: usize,
+    },
+
+    TextMapParseFailed(udmf::Error),
+    TextMapMissingField {
+        kind: &'static str,
+        field: &'static str,
+    },
+    UnsupportedNamespace(String),
+    InvalidZNodes,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ArrayConvertionFailed => {
+                write!(f, "failed to convert bytes to an array")
+            }
+            Error::ConvertToUsizeFailed => {
+                write!(f, "failed to convert a value to usize")
+            }
+            Error::ConvertToF32Failed => {
+                write!(f, "failed to convert a value to f32")
+            }
+            Error::BytesToStrFailed => {
+                write!(f, "failed to convert bytes to a string")
+            }
+
+            Error::TooSmall => write!(f, "the file is too small to be a WAD"),
+            Error::UnknownMagic(magic) => write!(
+                f,
+                "unknown WAD magic '{}' (expected IWAD or PWAD)",
+                String::from_utf8_lossy(magic)
+            ),
+            Error::NoDirFound => write!(f, "the lump doesn't exist"),
+            Error::MissingGlNodes => write!(
+                f,
+                "the map has no GL nodes (build them with glBSP or ZDBSP)"
+            ),
+            Error::IndexOutOfRange => write!(f, "index is out of range"),
+            Error::LumpOutOfBounds { index } => {
+                write!(f, "lump #{} is outside of the file", index)
+            }
+
+            Error::FrontSideMismatch { side } => write!(
+                f,
+                "a segment is on side {} (front) of a linedef without a \
+                 front sidedef",
+                side
+            ),
+            Error::BackSideMismatch { side } => write!(
+                f,
+                "a segment is on side {} (back) of a linedef without a back \
+                 sidedef",
+                side
+            ),
+            Error::UnknownSide { side } => {
+                write!(f, "a segment has the unknown side {}", side)
+            }
+
+            Error::InvalidBlockMap => write!(f, "the BLOCKMAP is invalid"),
+            Error::InvalidLumpSize {
+                lump,
+                size,
+                record_size,
+            } => write!(
+                f,
+                "the {} lump is {} bytes which isn't a multiple of the \
+                 {} byte records",
+                lump, size, record_size
+            ),
+
+            Error::TextMapParseFailed(e) => {
+                write!(f, "failed to parse the TEXTMAP: {}", e)
+            }
+            Error::TextMapMissingField { kind, field } => write!(
+                f,
+                "a {} in the TEXTMAP is missing the '{}' field",
+                kind, field
+            ),
+            Error::UnsupportedNamespace(namespace) => write!(
+                f,
+                "the TEXTMAP namespace '{}' isn't supported",
+                namespace
+            ),
+            Error::InvalidZNodes => write!(f, "the ZNODES lump is invalid"),
+        }
+    }
+}
 
-    FrontSideMismatch { side: usize },
-    BackSideMismatch { side: usize },
-    UnknownSide { side: usize },
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::TextMapParseFailed(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-const VERT_IS_GL: usize = 1 << 15;
+/// Set on the vertex indices of the segments that are GL vertices, the
+/// flag used by the GL node version is converted to this when loading
+const VERT_IS_GL: usize = 1 << 31;
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data.get(offset..offset + 2).ok_or(Error::TooSmall)?;
+    Ok(u16::from_le_bytes(
+        bytes.try_into().map_err(|_| Error::ArrayConvertionFailed)?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).ok_or(Error::TooSmall)?;
+    Ok(u32::from_le_bytes(
+        bytes.try_into().map_err(|_| Error::ArrayConvertionFailed)?,
+    ))
+}
+
+/// Splits the GL lump into the records, version 3 starts the lump with the
+/// magic that is skipped
+///
+/// A lump that isn't a whole number of records is an error, it usually
+/// means the version was detected wrong and every read would be misaligned
+fn gl_records<'a>(
+    data: &'a [u8],
+    lump: &'static str,
+    version: GlNodeVersion,
+    record_size: usize,
+) -> Result<std::slice::ChunksExact<'a, u8>> {
+    let data = if version == GlNodeVersion::V3 && data.starts_with(b"gNd3") {
+        &data[4..]
+    } else {
+        data
+    };
+
+    if data.len() % record_size != 0 {
+        return Err(Error::InvalidLumpSize {
+            lump,
+            size: data.len(),
+            record_size,
+        });
+    }
+
+    Ok(data.chunks_exact(record_size))
+}
 
 #[derive(Copy, Clone, Debug)]
 pub struct Dir {
@@ -45,6 +206,17 @@ pub struct Dir {
     pub name: [u8; 8],
 }
 
+impl Dir {
+    pub fn size(&self) -> usize {
+        self.data_size
+    }
+
+    /// The name of the lump without the padding
+    pub fn lump_name(&self) -> String {
+        util::array_to_string(&self.name)
+    }
+}
+
 pub struct Wad<'a> {
     bytes: &'a [u8],
 
@@ -54,8 +226,14 @@ pub struct Wad<'a> {
 
 impl<'a> Wad<'a> {
     pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        // NOTE: The header is the magic, the number of directory entries and
+        // the offset to the directory (4 bytes each)
+        if bytes.len() < 12 {
+            return Err(Error::TooSmall);
+        }
+
         let magic = &bytes[0..4];
-        if magic != b"IWAD" {
+        if magic != b"IWAD" && magic != b"PWAD" {
             let magic: [u8; 4] =
                 magic.try_into().map_err(|_| Error::ArrayConvertionFailed)?;
             return Err(Error::UnknownMagic(magic));
@@ -93,7 +271,10 @@ impl<'a> Wad<'a> {
         }
 
         let start = self.dir_start + index * 16;
-        let bytes = &self.bytes[start..start + 16];
+        let bytes = self
+            .bytes
+            .get(start..start + 16)
+            .ok_or(Error::IndexOutOfRange)?;
 
         let data_offset = i32::from_le_bytes(
             bytes[0..4]
@@ -124,19 +305,62 @@ impl<'a> Wad<'a> {
         })
     }
 
+    pub fn num_dirs(&self) -> usize {
+        self.num_dirs
+    }
+
+    /// Returns a hash of the whole file, used to know if a cached
+    /// conversion of the WAD is out of date
+    pub fn checksum(&self) -> u64 {
+        util::fnv1a_64(self.bytes)
+    }
+
+    /// Returns the file name of the game the IWAD is from (DOOM2.WAD, ...),
+    /// None for a PWAD or an unknown IWAD
+    ///
+    /// NOTE: The game is detected from lumps that only exists in that game
+    /// so modified IWADs are detected as well, Heretic and Hexen has
+    /// ENDTEXT and ENDSTRF instead of ENDOOM
+    pub fn iwad_name(&self) -> Option<&'static str> {
+        if !self.is_iwad() || self.find_dir("ENDOOM").is_err() {
+            return None;
+        }
+
+        const GAMES: &[(&str, &str)] = &[
+            ("FREEDOOM", "FREEDOOM.WAD"),
+            ("REDTNT2", "TNT.WAD"),
+            ("CAMO1", "PLUTONIA.WAD"),
+            ("MAP01", "DOOM2.WAD"),
+            ("E4M1", "DOOM.WAD"),
+            ("E2M1", "DOOM.WAD"),
+            ("E1M1", "DOOM1.WAD"),
+        ];
+
+        GAMES
+            .iter()
+            .find(|(lump, _)| self.find_dir(lump).is_ok())
+            .map(|(_, name)| *name)
+    }
+
+    /// Returns true for an IWAD (a complete game), false for a PWAD that
+    /// needs to be loaded on top of an IWAD
+    pub fn is_iwad(&self) -> bool {
+        &self.bytes[0..4] == b"IWAD"
+    }
+
     pub fn find_dir(&self, name: &str) -> Result<usize> {
-        for index in 0..self.num_dirs {
-            let dir_entry = self.read_dir_entry(index)?;
+        self.find_dir_after(0, name)
+    }
 
-            let find_zero = |n: &[u8]| {
-                for i in 0..n.len() {
-                    if n[i] == 0 {
-                        return i;
-                    }
-                }
+    /// Finds the first lump with the name starting the search at the index
+    /// start, used to find lumps that shares the name with lumps of other
+    /// maps (THINGS, LINEDEFS, ...)
+    pub fn find_dir_after(&self, start: usize, name: &str) -> Result<usize> {
+        for index in start..self.num_dirs {
+            let dir_entry = self.read_dir_entry(index)?;
 
-                n.len()
-            };
+            let find_zero =
+                |n: &[u8]| n.iter().position(|&c| c == 0).unwrap_or(n.len());
 
             let len = find_zero(&dir_entry.name);
             let dir_name = std::str::from_utf8(&dir_entry.name[0..len])
@@ -149,19 +373,205 @@ impl<'a> Wad<'a> {
         Err(Error::NoDirFound)
     }
 
+    /// Finds the first lump with the name and returns the data of it
+    pub fn read_dir_by_name(&self, name: &str) -> Result<&[u8]> {
+        let index = self.find_dir(name)?;
+        self.read_dir(index)
+    }
+
+    /// Finds all the map markers (the lumps followed by THINGS, LINEDEFS or
+    /// TEXTMAP) and detects the format and the GL nodes of the maps
+    pub fn iter_maps(&self) -> Result<Vec<MapEntry>> {
+        let mut result = Vec::new();
+
+        for index in 0..self.num_dirs.saturating_sub(1) {
+            let next =
+                util::array_to_string(&self.read_dir_entry(index + 1)?.name);
+            if next != "THINGS" && next != "LINEDEFS" && next != "TEXTMAP" {
+                continue;
+            }
+
+            // NOTE: Maps without THINGS starts with LINEDEFS, so THINGS
+            // followed by LINEDEFS is part of a map and not a marker
+            let name =
+                util::array_to_string(&self.read_dir_entry(index)?.name);
+            if MAP_LUMP_NAMES.contains(&name.as_str()) {
+                continue;
+            }
+
+            let format = Map::detect_format(self, index)?;
+            let gl_node_version = Map::detect_gl_nodes(self, index, format)?;
+
+            result.push(MapEntry {
+                name,
+                index,
+                format,
+                gl_node_version,
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Finds the first lump with the name and returns the data as text,
+    /// bytes that isn't valid UTF-8 are replaced instead of failing
+    pub fn read_text_lump(&self, name: &str) -> Result<String> {
+        let data = self.read_dir_by_name(name)?;
+        Ok(String::from_utf8_lossy(data).into_owned())
+    }
+
     pub fn read_dir(&self, index: usize) -> Result<&[u8]> {
         let dir_entry = self.read_dir_entry(index)?;
 
-        // TODO(patrik): Check bounds
-
         let start = dir_entry.data_offset;
-        let end = start + dir_entry.data_size;
-        let data = &self.bytes[start..end];
+        let end = start
+            .checked_add(dir_entry.data_size)
+            .ok_or(Error::LumpOutOfBounds { index })?;
+        let data = self
+            .bytes
+            .get(start..end)
+            .ok_or(Error::LumpOutOfBounds { index })?;
 
         Ok(data)
     }
 }
 
+/// The markers of the patches and flats, (start markers, end markers),
+/// PWADs uses PP_START and FF_START to add to the lumps of the IWAD
+const NAMESPACE_MARKERS: [(&[&str], &[&str]); 2] = [
+    (&["P_START", "PP_START"], &["P_END", "PP_END"]),
+    (&["F_START", "FF_START"], &["F_END", "FF_END"]),
+];
+
+/// The markers written around the merged patches and flats
+const MERGED_MARKERS: [(&str, &str); 2] =
+    [("P_START", "P_END"), ("F_START", "F_END")];
+
+/// The lumps of a WAD split into the lumps inside the patch and flat
+/// markers and all the other lumps
+struct WadLumps<'a> {
+    lumps: Vec<([u8; 8], &'a [u8])>,
+    namespaces: [Vec<([u8; 8], &'a [u8])>; 2],
+}
+
+impl<'a> WadLumps<'a> {
+    fn split(wad: &'a Wad) -> Result<Self> {
+        let mut result = Self {
+            lumps: Vec::new(),
+            namespaces: [Vec::new(), Vec::new()],
+        };
+
+        let mut namespace = None;
+        for index in 0..wad.num_dirs {
+            let entry = wad.read_dir_entry(index)?;
+            let name = util::array_to_string(&entry.name);
+            let data = wad.read_dir(index)?;
+
+            let start = NAMESPACE_MARKERS
+                .iter()
+                .position(|(start, _)| start.contains(&name.as_str()));
+            let end = NAMESPACE_MARKERS
+                .iter()
+                .position(|(_, end)| end.contains(&name.as_str()));
+
+            if start.is_some() {
+                namespace = start;
+            } else if end.is_some() {
+                namespace = None;
+            } else if let Some(namespace) = namespace {
+                // NOTE: The inner markers (P1_START, F2_END, ...) are
+                // only there for the editors, they are left out
+                let is_marker = data.is_empty()
+                    && (name.ends_with("_START") || name.ends_with("_END"));
+                if !is_marker {
+                    result.namespaces[namespace].push((entry.name, data));
+                }
+            } else {
+                result.lumps.push((entry.name, data));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// A PWAD loaded on top of a base IWAD, the lumps are merged into a new WAD
+/// so the rest of the code can read them as a single WAD
+///
+/// The lumps of the PWAD are placed before the lumps of the IWAD so the
+/// lookups by name finds the PWAD version first (maps, PLAYPAL, COLORMAP,
+/// PNAMES, TEXTURE1, ...), the patches and flats are merged and the ones
+/// in the PWAD replaces the ones in the IWAD with the same name
+pub struct WadStack {
+    bytes: Vec<u8>,
+}
+
+impl WadStack {
+    pub fn new(base: &Wad, pwad: &Wad) -> Result<Self> {
+        let base = WadLumps::split(base)?;
+        let pwad = WadLumps::split(pwad)?;
+
+        let marker = |name: &str| {
+            let mut result = [0u8; 8];
+            result[..name.len()].copy_from_slice(name.as_bytes());
+            (result, &[][..])
+        };
+
+        let mut lumps = Vec::new();
+        lumps.extend_from_slice(&pwad.lumps);
+        lumps.extend_from_slice(&base.lumps);
+
+        for (index, (start, end)) in MERGED_MARKERS.iter().enumerate() {
+            let replaced = |name: &[u8; 8]| {
+                pwad.namespaces[index].iter().any(|(n, _)| {
+                    util::array_to_string(n)
+                        .eq_ignore_ascii_case(&util::array_to_string(name))
+                })
+            };
+
+            lumps.push(marker(start));
+            lumps.extend_from_slice(&pwad.namespaces[index]);
+            lumps.extend(
+                base.namespaces[index]
+                    .iter()
+                    .filter(|(name, _)| !replaced(name)),
+            );
+            lumps.push(marker(end));
+        }
+
+        // NOTE: The layout is the header, the data of the lumps and the
+        // directory at the end
+        let data_size =
+            lumps.iter().map(|(_, data)| data.len()).sum::<usize>();
+        let dir_start = 12 + data_size;
+        let to_i32 = |value: usize| {
+            i32::try_from(value).map_err(|_| Error::ConvertToUsizeFailed)
+        };
+
+        let mut bytes = Vec::with_capacity(dir_start + lumps.len() * 16);
+        bytes.extend_from_slice(b"IWAD");
+        bytes.extend_from_slice(&to_i32(lumps.len())?.to_le_bytes());
+        bytes.extend_from_slice(&to_i32(dir_start)?.to_le_bytes());
+        for (_, data) in &lumps {
+            bytes.extend_from_slice(data);
+        }
+
+        let mut offset = 12;
+        for (name, data) in &lumps {
+            bytes.extend_from_slice(&to_i32(offset)?.to_le_bytes());
+            bytes.extend_from_slice(&to_i32(data.len())?.to_le_bytes());
+            bytes.extend_from_slice(name);
+            offset += data.len();
+        }
+
+        Ok(Self { bytes })
+    }
+
+    pub fn wad(&self) -> Wad<'_> {
+        Wad::parse(&self.bytes).expect("The merged WAD is always valid")
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Vertex {
     pub x: f32,
@@ -193,6 +603,8 @@ impl Line {
 pub struct Linedef {
     pub line: Line,
     pub flags: LinedefFlags,
+    pub special: u16,
+    pub tag: u16,
     pub front_sidedef: Option<usize>,
     pub back_sidedef: Option<usize>,
 }
@@ -201,12 +613,16 @@ impl Linedef {
     fn new(
         line: Line,
         flags: LinedefFlags,
+        special: u16,
+        tag: u16,
         front_sidedef: Option<usize>,
         back_sidedef: Option<usize>,
     ) -> Self {
         Self {
             line,
             flags,
+            special,
+            tag,
             front_sidedef,
             back_sidedef,
         }
@@ -244,14 +660,40 @@ impl Sidedef {
     }
 }
 
+/// A plane stored as the equation a * x + b * y + c * z + d = 0 (the same
+/// layout as the UDMF floorplane_* and ceilingplane_* fields)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Plane {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+}
+
+impl Plane {
+    /// Returns the height (z) of the plane at the map position
+    pub fn height_at(&self, x: f32, y: f32) -> f32 {
+        -(self.a * x + self.b * y + self.d) / self.c
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Sector {
     pub floor_height: f32,
     pub ceiling_height: f32,
 
+    /// Sloped floor, None if the floor is flat at floor_height
+    pub floor_plane: Option<Plane>,
+    /// Sloped ceiling, None if the ceiling is flat at ceiling_height
+    pub ceiling_plane: Option<Plane>,
+
     pub floor_texture: String,
     pub ceiling_texture: String,
 
+    pub light_level: i16,
+    pub special: u16,
+    pub tag: u16,
+
     pub lines: Vec<Linedef>,
     pub sub_sectors: Vec<SubSector>,
 }
@@ -262,18 +704,42 @@ impl Sector {
         ceiling_height: f32,
         floor_texture: String,
         ceiling_texture: String,
+        light_level: i16,
+        special: u16,
+        tag: u16,
     ) -> Self {
         Self {
             floor_height,
             ceiling_height,
 
+            floor_plane: None,
+            ceiling_plane: None,
+
             floor_texture,
             ceiling_texture,
 
+            light_level,
+            special,
+            tag,
+
             lines: Vec::new(),
             sub_sectors: Vec::new(),
         }
     }
+
+    pub fn floor_height_at(&self, x: f32, y: f32) -> f32 {
+        match self.floor_plane {
+            Some(plane) => plane.height_at(x, y),
+            None => self.floor_height,
+        }
+    }
+
+    pub fn ceiling_height_at(&self, x: f32, y: f32) -> f32 {
+        match self.ceiling_plane {
+            Some(plane) => plane.height_at(x, y),
+            None => self.ceiling_height,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -288,11 +754,21 @@ impl SubSector {
     }
 }
 
+/// The linedef of a segment that isn't along a linedef (a miniseg), the
+/// sentinel of the node format (0xffff for 16-bit segs and 0xffffffff for
+/// the 32-bit segs of the extended nodes) is converted to this when loading
+/// so linedef 0 stays a real linedef
+pub const MINISEG: usize = usize::MAX;
+
+/// The miniseg sentinel of the 16-bit GL segs (version 1 and 2)
+const MINISEG_U16: u16 = 0xffff;
+
 #[derive(Copy, Clone, Debug)]
 pub struct Segment {
     pub start_vertex: usize,
     pub end_vertex: usize,
 
+    /// The index of the linedef or MINISEG
     pub linedef: usize,
     pub side: usize,
     pub partner_segment: usize,
@@ -316,8 +792,199 @@ impl Segment {
     }
 }
 
+/// The collision grid of the map, each block is 128x128 map units and lists
+/// the linedefs that are inside (or touches) it
+#[derive(Clone, Debug)]
+pub struct BlockMap {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub columns: usize,
+    pub rows: usize,
+    /// The linedef indices of each block, stored row by row starting at the
+    /// origin
+    pub blocks: Vec<Vec<usize>>,
+}
+
+impl BlockMap {
+    pub const BLOCK_SIZE: usize = 128;
+
+    pub fn block(&self, column: usize, row: usize) -> Option<&[usize]> {
+        if column >= self.columns || row >= self.rows {
+            return None;
+        }
+
+        Some(&self.blocks[column + row * self.columns])
+    }
+}
+
+/// Counts and height ranges of a map, used to sanity check the parsing
+#[derive(Copy, Clone, Default, Debug)]
+pub struct MapStats {
+    pub vertices: usize,
+    pub linedefs: usize,
+    pub one_sided_linedefs: usize,
+    pub two_sided_linedefs: usize,
+    pub sidedefs: usize,
+    pub sectors: usize,
+    pub segments: usize,
+    pub sub_sectors: usize,
+
+    pub min_floor_height: f32,
+    pub max_floor_height: f32,
+    pub min_ceiling_height: f32,
+    pub max_ceiling_height: f32,
+}
+
+/// The sector to sector visibility table of the map
+#[derive(Clone, Debug)]
+pub struct Reject {
+    num_sectors: usize,
+    bits: Vec<u8>,
+}
+
+impl Reject {
+    /// Returns false if nothing in sector_a can see sector_b, a set bit in
+    /// the table means that the sectors can't see each other
+    pub fn can_see(&self, sector_a: usize, sector_b: usize) -> bool {
+        if sector_a >= self.num_sectors || sector_b >= self.num_sectors {
+            return false;
+        }
+
+        let bit = sector_a * self.num_sectors + sector_b;
+        self.bits[bit / 8] & (1 << (bit % 8)) == 0
+    }
+}
+
+/// The potentially visible set from the GL_PVS lump (written by glVIS), one
+/// row of bits for each sub sector where a set bit means that the sub
+/// sectors can see each other
+pub struct Pvs {
+    num_sub_sectors: usize,
+    bits: Vec<u8>,
+}
+
+impl Pvs {
+    fn row_size(&self) -> usize {
+        self.num_sub_sectors.div_ceil(8)
+    }
+
+    /// Returns true if anything in sub_sector_a can see sub_sector_b
+    pub fn can_see(&self, sub_sector_a: usize, sub_sector_b: usize) -> bool {
+        if sub_sector_a >= self.num_sub_sectors
+            || sub_sector_b >= self.num_sub_sectors
+        {
+            return false;
+        }
+
+        let byte = sub_sector_a * self.row_size() + sub_sector_b / 8;
+        self.bits[byte] & (1 << (sub_sector_b % 8)) != 0
+    }
+
+    /// Returns the sub sectors that can be seen from the sub sector, the
+    /// iterator is empty if the sub sector doesn't exist
+    pub fn visible_subsectors(
+        &self,
+        from: usize,
+    ) -> impl Iterator<Item = usize> + '_ {
+        (0..self.num_sub_sectors).filter(move |&to| self.can_see(from, to))
+    }
+}
+
+/// A map found inside the WAD by Wad::iter_maps
+#[derive(Clone, Debug)]
+pub struct MapEntry {
+    pub name: String,
+    /// Index of the map marker lump
+    pub index: usize,
+    pub format: MapFormat,
+    pub gl_node_version: Option<GlNodeVersion>,
+}
+
+impl MapEntry {
+    pub fn has_gl_nodes(&self) -> bool {
+        self.gl_node_version.is_some()
+    }
+}
+
+/// The version of the GL nodes, detected from the magic at the start of the
+/// GL_VERT lump (no magic means version 1)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GlNodeVersion {
+    V1,
+    V2,
+    V3,
+    V5,
+}
+
+impl GlNodeVersion {
+    /// The size of the records in the GL_SEGS lump
+    fn segment_size(self) -> usize {
+        match self {
+            GlNodeVersion::V1 | GlNodeVersion::V2 => 10,
+            GlNodeVersion::V3 | GlNodeVersion::V5 => 16,
+        }
+    }
+
+    /// The size of the records in the GL_SSECT lump
+    fn sub_sector_size(self) -> usize {
+        match self {
+            GlNodeVersion::V1 | GlNodeVersion::V2 => 4,
+            GlNodeVersion::V3 | GlNodeVersion::V5 => 8,
+        }
+    }
+
+    /// The bit of the segment vertex indices that marks the GL vertices
+    fn gl_vertex_flag(self) -> usize {
+        match self {
+            GlNodeVersion::V1 | GlNodeVersion::V2 => 1 << 15,
+            GlNodeVersion::V3 => 1 << 30,
+            GlNodeVersion::V5 => 1 << 31,
+        }
+    }
+}
+
+/// The binary format of the map lumps
+///
+/// Hexen maps have a BEHAVIOR lump after BLOCKMAP, bigger linedefs (special
+/// and args instead of special and tag) and bigger things (tid, z, special
+/// and args)
+///
+/// UDMF maps are stored as text inside the TEXTMAP lump
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MapFormat {
+    Doom,
+    Hexen,
+    Udmf,
+}
+
+impl MapFormat {
+    /// The size of the records in the LINEDEFS lump, None for UDMF maps
+    /// that has the linedefs inside the TEXTMAP
+    fn linedef_size(&self) -> Option<usize> {
+        match self {
+            MapFormat::Doom => Some(14),
+            MapFormat::Hexen => Some(16),
+            MapFormat::Udmf => None,
+        }
+    }
+
+    /// Offset of the GL lumps (GL_VERT, GL_SEGS, ...) from the map marker,
+    /// the BEHAVIOR lump pushes them one step down in Hexen maps
+    ///
+    /// None for UDMF maps, their GL nodes are inside the ZNODES lump
+    fn gl_lump_offset(&self) -> Option<usize> {
+        match self {
+            MapFormat::Doom => Some(11),
+            MapFormat::Hexen => Some(12),
+            MapFormat::Udmf => None,
+        }
+    }
+}
+
 pub struct Map {
     pub name: String,
+    pub format: MapFormat,
+    gl_node_version: Option<GlNodeVersion>,
 
     pub vertices: Vec<Vertex>,
     pub gl_vertices: Vec<Vertex>,
@@ -330,10 +997,75 @@ pub struct Map {
     pub sub_sectors: Vec<SubSector>,
 }
 
+/// The UDMF namespaces that uses the fields read by [`Map::parse_udmf`]
+const UDMF_NAMESPACES: &[&str] = &[
+    "doom",
+    "heretic",
+    "hexen",
+    "strife",
+    "zdoom",
+    "gzdoom",
+    "eternity",
+    "vavoom",
+    "zdoomtranslated",
+];
+
+/// Names of the lumps that can follow a map marker, the first lump that
+/// isn't one of these (or a GL_ lump) ends the map
+const MAP_LUMP_NAMES: &[&str] = &[
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES",
+    "SECTORS", "REJECT", "BLOCKMAP", "BEHAVIOR", "SCRIPTS",
+];
+
 impl Map {
+    /// Finds the lump with the name inside the lumps of the map, the
+    /// search stops at the first lump that doesn't belong to the map so
+    /// the lumps of the next map are never returned
+    pub fn find_map_lump(
+        wad: &Wad,
+        map_index: usize,
+        name: &str,
+    ) -> Result<usize> {
+        let mut end = map_index + 1;
+        while end < wad.num_dirs {
+            let dir_name =
+                util::array_to_string(&wad.read_dir_entry(end)?.name);
+
+            // NOTE: The GL lumps are placed after the normal lumps, the
+            // marker is named GL_<map name> or GL_LEVEL
+            let is_map_lump = MAP_LUMP_NAMES.contains(&dir_name.as_str())
+                || dir_name.starts_with("GL_");
+            if !is_map_lump {
+                break;
+            }
+
+            end += 1;
+        }
+
+        let index = wad.find_dir_after(map_index + 1, name)?;
+        if index >= end {
+            return Err(Error::NoDirFound);
+        }
+
+        Ok(index)
+    }
+
     pub fn parse_from_wad(wad: &Wad, map_name: &str) -> Result<Self> {
+        let map_index = wad.find_dir(map_name)?;
+        let format = Self::detect_format(wad, map_index)?;
+        if format == MapFormat::Udmf {
+            return Self::parse_udmf(wad, map_name);
+        }
+
+        let gl_node_version = Self::detect_gl_nodes(wad, map_index, format)?;
+        if gl_node_version.is_none() {
+            return Err(Error::MissingGlNodes);
+        }
+
         let mut res = Self {
             name: map_name.to_string(),
+            format,
+            gl_node_version,
 
             vertices: Vec::new(),
             gl_vertices: Vec::new(),
@@ -346,8 +1078,6 @@ impl Map {
             sub_sectors: Vec::new(),
         };
 
-        let map_index = wad.find_dir(map_name)?;
-
         res.load_vertices(wad, map_index)?;
         res.load_linedefs(wad, map_index)?;
         res.load_sidedefs(wad, map_index)?;
@@ -360,81 +1090,376 @@ impl Map {
         Ok(res)
     }
 
-    fn load_vertices(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
-        // Load the normal vertices
-        {
-            let data = wad.read_dir(map_index + 4)?;
-
-            let count = data.len() / 4;
+    /// Finds the lump with the name inside the lumps of a UDMF map, the
+    /// lumps of the map ends with the ENDMAP lump
+    pub fn find_udmf_lump(
+        wad: &Wad,
+        map_index: usize,
+        name: &str,
+    ) -> Result<Option<usize>> {
+        for index in map_index + 1..wad.num_dirs {
+            let dir_name =
+                util::array_to_string(&wad.read_dir_entry(index)?.name);
+            if dir_name == name {
+                return Ok(Some(index));
+            }
 
-            for index in 0..count {
-                let start = index * 4;
-                let data = &data[start..start + 4];
+            if dir_name == "ENDMAP" {
+                break;
+            }
+        }
 
-                let x = i16::from_le_bytes(
-                    data[0..2]
-                        .try_into()
-                        .map_err(|_| Error::ArrayConvertionFailed)?,
-                );
-                let y = i16::from_le_bytes(
-                    data[2..4]
-                        .try_into()
-                        .map_err(|_| Error::ArrayConvertionFailed)?,
-                );
+        Ok(None)
+    }
 
-                let x: f32 =
-                    x.try_into().map_err(|_| Error::ConvertToF32Failed)?;
-                let y: f32 =
-                    y.try_into().map_err(|_| Error::ConvertToF32Failed)?;
+    /// Parses a map stored in the UDMF text format, the TEXTMAP lump needs
+    /// to be the first lump after the map marker and the nodes are read
+    /// from the ZNODES lump (the GL nodes of ZDoom)
+    pub fn parse_udmf(wad: &Wad, map_name: &str) -> Result<Self> {
+        let map_index = wad.find_dir(map_name)?;
 
-                self.vertices.push(Vertex::new(x, y));
-            }
+        let data = wad.read_dir(map_index + 1)?;
+        let text =
+            std::str::from_utf8(data).map_err(|_| Error::BytesToStrFailed)?;
+        let text_map = udmf::parse(text).map_err(Error::TextMapParseFailed)?;
+
+        let namespace = text_map.namespace.as_deref().ok_or(
+            Error::TextMapMissingField {
+                kind: "map",
+                field: "namespace",
+            },
+        )?;
+        if !UDMF_NAMESPACES.contains(&namespace) {
+            return Err(Error::UnsupportedNamespace(namespace.to_string()));
         }
 
-        // Load the extra vertices (GL_VERT)
-        {
-            let data = wad.read_dir(map_index + 12)?;
-
-            //TODO(patrik): Make sure the gl_magic is correct
-            let _gl_magic = &data[0..4];
+        let znodes_index = Self::find_udmf_lump(wad, map_index, "ZNODES")?
+            .ok_or(Error::MissingGlNodes)?;
 
-            let data = &data[4..];
+        let mut res = Self {
+            name: map_name.to_string(),
+            format: MapFormat::Udmf,
+            gl_node_version: None,
 
-            let count = data.len() / 8;
+            vertices: Vec::new(),
+            gl_vertices: Vec::new(),
 
-            for index in 0..count {
-                let start = index * 8;
-                let data = &data[start..start + 8];
+            linedefs: Vec::new(),
+            sidedefs: Vec::new(),
+            sectors: Vec::new(),
 
-                let x = i32::from_le_bytes(
-                    data[0..4]
-                        .try_into()
-                        .map_err(|_| Error::ArrayConvertionFailed)?,
-                );
-                let y = i32::from_le_bytes(
-                    data[4..8]
-                        .try_into()
-                        .map_err(|_| Error::ArrayConvertionFailed)?,
-                );
+            segments: Vec::new(),
+            sub_sectors: Vec::new(),
+        };
 
-                let x = x as f32 / 65536.0;
-                let y = y as f32 / 65536.0;
+        let missing = |kind, field| Error::TextMapMissingField { kind, field };
+        let to_usize = |value: i64| -> Result<usize> {
+            value.try_into().map_err(|_| Error::ConvertToUsizeFailed)
+        };
 
-                self.gl_vertices.push(Vertex::new(x, y));
-            }
+        for block in text_map.blocks_of_kind("vertex") {
+            let x = block
+                .get("x")
+                .and_then(|v| v.as_f32())
+                .ok_or(missing("vertex", "x"))?;
+            let y = block
+                .get("y")
+                .and_then(|v| v.as_f32())
+                .ok_or(missing("vertex", "y"))?;
+
+            res.vertices.push(Vertex::new(x, y));
         }
 
-        Ok(())
-    }
+        for block in text_map.blocks_of_kind("linedef") {
+            let int = |field| block.get(field).and_then(|v| v.as_i64());
+            let flag = |field| {
+                block.get(field).and_then(|v| v.as_bool()).unwrap_or(false)
+            };
 
-    fn load_linedefs(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
-        let data = wad.read_dir(map_index + 2)?;
+            let start_vertex =
+                to_usize(int("v1").ok_or(missing("linedef", "v1"))?)?;
+            let end_vertex =
+                to_usize(int("v2").ok_or(missing("linedef", "v2"))?)?;
 
-        let count = data.len() / 14;
+            let front_sidedef =
+                int("sidefront").ok_or(missing("linedef", "sidefront"))?;
+            let back_sidedef = int("sideback").unwrap_or(-1);
 
-        for index in 0..count {
-            let start = index * 14;
-            let data = &data[start..start + 14];
+            let front_sidedef = if front_sidedef == -1 {
+                None
+            } else {
+                Some(to_usize(front_sidedef)?)
+            };
+
+            let back_sidedef = if back_sidedef == -1 {
+                None
+            } else {
+                Some(to_usize(back_sidedef)?)
+            };
+
+            let mut flags = LinedefFlags::empty();
+            flags.set(LinedefFlags::IMPASSABLE, flag("blocking"));
+            flags.set(LinedefFlags::BLOCKS_MONSTERS, flag("blockmonsters"));
+            flags.set(LinedefFlags::TWO_SIDED, flag("twosided"));
+            flags
+                .set(LinedefFlags::UPPER_TEXTURE_UNPEGGED, flag("dontpegtop"));
+            flags.set(
+                LinedefFlags::LOWER_TEXTURE_UNPEGGED,
+                flag("dontpegbottom"),
+            );
+            flags.set(LinedefFlags::SECRET, flag("secret"));
+            flags.set(LinedefFlags::BLOCKS_SOUND, flag("blocksound"));
+            flags.set(LinedefFlags::NEVER_SHOW_ON_AUTOMAP, flag("dontdraw"));
+            flags.set(LinedefFlags::ALWAYS_SHOWS_ON_AUTOMAP, flag("mapped"));
+
+            res.linedefs.push(Linedef::new(
+                Line::new(start_vertex, end_vertex),
+                flags,
+                int("special").unwrap_or(0) as u16,
+                int("id").unwrap_or(0) as u16,
+                front_sidedef,
+                back_sidedef,
+            ));
+        }
+
+        for block in text_map.blocks_of_kind("sidedef") {
+            let int = |field| block.get(field).and_then(|v| v.as_i64());
+            let texture = |field| {
+                block
+                    .get(field)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("-")
+                    .to_string()
+            };
+
+            let sector =
+                to_usize(int("sector").ok_or(missing("sidedef", "sector"))?)?;
+
+            res.sidedefs.push(Sidedef::new(
+                int("offsetx").unwrap_or(0) as i16,
+                int("offsety").unwrap_or(0) as i16,
+                sector,
+                texture("texturetop"),
+                texture("texturemiddle"),
+                texture("texturebottom"),
+            ));
+        }
+
+        for block in text_map.blocks_of_kind("sector") {
+            let height = |field| {
+                block.get(field).and_then(|v| v.as_f32()).unwrap_or(0.0)
+            };
+            let int = |field| block.get(field).and_then(|v| v.as_i64());
+            let texture = |field| {
+                block
+                    .get(field)
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+                    .ok_or(missing("sector", field))
+            };
+
+            // NOTE: The plane is only used if all the fields are set and
+            // the plane isn't vertical
+            let plane = |prefix: &str| {
+                let field = |name: &str| {
+                    block
+                        .get(&format!("{}_{}", prefix, name))
+                        .and_then(|v| v.as_f32())
+                };
+
+                let plane = Plane {
+                    a: field("a")?,
+                    b: field("b")?,
+                    c: field("c")?,
+                    d: field("d")?,
+                };
+
+                if plane.c == 0.0 {
+                    None
+                } else {
+                    Some(plane)
+                }
+            };
+
+            let mut sector = Sector::new(
+                height("heightfloor"),
+                height("heightceiling"),
+                texture("texturefloor")?,
+                texture("textureceiling")?,
+                // NOTE: 160 is the default light level in UDMF
+                int("lightlevel").unwrap_or(160) as i16,
+                int("special").unwrap_or(0) as u16,
+                int("id").unwrap_or(0) as u16,
+            );
+            sector.floor_plane = plane("floorplane");
+            sector.ceiling_plane = plane("ceilingplane");
+
+            res.sectors.push(sector);
+        }
+
+        res.load_znodes(wad.read_dir(znodes_index)?)?;
+        res.sort_subsectors()?;
+
+        Ok(res)
+    }
+
+    /// Finds the GL nodes of the map (the GL_<map name> marker lump) and
+    /// returns the version of them or None if the map doesn't have any
+    pub fn detect_gl_nodes(
+        wad: &Wad,
+        map_index: usize,
+        format: MapFormat,
+    ) -> Result<Option<GlNodeVersion>> {
+        let gl_index = match format.gl_lump_offset() {
+            Some(offset) => map_index + offset,
+            None => return Ok(None),
+        };
+        if gl_index + 1 >= wad.num_dirs {
+            return Ok(None);
+        }
+
+        // NOTE: The marker is GL_<map name> but maps with names longer than
+        // 5 characters use GL_LEVEL
+        let gl_name =
+            util::array_to_string(&wad.read_dir_entry(gl_index)?.name);
+        if !gl_name.starts_with("GL_") {
+            return Ok(None);
+        }
+
+        let data = wad.read_dir(gl_index + 1)?;
+        let version = match data.get(0..4) {
+            Some(b"gNd2") => GlNodeVersion::V2,
+            Some(b"gNd3") => GlNodeVersion::V3,
+            Some(b"gNd5") => GlNodeVersion::V5,
+            _ => GlNodeVersion::V1,
+        };
+
+        Ok(Some(version))
+    }
+
+    pub fn detect_format(wad: &Wad, map_index: usize) -> Result<MapFormat> {
+        if map_index + 1 < wad.num_dirs {
+            let dir_entry = wad.read_dir_entry(map_index + 1)?;
+            if util::array_to_string(&dir_entry.name) == "TEXTMAP" {
+                return Ok(MapFormat::Udmf);
+            }
+        }
+
+        let index = map_index + 11;
+        if index >= wad.num_dirs {
+            return Ok(MapFormat::Doom);
+        }
+
+        let dir_entry = wad.read_dir_entry(index)?;
+        if util::array_to_string(&dir_entry.name) == "BEHAVIOR" {
+            Ok(MapFormat::Hexen)
+        } else {
+            Ok(MapFormat::Doom)
+        }
+    }
+
+    fn load_vertices(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
+        // Load the normal vertices
+        {
+            let data = wad.read_dir(map_index + 4)?;
+
+            let count = data.len() / 4;
+            self.vertices.reserve(count);
+
+            for index in 0..count {
+                let start = index * 4;
+                let data = &data[start..start + 4];
+
+                let x = i16::from_le_bytes(
+                    data[0..2]
+                        .try_into()
+                        .map_err(|_| Error::ArrayConvertionFailed)?,
+                );
+                let y = i16::from_le_bytes(
+                    data[2..4]
+                        .try_into()
+                        .map_err(|_| Error::ArrayConvertionFailed)?,
+                );
+
+                self.vertices.push(Vertex::new(x.into(), y.into()));
+            }
+
+            debug_assert_eq!(self.vertices.len(), count);
+        }
+
+        // Load the extra vertices (GL_VERT)
+        {
+            let gl_index = map_index
+                + self.format.gl_lump_offset().ok_or(Error::MissingGlNodes)?;
+            let data = wad.read_dir(gl_index + 1)?;
+
+            let version = self.gl_node_version.ok_or(Error::MissingGlNodes)?;
+
+            // NOTE: Version 1 stores the vertices like VERTEXES (16 bit
+            // integers) without any magic, the later versions starts with
+            // the magic and uses 16.16 fixed point
+            let (data, size) = match version {
+                GlNodeVersion::V1 => (data, 4),
+                _ => (&data[4..], 8),
+            };
+
+            let count = data.len() / size;
+            self.gl_vertices.reserve(count);
+
+            for index in 0..count {
+                let start = index * size;
+                let data = &data[start..start + size];
+
+                let (x, y) = if version == GlNodeVersion::V1 {
+                    let x = i16::from_le_bytes(
+                        data[0..2]
+                            .try_into()
+                            .map_err(|_| Error::ArrayConvertionFailed)?,
+                    );
+                    let y = i16::from_le_bytes(
+                        data[2..4]
+                            .try_into()
+                            .map_err(|_| Error::ArrayConvertionFailed)?,
+                    );
+
+                    (x as f32, y as f32)
+                } else {
+                    let x = i32::from_le_bytes(
+                        data[0..4]
+                            .try_into()
+                            .map_err(|_| Error::ArrayConvertionFailed)?,
+                    );
+                    let y = i32::from_le_bytes(
+                        data[4..8]
+                            .try_into()
+                            .map_err(|_| Error::ArrayConvertionFailed)?,
+                    );
+
+                    (x as f32 / 65536.0, y as f32 / 65536.0)
+                };
+
+                self.gl_vertices.push(Vertex::new(x, y));
+            }
+
+            debug_assert_eq!(self.gl_vertices.len(), count);
+        }
+
+        Ok(())
+    }
+
+    fn load_linedefs(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
+        let data = wad.read_dir(map_index + 2)?;
+
+        let size = self.format.linedef_size().ok_or(Error::NoDirFound)?;
+        let count = data.len() / size;
+        self.linedefs.reserve(count);
+
+        // NOTE: The sidedefs are at the end of the record in both formats
+        let sides = size - 4;
+
+        for index in 0..count {
+            let start = index * size;
+            let data = &data[start..start + size];
 
             let start_vertex = i16::from_le_bytes(
                 data[0..2]
@@ -447,19 +1472,39 @@ impl Map {
                     .map_err(|_| Error::ArrayConvertionFailed)?,
             );
 
-            let flags = i32::from_le_bytes(
-                data[4..8]
+            let flags = u16::from_le_bytes(
+                data[4..6]
                     .try_into()
                     .map_err(|_| Error::ArrayConvertionFailed)?,
             );
 
+            // NOTE: Hexen has a 1 byte special followed by 5 arguments and
+            // no tag, the first argument is the tag for most specials
+            let (special, tag) = match self.format {
+                MapFormat::Hexen => (data[6] as u16, data[7] as u16),
+                _ => {
+                    let special = u16::from_le_bytes(
+                        data[6..8]
+                            .try_into()
+                            .map_err(|_| Error::ArrayConvertionFailed)?,
+                    );
+                    let tag = u16::from_le_bytes(
+                        data[8..10]
+                            .try_into()
+                            .map_err(|_| Error::ArrayConvertionFailed)?,
+                    );
+
+                    (special, tag)
+                }
+            };
+
             let front_sidedef = i16::from_le_bytes(
-                data[10..12]
+                data[sides..sides + 2]
                     .try_into()
                     .map_err(|_| Error::ArrayConvertionFailed)?,
             );
             let back_sidedef = i16::from_le_bytes(
-                data[12..14]
+                data[sides + 2..sides + 4]
                     .try_into()
                     .map_err(|_| Error::ArrayConvertionFailed)?,
             );
@@ -471,8 +1516,6 @@ impl Map {
                 .try_into()
                 .map_err(|_| Error::ConvertToUsizeFailed)?;
 
-            let flags: usize =
-                flags.try_into().map_err(|_| Error::ConvertToUsizeFailed)?;
             let flags = LinedefFlags::from_bits_truncate(flags);
 
             let line = Line::new(start_vertex, end_vertex);
@@ -500,17 +1543,22 @@ impl Map {
             self.linedefs.push(Linedef::new(
                 line,
                 flags,
+                special,
+                tag,
                 front_sidedef,
                 back_sidedef,
             ));
         }
 
+        debug_assert_eq!(self.linedefs.len(), count);
+
         Ok(())
     }
 
     fn load_sidedefs(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
         let data = wad.read_dir(map_index + 3)?;
         let count = data.len() / 30;
+        self.sidedefs.reserve(count);
 
         for index in 0..count {
             let start = index * 30;
@@ -562,12 +1610,15 @@ impl Map {
             ));
         }
 
+        debug_assert_eq!(self.sidedefs.len(), count);
+
         Ok(())
     }
 
     fn load_sectors(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
         let data = wad.read_dir(map_index + 8)?;
         let count = data.len() / 26;
+        self.sectors.reserve(count);
 
         for index in 0..count {
             let start = index * 26;
@@ -584,13 +1635,8 @@ impl Map {
                     .map_err(|_| Error::ArrayConvertionFailed)?,
             );
 
-            let floor_height: f32 = floor_height
-                .try_into()
-                .map_err(|_| Error::ConvertToF32Failed)?;
-
-            let ceiling_height: f32 = ceiling_height
-                .try_into()
-                .map_err(|_| Error::ConvertToF32Failed)?;
+            let floor_height = f32::from(floor_height);
+            let ceiling_height = f32::from(ceiling_height);
 
             let floor_texture: [u8; 8] = data[4..12]
                 .try_into()
@@ -602,113 +1648,287 @@ impl Map {
                 .map_err(|_| Error::ArrayConvertionFailed)?;
             let ceiling_texture = util::array_to_string(&ceiling_texture);
 
+            let light_level = i16::from_le_bytes(
+                data[20..22]
+                    .try_into()
+                    .map_err(|_| Error::ArrayConvertionFailed)?,
+            );
+            let special = u16::from_le_bytes(
+                data[22..24]
+                    .try_into()
+                    .map_err(|_| Error::ArrayConvertionFailed)?,
+            );
+            let tag = u16::from_le_bytes(
+                data[24..26]
+                    .try_into()
+                    .map_err(|_| Error::ArrayConvertionFailed)?,
+            );
+
             self.sectors.push(Sector::new(
                 floor_height,
                 ceiling_height,
                 floor_texture,
                 ceiling_texture,
+                light_level,
+                special,
+                tag,
             ));
         }
 
+        debug_assert_eq!(self.sectors.len(), count);
+
         Ok(())
     }
 
     fn load_subsectors(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
-        let data = wad.read_dir(map_index + 14)?;
-        // TODO(patrik): Look for magic
+        let gl_index = map_index
+            + self.format.gl_lump_offset().ok_or(Error::MissingGlNodes)?;
+        let data = wad.read_dir(gl_index + 3)?;
+
+        let version = self.gl_node_version.ok_or(Error::MissingGlNodes)?;
+        let size = version.sub_sector_size();
+        let records = gl_records(data, "GL_SSECT", version, size)?;
+
+        let count = records.len();
+        self.sub_sectors.reserve(count);
+        for data in records {
+            // NOTE: Version 1 and 2 uses 16 bit integers and the later
+            // versions 32 bit integers
+            let (count, start) = if size == 4 {
+                (read_u16(data, 0)? as usize, read_u16(data, 2)? as usize)
+            } else {
+                (read_u32(data, 0)? as usize, read_u32(data, 4)? as usize)
+            };
 
-        let count = data.len() / 4;
-        for index in 0..count {
-            let start = index * 4;
-            let data = &data[start..start + 4];
+            self.sub_sectors.push(SubSector::new(start, count));
+        }
 
-            let count = u16::from_le_bytes(
-                data[0..2]
-                    .try_into()
-                    .map_err(|_| Error::ArrayConvertionFailed)?,
-            );
-            let start = u16::from_le_bytes(
-                data[2..4]
-                    .try_into()
-                    .map_err(|_| Error::ArrayConvertionFailed)?,
-            );
+        debug_assert_eq!(self.sub_sectors.len(), count);
 
-            let start: usize =
-                start.try_into().map_err(|_| Error::ConvertToUsizeFailed)?;
-            let count: usize =
-                count.try_into().map_err(|_| Error::ConvertToUsizeFailed)?;
+        Ok(())
+    }
 
-            self.sub_sectors.push(SubSector::new(start, count));
+    fn load_segments(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
+        let gl_index = map_index
+            + self.format.gl_lump_offset().ok_or(Error::MissingGlNodes)?;
+        let data = wad.read_dir(gl_index + 2)?;
+
+        let version = self.gl_node_version.ok_or(Error::MissingGlNodes)?;
+        let size = version.segment_size();
+        let records = gl_records(data, "GL_SEGS", version, size)?;
+
+        // NOTE: The flag of the format is converted to VERT_IS_GL so the
+        // vertex lookup is the same for all the versions
+        let gl_flag = version.gl_vertex_flag();
+        let vertex = |index: usize| {
+            if index & gl_flag == gl_flag {
+                (index & !gl_flag) | VERT_IS_GL
+            } else {
+                index
+            }
+        };
+
+        let count = records.len();
+        self.segments.reserve(count);
+
+        for data in records {
+            // NOTE: Version 1 and 2 uses 16 bit integers for everything,
+            // the later versions uses 32 bit integers for the vertices and
+            // the partner segment (the linedef and the side are still 16
+            // bit)
+            let (start_vertex, end_vertex, rest, partner_segment) =
+                if size == 10 {
+                    (
+                        read_u16(data, 0)? as usize,
+                        read_u16(data, 2)? as usize,
+                        4,
+                        read_u16(data, 8)? as usize,
+                    )
+                } else {
+                    (
+                        read_u32(data, 0)? as usize,
+                        read_u32(data, 4)? as usize,
+                        8,
+                        read_u32(data, 12)? as usize,
+                    )
+                };
+
+            let linedef = read_u16(data, rest)?;
+            let linedef = if linedef == MINISEG_U16 {
+                MINISEG
+            } else {
+                linedef as usize
+            };
+            let side = read_u16(data, rest + 2)? as usize;
+
+            self.segments.push(Segment::new(
+                vertex(start_vertex),
+                vertex(end_vertex),
+                linedef,
+                side,
+                partner_segment,
+            ));
         }
 
+        debug_assert_eq!(self.segments.len(), count);
+
         Ok(())
     }
 
-    fn load_segments(&mut self, wad: &Wad, map_index: usize) -> Result<()> {
-        let data = wad.read_dir(map_index + 13)?;
-        // TODO(patrik): Look for magic
+    /// Loads the GL vertices, the sub sectors and the segments from the
+    /// ZNODES lump of a UDMF map
+    ///
+    /// Only the GL variants of the extended nodes (XGLN, XGL2 and XGL3 or
+    /// ZGLN, ZGL2 and ZGL3 when the data after the magic is compressed)
+    /// has the segments needed to build the floors
+    fn load_znodes(&mut self, data: &[u8]) -> Result<()> {
+        let magic: [u8; 4] = data
+            .get(0..4)
+            .ok_or(Error::TooSmall)?
+            .try_into()
+            .map_err(|_| Error::ArrayConvertionFailed)?;
+
+        // NOTE: XGLN uses 16 bit linedef indices, the later versions
+        // uses 32 bit indices
+        let (compressed, wide_linedefs) = match &magic {
+            b"XGLN" => (false, false),
+            b"XGL2" | b"XGL3" => (false, true),
+            b"ZGLN" => (true, false),
+            b"ZGL2" | b"ZGL3" => (true, true),
+            b"XNOD" | b"ZNOD" => return Err(Error::MissingGlNodes),
+            _ => return Err(Error::UnknownMagic(magic)),
+        };
 
-        let count = data.len() / 10;
+        let decompressed;
+        let data = if compressed {
+            let mut buffer = Vec::new();
+            flate2::read::ZlibDecoder::new(&data[4..])
+                .read_to_end(&mut buffer)
+                .map_err(|_| Error::InvalidZNodes)?;
+            decompressed = buffer;
+            &decompressed[..]
+        } else {
+            &data[4..]
+        };
 
-        for index in 0..count {
-            let start = index * 10;
-            let data = &data[start..start + 10];
+        let mut offset = 0;
 
-            let start_vertex = u16::from_le_bytes(
-                data[0..2]
-                    .try_into()
-                    .map_err(|_| Error::ArrayConvertionFailed)?,
-            );
-            let end_vertex = u16::from_le_bytes(
-                data[2..4]
-                    .try_into()
-                    .map_err(|_| Error::ArrayConvertionFailed)?,
-            );
+        // NOTE: The original vertices are the vertices of the TEXTMAP, the
+        // new vertices are the extra vertices created by the node builder
+        let original_count = read_u32(data, offset)? as usize;
+        let new_count = read_u32(data, offset + 4)? as usize;
+        offset += 8;
 
-            let linedef = u16::from_le_bytes(
-                data[4..6]
-                    .try_into()
-                    .map_err(|_| Error::ArrayConvertionFailed)?,
-            );
-            let side = u16::from_le_bytes(
-                data[6..8]
-                    .try_into()
-                    .map_err(|_| Error::ArrayConvertionFailed)?,
-            );
-            let partner_segment = u16::from_le_bytes(
-                data[8..10]
-                    .try_into()
-                    .map_err(|_| Error::ArrayConvertionFailed)?,
-            );
+        if original_count > self.vertices.len() {
+            return Err(Error::InvalidZNodes);
+        }
 
-            let start_vertex: usize = start_vertex
-                .try_into()
-                .map_err(|_| Error::ConvertToUsizeFailed)?;
+        self.gl_vertices.reserve(new_count);
+        for _ in 0..new_count {
+            // NOTE: The vertices are 16.16 fixed point
+            let x = read_u32(data, offset)? as i32;
+            let y = read_u32(data, offset + 4)? as i32;
+            offset += 8;
 
-            let end_vertex: usize = end_vertex
-                .try_into()
-                .map_err(|_| Error::ConvertToUsizeFailed)?;
+            self.gl_vertices
+                .push(Vertex::new(x as f32 / 65536.0, y as f32 / 65536.0));
+        }
 
-            let linedef: usize = linedef
-                .try_into()
-                .map_err(|_| Error::ConvertToUsizeFailed)?;
+        // NOTE: The sub sectors only stores the number of segments, the
+        // segments of the sub sectors follows each other
+        let sub_sector_count = read_u32(data, offset)? as usize;
+        offset += 4;
 
-            let side: usize =
-                side.try_into().map_err(|_| Error::ConvertToUsizeFailed)?;
+        let mut first = 0;
+        for _ in 0..sub_sector_count {
+            let count = read_u32(data, offset)? as usize;
+            offset += 4;
 
-            let partner_segment: usize = partner_segment
-                .try_into()
-                .map_err(|_| Error::ConvertToUsizeFailed)?;
+            if count == 0 {
+                return Err(Error::InvalidZNodes);
+            }
 
+            self.sub_sectors.push(SubSector::new(first, count));
+            first += count;
+        }
+
+        let segment_count = read_u32(data, offset)? as usize;
+        offset += 4;
+
+        if segment_count != first {
+            return Err(Error::InvalidZNodes);
+        }
+
+        let vertex = |index: usize| {
+            if index < original_count {
+                Ok(index)
+            } else if index - original_count < new_count {
+                Ok((index - original_count) | VERT_IS_GL)
+            } else {
+                Err(Error::InvalidZNodes)
+            }
+        };
+
+        self.segments.reserve(segment_count);
+        for _ in 0..segment_count {
+            let start_vertex = vertex(read_u32(data, offset)? as usize)?;
+            let partner_segment = read_u32(data, offset + 4)? as usize;
+            offset += 8;
+
+            let linedef = if wide_linedefs {
+                let linedef = read_u32(data, offset)?;
+                offset += 4;
+
+                if linedef == u32::MAX {
+                    MINISEG
+                } else {
+                    linedef as usize
+                }
+            } else {
+                let linedef = read_u16(data, offset)?;
+                offset += 2;
+
+                if linedef == MINISEG_U16 {
+                    MINISEG
+                } else {
+                    linedef as usize
+                }
+            };
+
+            if linedef != MINISEG && linedef >= self.linedefs.len() {
+                return Err(Error::InvalidZNodes);
+            }
+
+            let side = *data.get(offset).ok_or(Error::TooSmall)? as usize;
+            offset += 1;
+
+            // NOTE: The end vertex isn't stored, it's the start vertex of
+            // the next segment in the sub sector and is set below
             self.segments.push(Segment::new(
                 start_vertex,
-                end_vertex,
+                start_vertex,
                 linedef,
                 side,
                 partner_segment,
             ));
         }
 
+        // NOTE: The segments of a sub sector forms a closed loop so the
+        // last segment ends at the start of the first
+        for sub_sector in &self.sub_sectors {
+            let end = sub_sector.start + sub_sector.count;
+            for index in sub_sector.start..end {
+                let next = if index + 1 == end {
+                    sub_sector.start
+                } else {
+                    index + 1
+                };
+
+                self.segments[index].end_vertex =
+                    self.segments[next].start_vertex;
+            }
+        }
+
         Ok(())
     }
 
@@ -729,7 +1949,7 @@ impl Map {
 
         for sub_sector in &self.sub_sectors {
             let segment = self.segments[sub_sector.start];
-            if segment.linedef != 0xffff {
+            if segment.linedef != MINISEG {
                 let linedef = self.linedefs[segment.linedef];
                 let sidedef = if segment.side == 0 {
                     linedef
@@ -751,11 +1971,808 @@ impl Map {
         Ok(())
     }
 
+    /// Reads the BLOCKMAP lump of the map, UDMF maps are not supported
+    pub fn load_blockmap(&self, wad: &Wad) -> Result<BlockMap> {
+        if self.format == MapFormat::Udmf {
+            return Err(Error::NoDirFound);
+        }
+
+        let map_index = wad.find_dir(&self.name)?;
+        let index = Self::find_map_lump(wad, map_index, "BLOCKMAP")?;
+        let data = wad.read_dir(index)?;
+
+        let read_u16 = |offset: usize| -> Result<u16> {
+            let bytes =
+                data.get(offset..offset + 2).ok_or(Error::InvalidBlockMap)?;
+            Ok(u16::from_le_bytes(
+                bytes.try_into().map_err(|_| Error::ArrayConvertionFailed)?,
+            ))
+        };
+
+        let origin_x = read_u16(0)? as i16;
+        let origin_y = read_u16(2)? as i16;
+        let columns = read_u16(4)? as usize;
+        let rows = read_u16(6)? as usize;
+
+        // NOTE: The offsets are in 16-bit words from the start of the lump
+        let count = columns * rows;
+        let offsets = (0..count)
+            .map(|index| Ok(read_u16(8 + index * 2)? as usize * 2))
+            .collect::<Result<Vec<_>>>()?;
+
+        // NOTE: The lists starts with a 0 that isn't a linedef, some node
+        // builders leaves it out. It's only skipped when every list starts
+        // with it, otherwise a list starting with linedef 0 would lose it
+        let leading_zero = offsets
+            .iter()
+            .all(|&offset| matches!(read_u16(offset), Ok(0)));
+
+        let mut blocks = Vec::with_capacity(count);
+        for mut offset in offsets {
+            if leading_zero {
+                offset += 2;
+            }
+
+            let mut linedefs = Vec::new();
+            loop {
+                let linedef = read_u16(offset)?;
+                if linedef == 0xffff {
+                    break;
+                }
+
+                linedefs.push(linedef as usize);
+                offset += 2;
+            }
+
+            blocks.push(linedefs);
+        }
+
+        Ok(BlockMap {
+            origin_x,
+            origin_y,
+            columns,
+            rows,
+            blocks,
+        })
+    }
+
+    /// Reads the REJECT lump of the map, returns None if the lump is missing
+    /// or doesn't match the number of sectors
+    pub fn load_reject(&self, wad: &Wad) -> Option<Reject> {
+        if self.format == MapFormat::Udmf {
+            return None;
+        }
+
+        let map_index = wad.find_dir(&self.name).ok()?;
+        let index = Self::find_map_lump(wad, map_index, "REJECT").ok()?;
+        let data = wad.read_dir(index).ok()?;
+
+        let num_sectors = self.sectors.len();
+        if data.len() != (num_sectors * num_sectors).div_ceil(8) {
+            return None;
+        }
+
+        Some(Reject {
+            num_sectors,
+            bits: data.to_vec(),
+        })
+    }
+
+    /// Reads the GL_PVS lump of the map, returns None if the lump is
+    /// missing or doesn't match the number of sub sectors
+    pub fn load_pvs(&self, wad: &Wad) -> Option<Pvs> {
+        self.gl_node_version?;
+
+        let map_index = wad.find_dir(&self.name).ok()?;
+        let index = Self::find_map_lump(wad, map_index, "GL_PVS").ok()?;
+        let data = wad.read_dir(index).ok()?;
+
+        // NOTE: glVIS writes the rows uncompressed, each row is padded to a
+        // whole byte
+        let num_sub_sectors = self.sub_sectors.len();
+        if data.len() != num_sub_sectors * num_sub_sectors.div_ceil(8) {
+            return None;
+        }
+
+        Some(Pvs {
+            num_sub_sectors,
+            bits: data.to_vec(),
+        })
+    }
+
+    /// Returns the min and max corner of the box around all the vertices
+    pub fn bounding_box(&self) -> (Vertex, Vertex) {
+        if self.vertices.is_empty() {
+            return (Vertex::new(0.0, 0.0), Vertex::new(0.0, 0.0));
+        }
+
+        let mut min = Vertex::new(f32::MAX, f32::MAX);
+        let mut max = Vertex::new(f32::MIN, f32::MIN);
+
+        for vertex in &self.vertices {
+            min.x = min.x.min(vertex.x);
+            min.y = min.y.min(vertex.y);
+            max.x = max.x.max(vertex.x);
+            max.y = max.y.max(vertex.y);
+        }
+
+        (min, max)
+    }
+
+    /// Counts the parts of the map, the heights are 0 if the map has no
+    /// sectors
+    pub fn stats(&self) -> MapStats {
+        let two_sided_linedefs = self
+            .linedefs
+            .iter()
+            .filter(|l| l.back_sidedef.is_some())
+            .count();
+
+        let mut stats = MapStats {
+            vertices: self.vertices.len(),
+            linedefs: self.linedefs.len(),
+            one_sided_linedefs: self.linedefs.len() - two_sided_linedefs,
+            two_sided_linedefs,
+            sidedefs: self.sidedefs.len(),
+            sectors: self.sectors.len(),
+            segments: self.segments.len(),
+            sub_sectors: self.sub_sectors.len(),
+
+            ..Default::default()
+        };
+
+        if let Some(first) = self.sectors.first() {
+            stats.min_floor_height = first.floor_height;
+            stats.max_floor_height = first.floor_height;
+            stats.min_ceiling_height = first.ceiling_height;
+            stats.max_ceiling_height = first.ceiling_height;
+        }
+
+        for sector in &self.sectors {
+            stats.min_floor_height =
+                stats.min_floor_height.min(sector.floor_height);
+            stats.max_floor_height =
+                stats.max_floor_height.max(sector.floor_height);
+            stats.min_ceiling_height =
+                stats.min_ceiling_height.min(sector.ceiling_height);
+            stats.max_ceiling_height =
+                stats.max_ceiling_height.max(sector.ceiling_height);
+        }
+
+        stats
+    }
+
+    /// The version of the GL nodes, None for UDMF maps that has the nodes
+    /// inside the ZNODES lump instead
+    pub fn gl_node_version(&self) -> Option<GlNodeVersion> {
+        self.gl_node_version
+    }
+
     pub fn vertex(&self, index: usize) -> Vertex {
-        return if index & VERT_IS_GL == VERT_IS_GL {
+        if index & VERT_IS_GL == VERT_IS_GL {
             self.gl_vertices[index & !VERT_IS_GL]
         } else {
             self.vertices[index]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MapBuilder, WadBuilder};
+
+    fn udmf_wad(namespace: &str, magic: &[u8; 4]) -> Vec<u8> {
+        let map = MapBuilder::rooms("MAP01", &[(0, 128), (16, 112)]);
+        WadBuilder::new()
+            .lumps(map.udmf_lumps(namespace, magic))
+            .build(b"PWAD")
+    }
+
+    fn blockmap_wad(blockmap: Vec<u8>) -> Vec<u8> {
+        let mut map = MapBuilder::rooms("E1M1", &[(0, 128)]);
+        map.blockmap = blockmap;
+        WadBuilder::new().lumps(map.lumps()).build(b"PWAD")
+    }
+
+    #[test]
+    fn blockmap_lists_are_read() {
+        let lists = [vec![0, 1], vec![], vec![2, 0], vec![3]];
+
+        for leading_zero in [true, false] {
+            let data =
+                blockmap_wad(testing::blockmap(2, 2, &lists, leading_zero));
+            let wad = Wad::parse(&data).unwrap();
+            let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+            let blockmap = map.load_blockmap(&wad).unwrap();
+
+            assert_eq!((blockmap.columns, blockmap.rows), (2, 2));
+            assert_eq!(blockmap.block(0, 0), Some(&[0, 1][..]));
+            assert_eq!(blockmap.block(1, 0), Some(&[][..]));
+            assert_eq!(blockmap.block(0, 1), Some(&[2, 0][..]));
+            assert_eq!(blockmap.block(1, 1), Some(&[3][..]));
+            assert_eq!(blockmap.block(2, 0), None);
+        }
+    }
+
+    #[test]
+    fn blockmap_without_leading_zero_keeps_linedef_0() {
+        let lists = [vec![0, 2], vec![1]];
+        let data = blockmap_wad(testing::blockmap(2, 1, &lists, false));
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let blockmap = map.load_blockmap(&wad).unwrap();
+
+        assert_eq!(blockmap.block(0, 0), Some(&[0, 2][..]));
+        assert_eq!(blockmap.block(1, 0), Some(&[1][..]));
+    }
+
+    #[test]
+    fn udmf_map_reads_the_znodes() {
+        let doom_wad = testing::two_rooms_wad();
+        let doom_wad = Wad::parse(&doom_wad).unwrap();
+        let doom_map = Map::parse_from_wad(&doom_wad, "E1M1").unwrap();
+
+        for magic in [b"XGLN", b"XGL2", b"ZGLN", b"ZGL3"] {
+            let data = udmf_wad("zdoom", magic);
+            let wad = Wad::parse(&data).unwrap();
+            let map = Map::parse_from_wad(&wad, "MAP01").unwrap();
+
+            assert_eq!(map.format, MapFormat::Udmf);
+            assert_eq!(map.segments.len(), doom_map.segments.len());
+            for (segment, expected) in
+                map.segments.iter().zip(&doom_map.segments)
+            {
+                assert_eq!(segment.start_vertex, expected.start_vertex);
+                assert_eq!(segment.end_vertex, expected.end_vertex);
+                assert_eq!(segment.linedef, expected.linedef);
+                assert_eq!(segment.side, expected.side);
+            }
+
+            let sub_sectors = map
+                .sub_sectors
+                .iter()
+                .map(|s| (s.start, s.count))
+                .collect::<Vec<_>>();
+            assert_eq!(sub_sectors, [(0, 4), (4, 4)]);
+            assert_eq!(map.sectors[1].lines.len(), 4);
+        }
+    }
+
+    #[test]
+    fn udmf_map_with_unknown_namespace_is_rejected() {
+        let data = udmf_wad("mygame", b"XGLN");
+        let wad = Wad::parse(&data).unwrap();
+
+        match Map::parse_from_wad(&wad, "MAP01") {
+            Err(Error::UnsupportedNamespace(namespace)) => {
+                assert_eq!(namespace, "mygame")
+            }
+            result => {
+                panic!("expected UnsupportedNamespace: {:?}", result.err())
+            }
+        }
+    }
+
+    #[test]
+    fn udmf_map_without_znodes_is_an_error() {
+        let map = MapBuilder::rooms("MAP01", &[(0, 128)]);
+        let lumps = map
+            .udmf_lumps("doom", b"XGLN")
+            .into_iter()
+            .filter(|(name, _)| name != "ZNODES")
+            .collect();
+        let data = WadBuilder::new().lumps(lumps).build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        assert!(matches!(
+            Map::parse_from_wad(&wad, "MAP01"),
+            Err(Error::MissingGlNodes)
+        ));
+    }
+
+    #[test]
+    fn linedef_flags_and_texture_names() {
+        let flags = LinedefFlags::IMPASSABLE
+            | LinedefFlags::UPPER_TEXTURE_UNPEGGED
+            | LinedefFlags::SECRET
+            | LinedefFlags::ALWAYS_SHOWS_ON_AUTOMAP;
+
+        let mut map = MapBuilder::rooms("E1M1", &[(0, 128)]);
+        map.linedefs[0].flags = flags.bits();
+        map.sidedefs[0].middle = "WALL\0XYZ".to_string();
+        let data = WadBuilder::new().lumps(map.lumps()).build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+
+        assert_eq!(map.linedefs[0].flags, flags);
+        assert_eq!(map.sidedefs[0].middle_texture, "WALL");
+    }
+
+    #[test]
+    fn linedef_flag_values() {
+        assert_eq!(LinedefFlags::IMPASSABLE.bits(), 0x0001);
+        assert_eq!(LinedefFlags::BLOCKS_MONSTERS.bits(), 0x0002);
+        assert_eq!(LinedefFlags::TWO_SIDED.bits(), 0x0004);
+        assert_eq!(LinedefFlags::UPPER_TEXTURE_UNPEGGED.bits(), 0x0008);
+        assert_eq!(LinedefFlags::LOWER_TEXTURE_UNPEGGED.bits(), 0x0010);
+        assert_eq!(LinedefFlags::SECRET.bits(), 0x0020);
+        assert_eq!(LinedefFlags::BLOCKS_SOUND.bits(), 0x0040);
+        assert_eq!(LinedefFlags::NEVER_SHOW_ON_AUTOMAP.bits(), 0x0080);
+        assert_eq!(LinedefFlags::ALWAYS_SHOWS_ON_AUTOMAP.bits(), 0x0100);
+    }
+
+    #[test]
+    fn lump_outside_of_the_file_is_an_error() {
+        let mut data = WadBuilder::new()
+            .lumps(vec![("DATA".to_string(), vec![1, 2, 3, 4])])
+            .build(b"PWAD");
+
+        // NOTE: Claim that the lump is 1000 bytes and that there is a
+        // second directory entry after the end of the file
+        let dir_start = u32::from_le_bytes(data[8..12].try_into().unwrap());
+        let dir_start = dir_start as usize;
+        data[dir_start + 4..dir_start + 8]
+            .copy_from_slice(&1000i32.to_le_bytes());
+        data[4..8].copy_from_slice(&2i32.to_le_bytes());
+
+        let wad = Wad::parse(&data).unwrap();
+        assert!(matches!(
+            wad.read_dir(0),
+            Err(Error::LumpOutOfBounds { index: 0 })
+        ));
+        assert!(matches!(wad.read_dir_entry(1), Err(Error::IndexOutOfRange)));
+    }
+
+    #[test]
+    fn header_shorter_than_12_bytes_is_too_small() {
+        assert!(matches!(Wad::parse(b"IWA"), Err(Error::TooSmall)));
+    }
+
+    #[test]
+    fn hexen_and_doom_linedefs() {
+        for (format, map_format, record_size) in [
+            (testing::Format::Doom, MapFormat::Doom, 14),
+            (testing::Format::Hexen, MapFormat::Hexen, 16),
+        ] {
+            let mut map = MapBuilder::rooms("MAP01", &[(0, 128), (16, 112)]);
+            map.format = format;
+            map.linedefs[0].special = 80;
+            map.linedefs[0].tag = 3;
+            let count = map.linedefs.len();
+            let data = WadBuilder::new().lumps(map.lumps()).build(b"PWAD");
+            let wad = Wad::parse(&data).unwrap();
+
+            let map_index = wad.find_dir("MAP01").unwrap();
+            let linedefs = wad.read_dir(map_index + 2).unwrap();
+            assert_eq!(linedefs.len(), count * record_size);
+
+            let map = Map::parse_from_wad(&wad, "MAP01").unwrap();
+            assert_eq!(map.format, map_format);
+            assert_eq!(map.linedefs.len(), count);
+            assert_eq!(map.linedefs[0].special, 80);
+            assert_eq!(map.linedefs[0].tag, 3);
+            assert_eq!(map.sidedefs.len(), 8);
+        }
+    }
+
+    #[test]
+    fn gl_vertices_of_each_version() {
+        for (gl_version, version, expected) in [
+            (1, GlNodeVersion::V1, Vertex::new(-32.0, 64.0)),
+            (2, GlNodeVersion::V2, Vertex::new(-32.5, 64.25)),
+            (3, GlNodeVersion::V3, Vertex::new(-32.5, 64.25)),
+            (5, GlNodeVersion::V5, Vertex::new(-32.5, 64.25)),
+        ] {
+            let mut map = MapBuilder::rooms("E1M1", &[(0, 128)]);
+            map.gl_version = gl_version;
+            map.gl_vertices = vec![(0.0, 0.0), (-32.5, 64.25)];
+            map.segs[0].v2 = testing::GL_VERTEX | 1;
+            let data = WadBuilder::new().lumps(map.lumps()).build(b"PWAD");
+            let wad = Wad::parse(&data).unwrap();
+            let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+
+            assert_eq!(map.gl_node_version(), Some(version));
+            assert_eq!(map.gl_vertices.len(), 2);
+            assert_eq!(map.vertex(map.segments[0].end_vertex), expected);
+            assert_eq!(map.vertex(map.segments[0].start_vertex).x, 0.0);
+        }
+    }
+
+    #[test]
+    fn reject_of_two_sectors() {
+        let reject_wad = |reject: Vec<u8>| {
+            let map = MapBuilder::rooms("E1M1", &[(0, 128), (16, 112)]);
+            let mut lumps = map.lumps();
+            lumps
+                .iter_mut()
+                .find(|(name, _)| name == "REJECT")
+                .unwrap()
+                .1 = reject;
+            WadBuilder::new().lumps(lumps).build(b"PWAD")
+        };
+
+        // NOTE: 2 * 2 bits, bit 1 is sector 0 looking at sector 1
+        let data = reject_wad(vec![0b0010]);
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let reject = map.load_reject(&wad).unwrap();
+        assert!(reject.can_see(0, 0));
+        assert!(!reject.can_see(0, 1));
+        assert!(reject.can_see(1, 0));
+        assert!(reject.can_see(1, 1));
+        assert!(!reject.can_see(2, 0));
+
+        let data = reject_wad(vec![0, 0]);
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+        assert!(map.load_reject(&wad).is_none());
+    }
+
+    #[test]
+    fn sector_record_fields() {
+        let mut map = MapBuilder::rooms("E1M1", &[(-24, 200)]);
+        map.sectors[0].floor_texture = "NUKAGE1".to_string();
+        map.sectors[0].ceiling_texture = "F_SKY1".to_string();
+        map.sectors[0].light = 144;
+        map.sectors[0].special = 9;
+        map.sectors[0].tag = 666;
+        let data = WadBuilder::new().lumps(map.lumps()).build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        let map_index = wad.find_dir("E1M1").unwrap();
+        assert_eq!(wad.read_dir(map_index + 8).unwrap().len(), 26);
+
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let sector = &map.sectors[0];
+        assert_eq!(sector.floor_height, -24.0);
+        assert_eq!(sector.ceiling_height, 200.0);
+        assert_eq!(sector.floor_texture, "NUKAGE1");
+        assert_eq!(sector.ceiling_texture, "F_SKY1");
+        assert_eq!(sector.light_level, 144);
+        assert_eq!(sector.special, 9);
+        assert_eq!(sector.tag, 666);
+    }
+
+    #[test]
+    fn linedef_special_and_tag() {
+        let mut map = MapBuilder::rooms("E1M1", &[(0, 128), (16, 112)]);
+        let portal = map.linedefs.iter().position(|l| l.back.is_some());
+        let portal = portal.unwrap();
+        map.linedefs[portal].special = 1;
+        map.linedefs[portal].tag = 0x0203;
+        let data = WadBuilder::new().lumps(map.lumps()).build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+
+        let linedef = &map.linedefs[portal];
+        assert_eq!((linedef.special, linedef.tag), (1, 0x0203));
+        assert!(linedef.back_sidedef.is_some());
+        for (index, linedef) in map.linedefs.iter().enumerate() {
+            if index != portal {
+                assert_eq!((linedef.special, linedef.tag), (0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn error_display_text() {
+        let cases = [
+            (
+                Error::ArrayConvertionFailed,
+                "failed to convert bytes to an array",
+            ),
+            (
+                Error::ConvertToUsizeFailed,
+                "failed to convert a value to usize",
+            ),
+            (
+                Error::ConvertToF32Failed,
+                "failed to convert a value to f32",
+            ),
+            (
+                Error::BytesToStrFailed,
+                "failed to convert bytes to a string",
+            ),
+            (Error::TooSmall, "the file is too small to be a WAD"),
+            (
+                Error::UnknownMagic(*b"ZWAD"),
+                "unknown WAD magic 'ZWAD' (expected IWAD or PWAD)",
+            ),
+            (Error::NoDirFound, "the lump doesn't exist"),
+            (
+                Error::MissingGlNodes,
+                "the map has no GL nodes (build them with glBSP or ZDBSP)",
+            ),
+            (Error::IndexOutOfRange, "index is out of range"),
+            (
+                Error::LumpOutOfBounds { index: 7 },
+                "lump #7 is outside of the file",
+            ),
+            (
+                Error::FrontSideMismatch { side: 0 },
+                "a segment is on side 0 (front) of a linedef without a \
+                 front sidedef",
+            ),
+            (
+                Error::BackSideMismatch { side: 1 },
+                "a segment is on side 1 (back) of a linedef without a back \
+                 sidedef",
+            ),
+            (
+                Error::UnknownSide { side: 5 },
+                "a segment has the unknown side 5",
+            ),
+            (Error::InvalidBlockMap, "the BLOCKMAP is invalid"),
+            (
+                Error::InvalidLumpSize {
+                    lump: "GL_SEGS",
+                    size: 15,
+                    record_size: 10,
+                },
+                "the GL_SEGS lump is 15 bytes which isn't a multiple of the \
+                 10 byte records",
+            ),
+            (
+                Error::TextMapParseFailed(udmf::Error::UnexpectedEnd),
+                "failed to parse the TEXTMAP: unexpected end of the text",
+            ),
+            (
+                Error::TextMapMissingField {
+                    kind: "vertex",
+                    field: "x",
+                },
+                "a vertex in the TEXTMAP is missing the 'x' field",
+            ),
+            (
+                Error::UnsupportedNamespace("mygame".to_string()),
+                "the TEXTMAP namespace 'mygame' isn't supported",
+            ),
+            (Error::InvalidZNodes, "the ZNODES lump is invalid"),
+        ];
+
+        for (error, text) in cases {
+            assert_eq!(error.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn maps_with_the_same_lump_names() {
+        let map01 = MapBuilder::rooms("MAP01", &[(0, 128)]);
+        let mut map02 = MapBuilder::rooms("MAP02", &[(0, 128), (16, 112)]);
+        map02.format = testing::Format::Hexen;
+        let data = WadBuilder::new()
+            .lumps(map01.lumps())
+            .lumps(map02.lumps())
+            .build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        let map01_index = wad.find_dir("MAP01").unwrap();
+        let map02_index = wad.find_dir("MAP02").unwrap();
+        let sidedefs = Map::find_map_lump(&wad, map01_index, "SIDEDEFS");
+        assert_eq!(sidedefs.unwrap(), map01_index + 3);
+        let sidedefs = Map::find_map_lump(&wad, map02_index, "SIDEDEFS");
+        assert_eq!(sidedefs.unwrap(), map02_index + 3);
+
+        // NOTE: Only the second map has a BEHAVIOR lump
+        assert!(matches!(
+            Map::find_map_lump(&wad, map01_index, "BEHAVIOR"),
+            Err(Error::NoDirFound)
+        ));
+
+        let map = Map::parse_from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(map.format, MapFormat::Doom);
+        assert_eq!(map.sectors.len(), 1);
+
+        let map = Map::parse_from_wad(&wad, "MAP02").unwrap();
+        assert_eq!(map.format, MapFormat::Hexen);
+        assert_eq!(map.sectors.len(), 2);
+    }
+
+    #[test]
+    fn dehacked_text_lump() {
+        let mut text = b"Patch File for DeHackEd v3.0\n".to_vec();
+        // NOTE: 0xe9 is é in Latin-1 and not valid UTF-8
+        text.extend_from_slice(&[b'#', b' ', 0xe9, b'\n']);
+        let data = WadBuilder::new()
+            .lumps(vec![("DEHACKED".to_string(), text)])
+            .build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        assert_eq!(
+            wad.read_text_lump("DEHACKED").unwrap(),
+            "Patch File for DeHackEd v3.0\n# \u{fffd}\n"
+        );
+        assert!(matches!(
+            wad.read_text_lump("MISSING"),
+            Err(Error::NoDirFound)
+        ));
+    }
+
+    #[test]
+    fn stats_of_three_rooms() {
+        let map = MapBuilder::rooms("E1M1", &[(0, 128), (16, 112), (-8, 96)]);
+        let data = WadBuilder::new().lumps(map.lumps()).build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+
+        let stats = map.stats();
+        assert_eq!(stats.vertices, 8);
+        assert_eq!(stats.linedefs, 10);
+        assert_eq!(stats.one_sided_linedefs, 8);
+        assert_eq!(stats.two_sided_linedefs, 2);
+        assert_eq!(stats.sidedefs, 12);
+        assert_eq!(stats.sectors, 3);
+        assert_eq!(stats.segments, 12);
+        assert_eq!(stats.sub_sectors, 3);
+        assert_eq!(stats.min_floor_height, -8.0);
+        assert_eq!(stats.max_floor_height, 16.0);
+        assert_eq!(stats.min_ceiling_height, 96.0);
+        assert_eq!(stats.max_ceiling_height, 128.0);
+    }
+
+    #[test]
+    fn iter_maps_of_mixed_formats() {
+        let e1m1 = MapBuilder::rooms("E1M1", &[(0, 128)]);
+        let mut map01 = MapBuilder::rooms("MAP01", &[(0, 128)]);
+        map01.format = testing::Format::Hexen;
+        map01.gl_version = 5;
+        let map02 = MapBuilder::rooms("MAP02", &[(0, 128)]);
+        let data = WadBuilder::new()
+            .lumps(testing::game_lumps())
+            .lumps(e1m1.lumps())
+            .lumps(map01.lumps())
+            .lumps(map02.udmf_lumps("zdoom", b"XGLN"))
+            .build(b"PWAD");
+        let wad = Wad::parse(&data).unwrap();
+
+        let maps = wad.iter_maps().unwrap();
+        let maps = maps
+            .iter()
+            .map(|m| (m.name.as_str(), m.format, m.gl_node_version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            maps,
+            [
+                ("E1M1", MapFormat::Doom, Some(GlNodeVersion::V2)),
+                ("MAP01", MapFormat::Hexen, Some(GlNodeVersion::V5)),
+                ("MAP02", MapFormat::Udmf, None),
+            ]
+        );
+
+        for entry in wad.iter_maps().unwrap() {
+            assert_eq!(wad.find_dir(&entry.name).unwrap(), entry.index);
+        }
+    }
+
+    #[test]
+    fn gl_pvs_of_three_rooms() {
+        let pvs_wad = |pvs: Option<Vec<u8>>| {
+            let map =
+                MapBuilder::rooms("E1M1", &[(0, 128), (16, 112), (0, 128)]);
+            let mut lumps = map.lumps();
+            if let Some(pvs) = pvs {
+                lumps.push(("GL_PVS".to_string(), pvs));
+            }
+            WadBuilder::new().lumps(lumps).build(b"PWAD")
         };
+
+        // NOTE: One byte for each of the 3 sub sectors, the rooms at the
+        // ends can't see each other
+        let data = pvs_wad(Some(vec![0b011, 0b111, 0b110]));
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+        let pvs = map.load_pvs(&wad).unwrap();
+        let visible =
+            |from: usize| pvs.visible_subsectors(from).collect::<Vec<_>>();
+        assert_eq!(visible(0), vec![0, 1]);
+        assert_eq!(visible(1), vec![0, 1, 2]);
+        assert_eq!(visible(2), vec![1, 2]);
+        assert!(visible(3).is_empty());
+        assert!(!pvs.can_see(0, 2));
+
+        let data = pvs_wad(Some(vec![0b011, 0b111]));
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+        assert!(map.load_pvs(&wad).is_none());
+
+        let data = pvs_wad(None);
+        let wad = Wad::parse(&data).unwrap();
+        let map = Map::parse_from_wad(&wad, "E1M1").unwrap();
+        assert!(map.load_pvs(&wad).is_none());
+    }
+
+    #[test]
+    fn checksum_of_equal_and_modified_bytes() {
+        let data = testing::two_rooms_wad();
+        let copy = data.clone();
+        let mut modified = data.clone();
+        // NOTE: The first byte of the first lump (PLAYPAL)
+        modified[12] ^= 0xff;
+
+        let checksum = Wad::parse(&data).unwrap().checksum();
+        assert_eq!(Wad::parse(&copy).unwrap().checksum(), checksum);
+        assert_ne!(Wad::parse(&modified).unwrap().checksum(), checksum);
+    }
+
+    #[test]
+    fn miniseg_sentinels_of_each_node_format() {
+        let mut map = MapBuilder::rooms("MAP01", &[(0, 128)]);
+        map.gl_version = 2;
+        assert_eq!(map.segs[0].linedef, Some(0));
+        map.segs[1].linedef = None;
+
+        // NOTE: GL nodes and XGLN uses 0xffff, XGL2 uses 0xffffffff
+        for lumps in [
+            map.lumps(),
+            map.udmf_lumps("doom", b"XGLN"),
+            map.udmf_lumps("doom", b"XGL2"),
+        ] {
+            let data = WadBuilder::new().lumps(lumps).build(b"PWAD");
+            let wad = Wad::parse(&data).unwrap();
+            let map = Map::parse_from_wad(&wad, "MAP01").unwrap();
+
+            assert_eq!(map.segments[0].linedef, 0);
+            assert_eq!(map.segments[1].linedef, MINISEG);
+            assert!(map.segments[2..].iter().all(|s| s.linedef != MINISEG));
+        }
+    }
+
+    #[test]
+    fn gl_record_sizes_of_each_version() {
+        for (gl_version, version, segment_size, sub_sector_size) in [
+            (1, GlNodeVersion::V1, 10, 4),
+            (2, GlNodeVersion::V2, 10, 4),
+            (3, GlNodeVersion::V3, 16, 8),
+            (5, GlNodeVersion::V5, 16, 8),
+        ] {
+            assert_eq!(version.segment_size(), segment_size);
+            assert_eq!(version.sub_sector_size(), sub_sector_size);
+
+            let mut map = MapBuilder::rooms("E1M1", &[(0, 128), (16, 112)]);
+            map.gl_version = gl_version;
+            let lumps = map.lumps();
+            let lump = |name: &str| {
+                let (_, data) = lumps.iter().find(|(n, _)| n == name).unwrap();
+                data.clone()
+            };
+
+            // NOTE: Version 3 starts the lumps with the gNd3 magic
+            let magic = if gl_version == 3 { 4 } else { 0 };
+            assert_eq!(lump("GL_SEGS").len(), magic + 8 * segment_size);
+            assert_eq!(lump("GL_SSECT").len(), magic + 2 * sub_sector_size);
+
+            let data = WadBuilder::new().lumps(lumps.clone()).build(b"PWAD");
+            let wad = Wad::parse(&data).unwrap();
+            let parsed = Map::parse_from_wad(&wad, "E1M1").unwrap();
+            assert_eq!(parsed.gl_node_version(), Some(version));
+            assert_eq!(parsed.segments.len(), 8);
+            assert_eq!(parsed.sub_sectors.len(), 2);
+
+            // NOTE: A lump cut short isn't a whole number of records
+            for (name, record_size) in
+                [("GL_SEGS", segment_size), ("GL_SSECT", sub_sector_size)]
+            {
+                let mut lumps = lumps.clone();
+                let (_, data) =
+                    lumps.iter_mut().find(|(n, _)| n == name).unwrap();
+                data.truncate(data.len() - 3);
+
+                let data = WadBuilder::new().lumps(lumps).build(b"PWAD");
+                let wad = Wad::parse(&data).unwrap();
+                match Map::parse_from_wad(&wad, "E1M1") {
+                    Err(Error::InvalidLumpSize {
+                        lump,
+                        record_size: size,
+                        ..
+                    }) => {
+                        assert_eq!((lump, size), (name, record_size))
+                    }
+                    result => {
+                        panic!("expected InvalidLumpSize: {:?}", result.err())
+                    }
+                }
+            }
+        }
     }
 }
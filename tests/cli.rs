@@ -0,0 +1,206 @@
+//! Tests of the wad_reader binary
+
+use std::process::{Command, Output};
+
+use wad_reader::wad::Wad;
+
+#[path = "../src/testing.rs"]
+#[allow(dead_code)]
+mod testing;
+
+fn wad_reader(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wad_reader"))
+        .args(args)
+        .output()
+        .expect("Failed to run wad_reader")
+}
+
+#[test]
+fn extract_lump() {
+    let data = std::fs::read(testing::TWO_ROOMS_WAD).unwrap();
+    let wad = Wad::parse(&data).unwrap();
+    let colormap = wad.read_dir_by_name("COLORMAP").unwrap();
+
+    let output = wad_reader(&[testing::TWO_ROOMS_WAD, "extract", "COLORMAP"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, colormap);
+
+    let file = testing::temp_dir("extract").join("COLORMAP.lmp");
+    let output = wad_reader(&[
+        testing::TWO_ROOMS_WAD,
+        "extract",
+        "COLORMAP",
+        "--file",
+        file.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&file).unwrap(), colormap);
+
+    let output = wad_reader(&[testing::TWO_ROOMS_WAD, "extract", "MISSING"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("The WAD has no lump named 'MISSING'"));
+}
+
+#[test]
+fn missing_playpal_is_an_error() {
+    let map = testing::MapBuilder::rooms("E1M1", &[(0, 128)]);
+    let data = testing::WadBuilder::new().lumps(map.lumps()).build(b"PWAD");
+    let dir = testing::temp_dir("missing_playpal");
+    let wad_file = dir.join("map.wad");
+    std::fs::write(&wad_file, data).unwrap();
+
+    let output = wad_reader(&[
+        wad_file.to_str().unwrap(),
+        "-o",
+        dir.join("out").to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No PLAYPAL found"), "{}", stderr);
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn map_number_selects_the_map() {
+    let e1m1 = testing::MapBuilder::rooms("E1M1", &[(0, 128)]);
+    let e1m2 = testing::MapBuilder::rooms("E1M2", &[(0, 128), (16, 112)]);
+    let data = testing::WadBuilder::new()
+        .lumps(testing::game_lumps())
+        .lumps(e1m1.lumps())
+        .lumps(e1m2.lumps())
+        .build(b"IWAD");
+    let dir = testing::temp_dir("map_number");
+    let wad_file = dir.join("maps.wad");
+    std::fs::write(&wad_file, data).unwrap();
+
+    let out = dir.join("out");
+    let output = wad_reader(&[
+        wad_file.to_str().unwrap(),
+        "--map",
+        "2",
+        "-o",
+        out.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Converting 'E1M2'"), "{}", stdout);
+    assert!(out.join("E1M2.glb").exists());
+    assert!(!out.join("E1M1.glb").exists());
+}
+
+#[test]
+fn check_writes_no_files() {
+    let dir = testing::temp_dir("check");
+    for format in ["gltf", "mime", "obj"] {
+        let output = wad_reader(&[
+            testing::TWO_ROOMS_WAD,
+            "--check",
+            "--format",
+            format,
+            "-m",
+            "E1M1",
+            "-o",
+            dir.to_str().unwrap(),
+        ]);
+        assert!(output.status.success(), "{}", format);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("Checking 'E1M1'"), "{}", stdout);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    let output = wad_reader(&[
+        testing::TWO_ROOMS_WAD,
+        "--check",
+        "-m",
+        "E9M9",
+        "-o",
+        dir.to_str().unwrap(),
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn check_fails_on_warnings() {
+    let mut map = testing::MapBuilder::rooms("E1M1", &[(0, 128)]);
+    map.sidedefs[0].middle = "NOSUCH".to_string();
+    let data = testing::WadBuilder::new()
+        .lumps(testing::game_lumps())
+        .lumps(map.lumps())
+        .build(b"IWAD");
+    let dir = testing::temp_dir("check_warnings");
+    let wad_file = dir.join("map.wad");
+    std::fs::write(&wad_file, data).unwrap();
+
+    for format in ["gltf", "mime", "obj"] {
+        let output = wad_reader(&[
+            wad_file.to_str().unwrap(),
+            "--check",
+            "--format",
+            format,
+            "-o",
+            dir.join("out").to_str().unwrap(),
+        ]);
+        assert_eq!(output.status.code(), Some(1), "{}", format);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Missing texture 'NOSUCH'"), "{}", stderr);
+        assert!(!dir.join("out").exists());
+    }
+}
+
+#[test]
+fn strict_prints_each_warning_once() {
+    let mut map = testing::MapBuilder::rooms("E1M1", &[(0, 128)]);
+    map.sidedefs[0].middle = "NOSUCH".to_string();
+    map.sidedefs[1].middle = "NOSUCH".to_string();
+    let data = testing::WadBuilder::new()
+        .lumps(testing::game_lumps())
+        .lumps(map.lumps())
+        .build(b"IWAD");
+    let dir = testing::temp_dir("strict_warnings");
+    let wad_file = dir.join("map.wad");
+    std::fs::write(&wad_file, data).unwrap();
+
+    let output = wad_reader(&[
+        wad_file.to_str().unwrap(),
+        "--strict",
+        "-o",
+        dir.join("out").to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("Missing texture 'NOSUCH'").count(), 1);
+    assert!(stderr.contains("Found 1 problem(s)"), "{}", stderr);
+}
+
+#[test]
+fn only_sector_of_a_map_without_sectors() {
+    let mut map = testing::MapBuilder::rooms("E1M1", &[(0, 128)]);
+    map.vertices.clear();
+    map.gl_vertices.clear();
+    map.linedefs.clear();
+    map.sidedefs.clear();
+    map.sectors.clear();
+    map.segs.clear();
+    map.sub_sectors.clear();
+    let data = testing::WadBuilder::new()
+        .lumps(testing::game_lumps())
+        .lumps(map.lumps())
+        .build(b"IWAD");
+    let dir = testing::temp_dir("only_sector_empty");
+    let wad_file = dir.join("map.wad");
+    std::fs::write(&wad_file, data).unwrap();
+
+    let output = wad_reader(&[
+        wad_file.to_str().unwrap(),
+        "--only-sector",
+        "0",
+        "-o",
+        dir.join("out").to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the map has no sectors"), "{}", stderr);
+}